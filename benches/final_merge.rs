@@ -0,0 +1,85 @@
+//! Final merge of sorted chunks: the default single heap merge vs `--partitioned`.
+//!
+//! The partitioned merge splits the inputs into `MERGE_PARTITIONS` key ranges
+//! (default: the rayon thread count) and merges them in parallel into partition files.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use split_merge_hub_demo::parallel_merge::{
+    merge_k_files_mtlog_with, parallel_merge_chunks_with, MTLogSortColumn, MTLogSortType, RunOptions,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CHUNKS: usize = 4;
+const MTLOG_RECORDS: usize = 2_500;
+const CSV_ROWS: usize = 100_000;
+
+/// Sorted MT log chunks of 4310-byte records with interleaved task numbers.
+fn mtlog_chunks(dir: &Path) -> Vec<PathBuf> {
+    (0..CHUNKS)
+        .map(|c| {
+            let path = dir.join(format!("chunk_{}.mtlog", c));
+            let mut content = String::new();
+            for r in 0..MTLOG_RECORDS {
+                let mut line = format!("{:014}{:07}", 0, r * CHUNKS + c);
+                line.extend(std::iter::repeat_n('X', 4310 - line.len()));
+                content.push_str(&line);
+                content.push('\n');
+            }
+            fs::write(&path, content).unwrap();
+            path
+        })
+        .collect()
+}
+
+/// Sorted CSV chunks with interleaved ids.
+fn csv_chunks(dir: &Path) -> Vec<PathBuf> {
+    (0..CHUNKS)
+        .map(|c| {
+            let path = dir.join(format!("chunk_{}.csv", c));
+            let mut content = String::from("id,name\n");
+            for r in 0..CSV_ROWS {
+                let id = r * CHUNKS + c;
+                content.push_str(&format!("{},name{}\n", id, id));
+            }
+            fs::write(&path, content).unwrap();
+            path
+        })
+        .collect()
+}
+
+fn bench_final_merge(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let partitioned = {
+        let mut options = RunOptions::default();
+        options.output.partitioned = true;
+        options
+    };
+    let runs = [("heap", RunOptions::default()), ("partitioned", partitioned)];
+
+    let chunks = mtlog_chunks(dir.path());
+    let sort_columns = [MTLogSortColumn { index: 2, col_type: MTLogSortType::Num }];
+    let output = dir.path().join("merged.mtlog");
+    let mut group = c.benchmark_group("mtlog_final_merge");
+    group.sample_size(10);
+    for (name, options) in &runs {
+        group.bench_function(*name, |b| {
+            b.iter(|| merge_k_files_mtlog_with(&chunks, &output, &sort_columns, options).unwrap())
+        });
+    }
+    group.finish();
+
+    let chunks = csv_chunks(dir.path());
+    let output = dir.path().join("merged.csv");
+    let mut group = c.benchmark_group("csv_final_merge");
+    group.sample_size(10);
+    for (name, options) in &runs {
+        group.bench_function(*name, |b| {
+            b.iter(|| parallel_merge_chunks_with(chunks.clone(), &output, &["id"], CHUNKS, options).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_final_merge);
+criterion_main!(benches);
@@ -0,0 +1,113 @@
+//! MT log sort: per-comparison field parsing vs precomputed binary keys.
+//!
+//! Records mimic `generate_mt_log_large_files`: 4310 bytes with a random
+//! date, time and task number at the front.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use rand::Rng;
+use split_merge_hub_demo::parallel_merge::{mtlog_sort_key, MTLogSortColumn, MTLogSortType};
+use std::cmp::Ordering;
+
+const RECORD_LEN: usize = 4310;
+const RECORDS: usize = 20_000;
+
+// (start, len) of the leading MT log fields, as the replaced comparator had them
+const OFFSETS: &[(usize, usize)] = &[(0, 8), (8, 6), (14, 7), (21, 4), (25, 1), (26, 8), (34, 1), (35, 1)];
+
+fn get_mtlog_field(line: &str, col: usize) -> String {
+    if col >= OFFSETS.len() {
+        return String::new();
+    }
+    let (start, len) = OFFSETS[col];
+    line.get(start..start + len).unwrap_or("").trim().to_string()
+}
+
+/// The comparator `mtlog_sort_key` replaced: two trimmed `String`s per column per
+/// comparison, and numbers re-parsed every time.
+fn compare_mtlog_by_columns_legacy(a: &str, b: &str, sort_columns: &[MTLogSortColumn]) -> Ordering {
+    for col in sort_columns {
+        let (v1, v2) = (get_mtlog_field(a, col.index), get_mtlog_field(b, col.index));
+        let ord = match col.col_type {
+            MTLogSortType::Date | MTLogSortType::Time => v1.cmp(&v2),
+            MTLogSortType::Num => v1.parse::<u64>().unwrap_or(0).cmp(&v2.parse::<u64>().unwrap_or(0)),
+            MTLogSortType::Str => v1.cmp(&v2),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+fn records() -> Vec<String> {
+    let mut rng = rand::rng();
+    (0..RECORDS)
+        .map(|_| {
+            let mut line = format!(
+                "{:04}{:02}{:02}{:02}{:02}{:02}{:07}ATM 1TRANCODE",
+                rng.random_range(2000..=2025),
+                rng.random_range(1..=12),
+                rng.random_range(1..=28),
+                rng.random_range(0..=23),
+                rng.random_range(0..=59),
+                rng.random_range(0..=59),
+                rng.random_range(1000000..=9999999),
+            );
+            line.extend(std::iter::repeat_n('X', RECORD_LEN - line.len()));
+            line
+        })
+        .collect()
+}
+
+fn sort_columns() -> Vec<MTLogSortColumn> {
+    vec![
+        MTLogSortColumn { index: 0, col_type: MTLogSortType::Date },
+        MTLogSortColumn { index: 1, col_type: MTLogSortType::Time },
+        MTLogSortColumn { index: 2, col_type: MTLogSortType::Num },
+    ]
+}
+
+fn bench_sort(c: &mut Criterion) {
+    let lines = records();
+    let cols = sort_columns();
+    let mut group = c.benchmark_group("mtlog_sort_20k");
+    group.sample_size(20);
+    group.bench_function("legacy_compare", |b| {
+        b.iter_batched(
+            || lines.clone(),
+            |mut v| {
+                v.sort_unstable_by(|a, b| compare_mtlog_by_columns_legacy(a, b, &cols));
+                black_box(v)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("precomputed_key", |b| {
+        b.iter_batched(
+            || lines.clone(),
+            |v| {
+                let mut keyed: Vec<(Vec<u8>, String)> =
+                    v.into_iter().map(|l| (mtlog_sort_key(l.as_bytes(), &cols), l)).collect();
+                keyed.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+                black_box(keyed)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_compare(c: &mut Criterion) {
+    let lines = records();
+    let cols = sort_columns();
+    let keys: Vec<Vec<u8>> = lines.iter().map(|l| mtlog_sort_key(l.as_bytes(), &cols)).collect();
+    let mut group = c.benchmark_group("mtlog_compare_pair");
+    group.bench_function("legacy_compare", |b| {
+        b.iter(|| compare_mtlog_by_columns_legacy(black_box(&lines[0]), black_box(&lines[1]), &cols))
+    });
+    group.bench_function("precomputed_key", |b| b.iter(|| black_box(&keys[0]).cmp(black_box(&keys[1]))));
+    group.finish();
+}
+
+criterion_group!(benches, bench_sort, bench_compare);
+criterion_main!(benches);
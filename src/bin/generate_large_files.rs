@@ -1,7 +1,5 @@
 use anyhow::{Context, Result};
-use humansize::{format_size, DECIMAL};
 use rand::seq::SliceRandom;
-use rand::Rng;
 use rayon::prelude::*;
 use std::env;
 use std::fs::File;
@@ -60,7 +58,7 @@ fn generate_large_csv(file_path: &str, rows: usize, _start_account_no: usize) ->
     let batch_size = 200_000;
     // สุ่ม account_no ไม่เรียงและไม่ซ้ำ
     let mut account_nos: Vec<usize> = (1..=rows * 10).collect();
-    let mut rng = rand::thread_rng();
+    let mut rng = rand::rng();
     account_nos.shuffle(&mut rng);
     let picked: Vec<usize> = account_nos.into_iter().take(rows).collect();
 
@@ -71,10 +69,8 @@ fn generate_large_csv(file_path: &str, rows: usize, _start_account_no: usize) ->
             .par_iter()
             .map(|&account_no| {
                 format!(
-                    "{},{},{}",
-                    account_no,
-                    format!("FirstName{}", account_no),
-                    format!("LastName{}", account_no)
+                    "{},FirstName{},LastName{}",
+                    account_no, account_no, account_no
                 )
             })
             .collect();
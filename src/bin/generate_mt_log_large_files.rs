@@ -1,8 +1,7 @@
 use log::info;
 use rand::Rng;
-use chrono::{Datelike, Local};
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::Write;
 use split_merge_hub_demo::mt_log::mt_log_record::MTLogRecord;
 use rayon::prelude::*;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -10,16 +9,16 @@ use std::sync::Arc;
 use std::time::Instant;
 
 fn random_date<R: Rng>(rng: &mut R) -> String {
-    let year = rng.gen_range(2000..=2025);
-    let month = rng.gen_range(1..=12);
-    let day = rng.gen_range(1..=28); // safe for all months
+    let year = rng.random_range(2000..=2025);
+    let month = rng.random_range(1..=12);
+    let day = rng.random_range(1..=28); // safe for all months
     format!("{:04}{:02}{:02}", year, month, day)
 }
 
 fn random_time_hhmmss<R: Rng>(rng: &mut R) -> String {
-    let hour = rng.gen_range(0..=23);
-    let min = rng.gen_range(0..=59);
-    let sec = rng.gen_range(0..=59);
+    let hour = rng.random_range(0..=23);
+    let min = rng.random_range(0..=59);
+    let sec = rng.random_range(0..=59);
     format!("{:02}{:02}{:02}", hour, min, sec)
 }
 
@@ -27,17 +26,17 @@ fn random_mt_log_record<R: Rng>(rng: &mut R) -> MTLogRecord {
     MTLogRecord {
         milog_rec_sys_date: random_date(rng).parse().unwrap(),
         milog_rec_sys_time: random_time_hhmmss(rng),
-        milog_rec_taskno: rng.gen_range(1000000..=9999999),
+        milog_rec_taskno: rng.random_range(1000000..=9999999),
         milog_channel_code: "ATM".to_string(),
         milog_rec_rectype: "1".to_string(),
         milog_ts_ext_tran_code: "TRANCODE".to_string(),
         milog_tran_type: "T".to_string(),
         milog_record_status: "S".to_string(),
-        milog_atm_cardnumber: format!("{:016}", rng.gen_range(0..=9999999999999999u64)),
-        milog_terminal_id: format!("TERM{:010}", rng.gen_range(0..=9999999999u64)),
-        milog_terminal_recno: format!("{:06}", rng.gen_range(0..=999999)),
-        milog_ts_teller_id: format!("TELL{:04}", rng.gen_range(0..=9999)),
-        milog_ts_tran_serno: rng.gen_range(0..=999999),
+        milog_atm_cardnumber: format!("{:016}", rng.random_range(0..=9999999999999999u64)),
+        milog_terminal_id: format!("TERM{:010}", rng.random_range(0..=9999999999u64)),
+        milog_terminal_recno: format!("{:06}", rng.random_range(0..=999999)),
+        milog_ts_teller_id: format!("TELL{:04}", rng.random_range(0..=9999)),
+        milog_ts_tran_serno: rng.random_range(0..=999999),
         milog_ts_proc_date: random_date(rng),
         milog_eib_tranid: "EIBT".to_string(),
         milog_eib_termid: "EIBT".to_string(),
@@ -47,65 +46,65 @@ fn random_mt_log_record<R: Rng>(rng: &mut R) -> MTLogRecord {
         mit_isc_cics_tran_code: "CICS".to_string(),
         mit_isc_func_code: "FUNCODE".to_string(),
         mit_isc_front_end_login_id: "LOGINID".to_string(),
-        mit_isc_front_end_tran_serno: rng.gen_range(0..=999999),
+        mit_isc_front_end_tran_serno: rng.random_range(0..=999999),
         mit_isc_reversal_flag: "N".to_string(),
         mit_isc_tran_time: random_time_hhmmss(rng),
         mit_isc_tran_posting_date: random_date(rng),
-        mit_isc_tran_branch_code: format!("{:04}", rng.gen_range(0..=9999)),
+        mit_isc_tran_branch_code: format!("{:04}", rng.random_range(0..=9999)),
         mit_isc_channel_code: "CHN1".to_string(),
         mit_isc_front_end_term_id: "TERMFRONTEND".to_string(),
-        mit_isc_front_end_term_recno: format!("{:06}", rng.gen_range(0..=999999)),
+        mit_isc_front_end_term_recno: format!("{:06}", rng.random_range(0..=999999)),
         mit_isc_repeat_ind: "N".to_string(),
         mit_mq_channel: "MQCH".to_string(),
         mit_mq_trans_id: "MQTI".to_string(),
         mit_mq_trans_desc: "DESC".to_string(),
         mit_mq_rquid: "RQUID".to_string(),
-        mit_acct1_acctnum: format!("{:020}", rng.gen_range(0..=99999999999999999999u128)),
-        mit_acct2_acctnum: format!("{:020}", rng.gen_range(0..=99999999999999999999u128)),
-        mit_acct3_acctnum: format!("{:010}", rng.gen_range(0..=9999999999u64)),
+        mit_acct1_acctnum: format!("{:020}", rng.random_range(0..=99999999999999999999u128)),
+        mit_acct2_acctnum: format!("{:020}", rng.random_range(0..=99999999999999999999u128)),
+        mit_acct3_acctnum: format!("{:010}", rng.random_range(0..=9999999999u64)),
         mit_acct3_filler: " ".to_string(),
-        mit_bank_cd: format!("{:02}", rng.gen_range(0..=99)),
+        mit_bank_cd: format!("{:02}", rng.random_range(0..=99)),
         mit_drcr_ind: "D".to_string(),
         mit_financial_type: "FTYP".to_string(),
-        mit_cheque_number: rng.gen_range(0..=9999999999),
+        mit_cheque_number: rng.random_range(0..=9999999999),
         mit_cheque_clrg_type: "CL".to_string(),
-        mit_dr_tran_amount: rng.gen_range(0..=999999999999999),
+        mit_dr_tran_amount: rng.random_range(0..=999999999999999),
         mit_dr_tran_ccy: "THB".to_string(),
         mit_dr_user_tran_code: "DRUC".to_string(),
         mit_dr_ats_company_id: "ATSID1".to_string(),
         mit_dr_ats_desc: "DAD".to_string(),
         filler_r2: " ".to_string(),
-        mit_cr_tran_amount: rng.gen_range(0..=999999999999999),
+        mit_cr_tran_amount: rng.random_range(0..=999999999999999),
         mit_cr_tran_ccy: "THB".to_string(),
         mit_cr_user_tran_code: "CRUC".to_string(),
         mit_cr_ats_company_id: "ATSID2".to_string(),
         mit_cr_ats_desc: "CAD".to_string(),
         filler_r3: " ".to_string(),
-        mit_chg_tran_amount: rng.gen_range(0..=999999999999999),
+        mit_chg_tran_amount: rng.random_range(0..=999999999999999),
         mit_chg_tran_ccy: "THB".to_string(),
         mit_chg_user_tran_code: "CHUC".to_string(),
         mit_chg_tran_desc: "CHGDESC".to_string(),
         mit_fee_process_ind: "F".to_string(),
         mit_fee_type_01: "F01".to_string(),
-        mit_fee_amount_01: rng.gen_range(0..=999999999999999),
+        mit_fee_amount_01: rng.random_range(0..=999999999999999),
         mit_fee_type_02: "F02".to_string(),
-        mit_fee_amount_02: rng.gen_range(0..=999999999999999),
+        mit_fee_amount_02: rng.random_range(0..=999999999999999),
         mit_fee_type_03: "F03".to_string(),
-        mit_fee_amount_03: rng.gen_range(0..=999999999999999),
+        mit_fee_amount_03: rng.random_range(0..=999999999999999),
         mit_fee_type_04: "F04".to_string(),
-        mit_fee_amount_04: rng.gen_range(0..=999999999999999),
+        mit_fee_amount_04: rng.random_range(0..=999999999999999),
         mit_fee_type_05: "F05".to_string(),
-        mit_fee_amount_05: rng.gen_range(0..=999999999999999),
+        mit_fee_amount_05: rng.random_range(0..=999999999999999),
         mit_fee_type_06: "F06".to_string(),
-        mit_fee_amount_06: rng.gen_range(0..=999999999999999),
+        mit_fee_amount_06: rng.random_range(0..=999999999999999),
         mit_fee_type_07: "F07".to_string(),
-        mit_fee_amount_07: rng.gen_range(0..=999999999999999),
+        mit_fee_amount_07: rng.random_range(0..=999999999999999),
         mit_fee_type_08: "F08".to_string(),
-        mit_fee_amount_08: rng.gen_range(0..=999999999999999),
+        mit_fee_amount_08: rng.random_range(0..=999999999999999),
         mit_fee_type_09: "F09".to_string(),
-        mit_fee_amount_09: rng.gen_range(0..=999999999999999),
+        mit_fee_amount_09: rng.random_range(0..=999999999999999),
         mit_fee_type_10: "F10".to_string(),
-        mit_fee_amount_10: rng.gen_range(0..=999999999999999),
+        mit_fee_amount_10: rng.random_range(0..=999999999999999),
         mit_bpay_extra_flag: "B".to_string(),
         mit_bpay_extra_data_1: "BPED1".to_string(),
         mit_bpay_extra_data_2: "BPED2".to_string(),
@@ -185,13 +184,13 @@ fn main() {
 
 fn generate_mt_log_file_parallel(file_path: &str, rows: usize) {
     let batch_size = 100_000;
-    let batches = (rows + batch_size - 1) / batch_size;
+    let batches = rows.div_ceil(batch_size);
     println!("📝 Generating: {} ({} rows, {} batches)", file_path, rows, batches);
     let start = Instant::now();
-    let mut file = File::create(file_path).expect("Failed to create file");
+    File::create(file_path).expect("Failed to create file");
     let counter = Arc::new(AtomicUsize::new(0));
     (0..batches).into_par_iter().for_each(|batch_idx| {
-        let mut rng = rand::thread_rng();
+        let mut rng = rand::rng();
         let start_row = batch_idx * batch_size;
         let end_row = ((batch_idx + 1) * batch_size).min(rows);
         let mut buf = Vec::with_capacity((end_row - start_row) * 4320);
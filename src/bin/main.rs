@@ -1,14 +1,14 @@
 use anyhow::{Context, Result};
-use chrono::Local;
 use clap::{Parser, Subcommand};
-use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use log::{debug, info};
-use std::fs::{self, File};
-use std::io::{self, BufReader, BufWriter, Write, BufRead};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
-use rayon::slice::ParallelSliceMut;
 use split_merge_hub_demo::parallel_merge::*;
 
 /// A tool for splitting and merging CSV files or MT log files with parallel processing
@@ -23,15 +23,20 @@ struct Cli {
 enum Commands {
     /// Merge multiple CSV files or MT log files into one
     Merge {
-        /// Input files to merge (CSV or MT log)
+        /// Input files to merge (CSV or MT log); `-` reads stdin
         #[arg(required = true)]
         input_files: Vec<String>,
 
-        /// Output file path
-        #[arg(short, long)]
-        output: String,
+        /// Output file path (`-` for stdout)
+        #[arg(short, long, required_unless_present = "into")]
+        output: Option<String>,
+
+        /// Existing sorted output to merge the inputs into: only the inputs are sorted,
+        /// then merged with it in one pass (sort keys are checked against `<file>.meta.json`)
+        #[arg(long, conflicts_with = "output")]
+        into: Option<String>,
 
-        /// Columns to sort by (comma-separated, for CSV only)
+        /// Columns to sort by (comma-separated, for CSV only), each `name[:auto|num|str][:asc|desc]`
         #[arg(long, value_delimiter = ',')]
         sort_by: Vec<String>,
 
@@ -43,28 +48,273 @@ enum Commands {
         #[arg(long, default_value = "false")]
         mt_log: bool,
 
-        /// MT log sort columns (e.g. 0:date,1:time,5:num)
+        /// MT log sort columns, by index in `mt_log_record::FIELDS` (e.g. 0:date,1:time,26:str)
         #[arg(long, value_delimiter = ',')]
         mtlog_sort_cols: Vec<String>,
+
+        /// Show progress bars (one per chunking worker, plus merge progress with ETA)
+        #[arg(long, default_value = "false")]
+        progress: bool,
+
+        /// Write an empty `<output>.done` marker once the output is in place
+        #[arg(long, default_value = "false")]
+        done_marker: bool,
+
+        /// Write a `<output>.sha256` checksum sidecar (sha256sum format)
+        #[arg(long, default_value = "false")]
+        checksum: bool,
+
+        /// Merge key ranges in parallel into partition files (`<stem>.part-NNNNN.<ext>`) instead of one output
+        #[arg(long, default_value = "false")]
+        partitioned: bool,
+
+        /// Inputs are already sorted: merge them directly, failing on an unsorted input
+        /// (`--presorted=fallback` sorts them in full instead)
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "fail", value_parser = ["fail", "fallback"])]
+        presorted: Option<String>,
+
+        /// How CSV inputs with differing headers are merged: `strict` (identical headers),
+        /// `reorder` (same columns in any order), `union` (all columns, missing ones left empty)
+        /// or `intersect` (only the columns every input has)
+        #[arg(long, default_value = "strict", conflicts_with = "mt_log")]
+        schema: SchemaMode,
+
+        /// JSON object of input column names to merged names, e.g. `{"acct_no": "acct"}`,
+        /// applied before the headers are reconciled
+        #[arg(long, conflicts_with = "mt_log")]
+        rename_map: Option<PathBuf>,
+
+        /// Columns to write, in order (comma-separated), each `name` or `name=new_name`;
+        /// with --mt-log, MT log fields written as CSV. Sort keys need not be selected
+        #[arg(long, value_delimiter = ',', value_parser = SelectColumn::parse, conflicts_with = "into")]
+        select: Vec<SelectColumn>,
+
+        /// Only merge records matching this expression, e.g. `branch IN ('001','002') AND
+        /// amount:num >= 1000` (with --mt-log, over MT log field names); see the README
+        #[arg(long = "where", value_parser = Filter::parse)]
+        filter: Option<Filter>,
+
+        /// Write the output as `parquet` or `arrow` (Arrow IPC) [default: by the output's
+        /// extension (`.parquet`, `.arrow`), CSV or MT log otherwise]
+        #[arg(long, conflicts_with = "into")]
+        output_format: Option<ColumnarFormat>,
+
+        #[command(flatten)]
+        columnar: ColumnarArgs,
+
+        #[command(flatten)]
+        dialect: DialectArgs,
     },
 
-    /// Split a CSV file into smaller chunks
+    /// Split a CSV file or MT log file into smaller files
     Split {
-        /// Input CSV file to split
+        /// Input file to split (CSV or MT log)
         input_file: String,
 
         /// Output directory for split files
         #[arg(short, long, default_value = "split_files")]
         output_dir: String,
 
-        /// Maximum number of rows per file
-        #[arg(short, long, default_value = "10000")]
-        rows_per_file: usize,
+        /// Maximum number of rows per file [default: 10000, unlimited with --partition-by, --max-bytes or --parts]
+        #[arg(short, long)]
+        rows_per_file: Option<usize>,
 
-        /// Columns to sort by (comma-separated)
-        #[arg(long, value_delimiter = ',')]
+        /// Maximum size per file, including the header (e.g. 2G, 500M, 64K or bytes);
+        /// records are never broken
+        #[arg(long, value_parser = parse_byte_size)]
+        max_bytes: Option<u64>,
+
+        /// Columns to sort by (comma-separated, for CSV only), each `name[:auto|num|str][:asc|desc]`;
+        /// the parts follow each other in sorted order
+        #[arg(long, value_delimiter = ',', conflicts_with = "mt_log")]
         sort_by: Vec<String>,
+
+        /// MT log mode (split whole fixed-width MT log records; keys are MT log field names
+        /// such as `mit_isc_tran_branch_code`)
+        #[arg(long, default_value = "false")]
+        mt_log: bool,
+
+        /// MT log sort columns, by index in `mt_log_record::FIELDS` (e.g. 0:date,1:time,26:str);
+        /// the parts follow each other in sorted order
+        #[arg(long, value_delimiter = ',', requires = "mt_log")]
+        mtlog_sort_cols: Vec<String>,
+
+        /// Write one set of files per value of these columns (comma-separated)
+        #[arg(long, value_delimiter = ',')]
+        partition_by: Vec<String>,
+
+        /// Split into exactly N files of about the same size, by --hash-by or --round-robin
+        #[arg(long, conflicts_with_all = ["rows_per_file", "max_bytes", "partition_by"])]
+        parts: Option<usize>,
+
+        /// Columns (comma-separated) whose values pick the file, so equal values share a file
+        #[arg(long, value_delimiter = ',', requires = "parts")]
+        hash_by: Vec<String>,
+
+        /// Deal the rows to the files in turn [default with --parts unless --hash-by is given]
+        #[arg(long, requires = "parts", conflicts_with = "hash_by")]
+        round_robin: bool,
+
+        /// Output file names, relative to the output directory (`{key}`, `{stem}`, `{part}`,
+        /// `{part:04}`) [default: `{stem}_part_{part:04}.csv`, `{key}/{stem}_{part:04}.csv` with --partition-by;
+        /// `.mtlog` instead of `.csv` with --mt-log]
+        #[arg(long)]
+        template: Option<String>,
+
+        /// Columns to write, in order (comma-separated), each `name` or `name=new_name`;
+        /// with --mt-log, MT log fields written as CSV parts
+        #[arg(long, value_delimiter = ',', value_parser = SelectColumn::parse)]
+        select: Vec<SelectColumn>,
+
+        /// Only write records matching this expression (as for merge)
+        #[arg(long = "where", value_parser = Filter::parse)]
+        filter: Option<Filter>,
+
+        #[command(flatten)]
+        dialect: DialectArgs,
     },
+
+    /// Convert MT log files to CSV, JSON Lines, Parquet or Arrow IPC, or CSV or JSON Lines back to MT log
+    Convert {
+        /// Input file (`-` for stdin)
+        input_file: String,
+
+        /// Output file (`-` for stdout)
+        #[arg(short, long)]
+        output: String,
+
+        /// Output format: `csv`, `jsonl`, `parquet`, `arrow` or `mtlog`
+        #[arg(long)]
+        to: ConvertFormat,
+
+        /// Input format [default: `mtlog`; with `--to mtlog`, by the input's extension (`.csv` or `.jsonl`)]
+        #[arg(long)]
+        from: Option<ConvertFormat>,
+
+        #[command(flatten)]
+        columnar: ColumnarArgs,
+
+        #[command(flatten)]
+        dialect: DialectArgs,
+    },
+}
+
+/// How Parquet and Arrow IPC outputs are written
+#[derive(clap::Args, Debug)]
+struct ColumnarArgs {
+    /// Rows per Parquet row group or Arrow IPC record batch; CSV column types are inferred
+    /// from the first one
+    #[arg(long, default_value_t = ColumnarOptions::DEFAULT_ROW_GROUP_ROWS)]
+    row_group_size: usize,
+
+    /// Compression of Parquet or Arrow IPC output: `none`, `snappy`, `gzip`, `lz4` or `zstd`
+    /// (Arrow IPC: `lz4` or `zstd`) [default: `snappy` for Parquet, none for Arrow IPC]
+    #[arg(long)]
+    compression: Option<ColumnarCompression>,
+
+    /// Do not dictionary-encode Parquet columns
+    #[arg(long, default_value = "false")]
+    no_dictionary: bool,
+
+    /// Write every CSV column as text instead of inferring integer, decimal, boolean and date columns
+    #[arg(long, default_value = "false")]
+    no_infer_types: bool,
+}
+
+impl ColumnarArgs {
+    fn options(&self, format: ColumnarFormat) -> Result<ColumnarOptions> {
+        let options = ColumnarOptions {
+            format,
+            row_group_rows: self.row_group_size,
+            compression: self.compression,
+            dictionary: !self.no_dictionary,
+            infer_types: !self.no_infer_types,
+        };
+        options.check()?;
+        Ok(options)
+    }
+}
+
+/// CSV dialect of the inputs and the output
+#[derive(clap::Args, Debug)]
+struct DialectArgs {
+    /// Field delimiter of CSV inputs: one ASCII character, `tab` or a hex byte such as `\x1F`
+    #[arg(long, value_parser = parse_csv_byte, default_value = ",")]
+    delimiter: u8,
+
+    /// Quote character of CSV inputs
+    #[arg(long, value_parser = parse_csv_byte, default_value = "\"")]
+    quote: u8,
+
+    /// Escape character for quotes inside quoted fields of CSV inputs (e.g. `\`),
+    /// instead of doubling them
+    #[arg(long, value_parser = parse_csv_byte)]
+    escape: Option<u8>,
+
+    /// Record terminator of CSV inputs: `lf` (`\n` or `\r\n`), `crlf` or one character
+    #[arg(long, value_parser = parse_terminator, default_value = "lf")]
+    terminator: RecordTerminator,
+
+    /// Skip CSV input records starting with this character
+    #[arg(long, value_parser = parse_csv_byte)]
+    comment: Option<u8>,
+
+    /// CSV inputs have no header row; columns are named by position (`1`, `2`, ...)
+    #[arg(long, default_value = "false")]
+    no_header: bool,
+
+    /// Encoding of CSV inputs, UTF-8 or single-byte, decoded to UTF-8 (e.g. `tis-620`, `windows-874`, `latin1`)
+    #[arg(long)]
+    encoding: Option<String>,
+
+    /// Field delimiter of the CSV output [default: --delimiter]
+    #[arg(long, value_parser = parse_csv_byte)]
+    output_delimiter: Option<u8>,
+
+    /// Quote character of the CSV output [default: --quote]
+    #[arg(long, value_parser = parse_csv_byte)]
+    output_quote: Option<u8>,
+
+    /// Escape character for quotes in the CSV output [default: quotes are doubled]
+    #[arg(long, value_parser = parse_csv_byte)]
+    output_escape: Option<u8>,
+
+    /// Record terminator of the CSV output [default: --terminator]
+    #[arg(long, value_parser = parse_terminator)]
+    output_terminator: Option<RecordTerminator>,
+
+    /// Whether the CSV output starts with a header row [default: true unless --no-header]
+    #[arg(long, value_name = "BOOL")]
+    output_header: Option<bool>,
+}
+
+impl DialectArgs {
+    /// The input and output dialects; output options default to the input's.
+    fn dialects(&self) -> Result<(CsvDialect, CsvDialect)> {
+        let input = CsvDialect {
+            delimiter: self.delimiter,
+            quote: self.quote,
+            escape: self.escape,
+            terminator: self.terminator,
+            comment: self.comment,
+            has_header: !self.no_header,
+            encoding: match &self.encoding {
+                Some(label) => CsvDialect::encoding_for_label(label)?,
+                None => CsvDialect::default().encoding,
+            },
+        };
+        let output = CsvDialect {
+            delimiter: self.output_delimiter.unwrap_or(input.delimiter),
+            quote: self.output_quote.unwrap_or(input.quote),
+            escape: self.output_escape,
+            terminator: self.output_terminator.unwrap_or(input.terminator),
+            has_header: self.output_header.unwrap_or(input.has_header),
+            ..CsvDialect::default()
+        };
+        input.check().context("Invalid input CSV dialect")?;
+        output.check().context("Invalid output CSV dialect")?;
+        Ok((input, output))
+    }
 }
 
 fn main() -> Result<()> {
@@ -82,32 +332,242 @@ fn main() -> Result<()> {
             chunk_size,
             mt_log,
             mtlog_sort_cols,
+            progress,
+            done_marker,
+            checksum,
+            partitioned,
+            presorted,
+            schema,
+            rename_map,
+            select,
+            filter,
+            into,
+            output_format,
+            columnar,
+            dialect,
         } => unsafe {
             // Set the chunk size as an environment variable
             std::env::set_var("CHUNK_SIZE_MB", chunk_size.to_string());
             let sort_columns: Vec<&str> = sort_by.iter().map(|s| s.as_str()).collect();
-            if mt_log {
+            let mut options = RunOptions::default();
+            if progress {
+                options.progress = Arc::new(TerminalProgress::new());
+            }
+            if partitioned && sort_by.is_empty() && !mt_log {
+                anyhow::bail!("--partitioned needs a sorted merge (--sort-by or --mt-log)");
+            }
+            if into.is_some() && sort_by.is_empty() && !mt_log {
+                anyhow::bail!("--into needs a sorted merge (--sort-by or --mt-log)");
+            }
+            let output = into.clone().or(output).expect("clap requires --output or --into");
+            if presorted.is_some() && sort_by.is_empty() && !mt_log {
+                anyhow::bail!("--presorted needs a sorted merge (--sort-by or --mt-log)");
+            }
+            options.presorted = match presorted.as_deref() {
+                Some("fallback") => Presorted::OrSort,
+                Some(_) => Presorted::Verify,
+                None => Presorted::No,
+            };
+            if output == "-" && (done_marker || checksum || partitioned) {
+                anyhow::bail!("--done-marker, --checksum and --partitioned need an output file, not stdout");
+            }
+            if input_files.iter().filter(|f| *f == "-").count() > 1 {
+                anyhow::bail!("Stdin (`-`) can only be given once as an input");
+            }
+            options.output = OutputOptions { done_marker, checksum, partitioned };
+            (options.input_dialect, options.output_dialect) = dialect.dialects()?;
+            let renames = rename_map.as_deref().map(Schema::load_renames).transpose()?.unwrap_or_default();
+            options.schema = Schema { mode: schema, renames };
+            options.select = select;
+            options.filter = filter;
+            let output_format = output_format.or_else(|| into.is_none().then(|| ColumnarFormat::from_path(Path::new(&output))).flatten());
+            options.columnar = output_format.map(|format| columnar.options(format)).transpose()?;
+            cancel_on_signal(options.cancel.clone())?;
+            let result = if mt_log {
                 let input_paths: Vec<std::path::PathBuf> = input_files.iter().map(std::path::PathBuf::from).collect();
                 let sort_columns = parse_mtlog_sort_cols(&mtlog_sort_cols)?;
-                split_merge_hub_demo::parallel_merge::parallel_merge_sort_mtlog(&input_paths, &output, &sort_columns)
+                if into.is_some() {
+                    parallel_merge_into_mtlog(Path::new(&output), &input_paths, &sort_columns, &options)
+                } else {
+                    split_merge_hub_demo::parallel_merge::parallel_merge_sort_mtlog_with(&input_paths, &output, &sort_columns, &options)
+                }
+            } else if into.is_some() {
+                let input_paths: Vec<PathBuf> = input_files.iter().map(PathBuf::from).collect();
+                parallel_merge_into(Path::new(&output), &input_paths, &sort_columns, &options)
             } else {
-                merge_csv_files(&input_files, &output, &sort_columns)
-            }
+                merge_csv_files(&input_files, &output, &sort_columns, &options)
+            };
+            exit_if_cancelled(&result);
+            result
         },
         Commands::Split {
             input_file,
             output_dir,
             rows_per_file,
+            max_bytes,
             sort_by,
+            mt_log,
+            mtlog_sort_cols,
+            partition_by,
+            parts,
+            hash_by,
+            round_robin: _,
+            template,
+            select,
+            filter,
+            dialect,
         } => {
-            let sort_columns: Vec<&str> = sort_by.iter().map(|s| s.as_str()).collect();
-            split_csv_file(&input_file, &output_dir, rows_per_file, &sort_columns)
+            let by_key = !partition_by.is_empty();
+            // MT log fields are selected into CSV parts
+            let default_template = match (mt_log && select.is_empty(), by_key) {
+                (false, false) => CSV_TEMPLATE,
+                (false, true) => CSV_KEY_TEMPLATE,
+                (true, false) => MTLOG_TEMPLATE,
+                (true, true) => MTLOG_KEY_TEMPLATE,
+            };
+            let split = SplitOptions {
+                template: template.unwrap_or_else(|| default_template.to_string()),
+                partition_by,
+                max_rows: rows_per_file.or((!by_key && max_bytes.is_none() && parts.is_none()).then_some(10000)),
+                max_bytes,
+                parts,
+                hash_by,
+            };
+            let mut options = RunOptions::default();
+            (options.input_dialect, options.output_dialect) = dialect.dialects()?;
+            options.select = select;
+            options.filter = filter;
+            if mt_log {
+                let sort_columns = parse_mtlog_sort_cols(&mtlog_sort_cols)?;
+                split_file(&input_file, &output_dir, options, |input, output_dir, options| {
+                    split_mtlog(input, output_dir, &sort_columns, &split, options)
+                })
+            } else {
+                let sort_columns: Vec<&str> = sort_by.iter().map(|s| s.as_str()).collect();
+                split_file(&input_file, &output_dir, options, |input, output_dir, options| {
+                    split_csv(input, output_dir, &sort_columns, &split, options)
+                })
+            }
+        }
+        Commands::Convert {
+            input_file,
+            output,
+            to,
+            from,
+            columnar,
+            dialect,
+        } => {
+            let from = from.unwrap_or(match to {
+                ConvertFormat::MtLog => ConvertFormat::from_path(Path::new(&input_file)),
+                _ => ConvertFormat::MtLog,
+            });
+            let mut options = RunOptions::default();
+            (options.input_dialect, options.output_dialect) = dialect.dialects()?;
+            let format = match to {
+                ConvertFormat::Parquet => Some(ColumnarFormat::Parquet),
+                ConvertFormat::ArrowIpc => Some(ColumnarFormat::ArrowIpc),
+                _ => None,
+            };
+            options.columnar = format.map(|format| columnar.options(format)).transpose()?;
+            cancel_on_signal(options.cancel.clone())?;
+            let result = convert_file(Path::new(&input_file), Path::new(&output), from, to, &options);
+            exit_if_cancelled(&result);
+            result
+        }
+    }
+}
+
+/// Cancels `token` on SIGINT/SIGTERM so the run can unwind and remove its temp files
+/// (outputs are written to a temp file and only renamed into place on success).
+/// A second signal exits immediately.
+fn cancel_on_signal(token: CancellationToken) -> Result<()> {
+    ctrlc::set_handler(move || {
+        if token.is_cancelled() {
+            std::process::exit(130);
+        }
+        eprintln!("\nCancelling... (press Ctrl-C again to force quit)");
+        token.cancel();
+    })
+    .context("Failed to install signal handler")
+}
+
+/// If `result` is a cancellation, exits with status 130.
+fn exit_if_cancelled<T>(result: &Result<T>) {
+    let Err(e) = result else { return };
+    if !e.chain().any(|cause| cause.is::<Cancelled>()) {
+        return;
+    }
+    eprintln!("❌ Cancelled");
+    std::process::exit(130);
+}
+
+/// Renders library progress updates as terminal progress bars on stderr.
+///
+/// Updates with a total get a bar with ETA; per-worker chunking updates, whose
+/// share of the input is not known up front, get a spinner with running counters.
+struct TerminalProgress {
+    multi: MultiProgress,
+    bars: Mutex<HashMap<(ProgressPhase, Option<usize>), ProgressBar>>,
+}
+
+impl TerminalProgress {
+    fn new() -> Self {
+        Self {
+            multi: MultiProgress::with_draw_target(ProgressDrawTarget::stderr()),
+            bars: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn new_bar(&self, update: &ProgressUpdate) -> ProgressBar {
+        let phase = match update.phase {
+            ProgressPhase::Chunk => "chunk",
+            ProgressPhase::Merge => "merge",
+            ProgressPhase::Validate => "validate",
+        };
+        let per_worker_chunk = update.phase == ProgressPhase::Chunk && update.worker.is_some();
+        let bar = if per_worker_chunk || update.total_bytes == 0 {
+            ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template("{prefix:>12} {spinner} {bytes} {msg}")
+                    .expect("valid progress template"),
+            )
+        } else {
+            ProgressBar::new(update.total_bytes).with_style(
+                ProgressStyle::with_template(
+                    "{prefix:>12} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {msg} ETA {eta}",
+                )
+                .expect("valid progress template")
+                .progress_chars("=> "),
+            )
+        };
+        let prefix = match update.worker {
+            Some(worker) => format!("{} #{}", phase, worker),
+            None => phase.to_string(),
+        };
+        self.multi.add(bar.with_prefix(prefix))
+    }
+}
+
+impl ProgressSink for TerminalProgress {
+    fn on_progress(&self, update: &ProgressUpdate) {
+        let mut bars = self.bars.lock().expect("progress bar lock poisoned");
+        let bar = bars
+            .entry((update.phase, update.worker))
+            .or_insert_with(|| self.new_bar(update));
+        bar.set_position(update.bytes_processed);
+        bar.set_message(format!("{} records", update.records_processed));
+        if update.total_bytes > 0 && update.bytes_processed >= update.total_bytes {
+            bar.finish();
         }
     }
 }
 
 /// Merges multiple CSV files into a single output file with optional sorting
-fn merge_csv_files(input_files: &[String], output_file: &str, sort_columns: &[&str]) -> Result<()> {
+fn merge_csv_files(
+    input_files: &[String],
+    output_file: &str,
+    sort_columns: &[&str],
+    options: &RunOptions,
+) -> Result<()> {
     info!("Merging {} files into {}", input_files.len(), output_file);
     let start_time = Instant::now();
 
@@ -119,18 +579,11 @@ fn merge_csv_files(input_files: &[String], output_file: &str, sort_columns: &[&s
         debug!("No sorting needed");
         debug!("Concatenating files: {:?}", input_paths);
 
-        let first_file = File::open(&input_paths[0]).context("Failed to open first input file")?;
-        let headers = ReaderBuilder::new()
-            .has_headers(true)
-            .from_reader(&first_file)
-            .headers()?
-            .clone();
-
-        concatenate_files(&input_paths, output_file, &headers)?;
+        concatenate_files(&input_paths, output_file, options)?;
     } else {
         // Use parallel merge sort for large files with sorting
         debug!("Using parallel merge sort");
-        parallel_merge_sort(&input_paths, Path::new(output_file), sort_columns)
+        parallel_merge_sort_with(&input_paths, Path::new(output_file), sort_columns, options)
             .context("Parallel merge sort failed")?;
     }
 
@@ -140,242 +593,179 @@ fn merge_csv_files(input_files: &[String], output_file: &str, sort_columns: &[&s
     Ok(())
 }
 
-/// Concatenates multiple CSV files without sorting
-fn concatenate_files(files: &[PathBuf], output_file: &str, headers: &StringRecord) -> Result<()> {
+/// Concatenates multiple CSV files without sorting, keeping the first file's header
+/// (read in the input dialect, written in the output dialect)
+fn concatenate_files(
+    files: &[PathBuf],
+    output_file: &str,
+    options: &RunOptions,
+) -> Result<()> {
     info!("Concatenating {} files", files.len());
 
-    let output = File::create(output_file).context("Failed to create output file")?;
-    let mut writer = WriterBuilder::new()
-        .has_headers(true)
-        .from_writer(io::BufWriter::new(output));
+    // `-` streams to stdout; files are only published once complete
+    let output = match is_stdio(Path::new(output_file)) {
+        true => None,
+        false => Some(AtomicOutput::create(Path::new(output_file)).context("Failed to create output file")?),
+    };
+    let sink: Box<dyn io::Write + Send + '_> = match &output {
+        Some(output) => Box::new(output.file()),
+        None => Box::new(io::stdout()),
+    };
 
-    // Write headers
-    writer
-        .write_record(headers.iter())
-        .context("Failed to write headers")?;
+    // Every header is read up front to reconcile them
+    let dialect = &options.input_dialect;
+    let mut readers = Vec::with_capacity(files.len());
+    let mut headers = Vec::with_capacity(files.len());
+    for file in files {
+        let source: Box<dyn io::Read> = match is_stdio(file) {
+            true => Box::new(io::stdin().lock()),
+            false => Box::new(
+                File::open(file).with_context(|| format!("Failed to open input file: {}", file.display()))?,
+            ),
+        };
+        let mut rdr = dialect.reader().from_reader(dialect.decode_reader(source));
+        headers.push((file.as_path(), dialect.read_headers(&mut rdr)?));
+        readers.push(rdr);
+    }
+    let (merged, projections) = reconcile_headers(&headers, &options.schema)?;
+    let (output_headers, select) = select_columns(&merged, &options.select)?;
+    let filter = options.filter.as_ref().map(|f| f.for_csv(&merged)).transpose()?;
+    let mut writer: Box<dyn RecordSink + '_> = match &options.columnar {
+        Some(columnar) => Box::new(ColumnarWriter::for_csv(&output_headers, columnar, io::BufWriter::new(sink))?),
+        None => {
+            let mut writer = options.output_dialect.writer().from_writer(io::BufWriter::new(sink));
+            if options.output_dialect.has_header {
+                writer.write_record(output_headers.iter()).context("Failed to write headers")?;
+            }
+            Box::new(writer)
+        }
+    };
 
     // Concatenate all files
-    for file in files.iter() {
-        let mut rdr = ReaderBuilder::new()
-            .has_headers(true) // Always skip header row automatically
-            .from_path(file)
-            .with_context(|| format!("Failed to open input file: {}", file.display()))?;
-
+    // Records are filtered on the merged header, before the selection
+    let mut projected = csv::StringRecord::new();
+    let mut selected = csv::StringRecord::new();
+    for (mut rdr, projection) in readers.into_iter().zip(projections) {
+        let identity = projection.is_identity();
         for result in rdr.records() {
             let record = result.context("Failed to read record")?;
+            let record = match identity {
+                true => &record,
+                false => {
+                    projection.apply(&record, &mut projected);
+                    &projected
+                }
+            };
+            if filter.as_ref().is_some_and(|f| !f.matches_record(record)) {
+                continue;
+            }
+            let record = match select.is_identity() {
+                true => record,
+                false => {
+                    select.apply(record, &mut selected);
+                    &selected
+                }
+            };
             writer
-                .write_record(&record)
+                .write_record(record)
                 .context("Failed to write record")?;
         }
+        options.cancel.check()?;
     }
 
-    writer.flush().context("Failed to flush writer")?;
+    writer.finish().context("Failed to finish the output")?;
+    drop(writer);
+    if let Some(output) = output {
+        output.commit(&options.output)?;
+    }
     Ok(())
 }
 
-/// Splits a CSV file into multiple smaller files with optional sorting
-fn split_csv_file(
+/// Runs `split` (`split_csv` or `split_mtlog`) on `input_file`, cancelling it on Ctrl-C.
+fn split_file(
     input_file: &str,
     output_dir: &str,
-    rows_per_file: usize,
-    sort_columns: &[&str],
+    options: RunOptions,
+    split: impl FnOnce(&Path, &Path, &RunOptions) -> Result<SplitManifest>,
 ) -> Result<()> {
-    info!(
-        "Splitting {} into chunks of {} rows",
-        input_file, rows_per_file
-    );
-
-    // Create the output directory
-    fs::create_dir_all(output_dir).context("Failed to create output directory")?;
-
-    // Create a temporary directory for sorting
-    let temp_dir = tempfile::tempdir().context("Failed to create temp directory")?;
-
-    // If sorting is needed, sort the file first
-    let sorted_file = if !sort_columns.is_empty() {
-        let sorted_path = temp_dir.path().join("sorted.csv");
-        external_sort(
-            Path::new(input_file),
-            &sorted_path,
-            sort_columns,
-            temp_dir.path(),
-        )?;
-        sorted_path
-    } else {
-        PathBuf::from(input_file)
-    };
-
-    // Open the input file
-    let file = File::open(&sorted_file).context("Failed to open input file")?;
-    let mut rdr = ReaderBuilder::new()
-        .has_headers(true)
-        .from_reader(io::BufReader::new(file));
-
-    // Get headers
-    let headers = rdr.headers()?.clone();
-
-    // Process the file in chunks
-    let mut chunk_num = 0;
-    let mut current_chunk: Vec<StringRecord> = Vec::with_capacity(rows_per_file);
-
-    for result in rdr.records() {
-        let record = result.context("Failed to read record")?;
-        current_chunk.push(record);
-
-        if current_chunk.len() >= rows_per_file {
-            write_chunk(
-                output_dir,
-                &headers,
-                &current_chunk,
-                Path::new(input_file)
-                    .file_stem()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("split"),
-                chunk_num,
-            )?;
-
-            chunk_num += 1;
-            current_chunk.clear();
-        }
-    }
-
-    // Write the last chunk if it's not empty
-    if !current_chunk.is_empty() {
-        write_chunk(
-            output_dir,
-            &headers,
-            &current_chunk,
-            Path::new(input_file)
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap_or("split"),
-            chunk_num,
-        )?;
-    }
-
-    info!("Split into {} files in {}", chunk_num + 1, output_dir);
+    info!("Splitting {} into {}", input_file, output_dir);
+    cancel_on_signal(options.cancel.clone())?;
+    let result = split(Path::new(input_file), Path::new(output_dir), &options);
+    exit_if_cancelled(&result);
+    let manifest = result?;
+    info!("Split into {} files in {}", manifest.files.len(), output_dir);
     Ok(())
 }
 
-/// Writes a chunk of records to a file
-fn write_chunk(
-    output_dir: &str,
-    headers: &StringRecord,
-    records: &[StringRecord],
-    base_name: &str,
-    chunk_num: usize,
-) -> Result<()> {
-    let output_path =
-        Path::new(output_dir).join(format!("{}_part_{:04}.csv", base_name, chunk_num));
-
-    let mut wtr = WriterBuilder::new()
-        .has_headers(true)
-        .from_path(&output_path)
-        .context("Failed to create output file")?;
-
-    // Write headers
-    wtr.write_record(headers.iter())
-        .context("Failed to write headers")?;
-
-    // Write records
-    for record in records {
-        wtr.write_record(record)?;
+/// Parses a size such as `2G`, `500M`, `64K` or `1000` (bytes); suffixes are powers of 1024.
+fn parse_byte_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let upper = size.to_ascii_uppercase();
+    let digits = upper.trim_end_matches('B').trim_end_matches('I');
+    let (number, unit) = match digits.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => digits.split_at(i),
+        None => (digits, ""),
+    };
+    let multiplier: u64 = match unit.trim() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => anyhow::bail!("Invalid size: {} (expected e.g. 2G, 500M, 64K or bytes)", size),
+    };
+    let number: u64 = number.parse().with_context(|| format!("Invalid size: {}", size))?;
+    if number == 0 {
+        anyhow::bail!("Size must be greater than zero: {}", size);
     }
-
-    wtr.flush()?;
-    Ok(())
+    number.checked_mul(multiplier).with_context(|| format!("Size too large: {}", size))
 }
 
-/// Sorts a CSV file using external sort
-fn external_sort(
-    input_path: &Path,
-    output_path: &Path,
-    sort_columns: &[&str],
-    _temp_dir: &Path,
-) -> Result<()> {
-    info!("Sorting {:?} by {:?}", input_path, sort_columns);
-
-    // Read headers
-    let file = File::open(input_path).context("Failed to open input file")?;
-    let mut rdr = ReaderBuilder::new()
-        .has_headers(true)
-        .from_reader(io::BufReader::new(file));
-
-    let headers = rdr.headers()?.clone();
-
-    // Get column indices for sorting
-    let column_indices: Vec<usize> = sort_columns
-        .iter()
-        .filter_map(|col| headers.iter().position(|h| h == *col))
-        .collect();
-
-    if column_indices.is_empty() {
-        return Err(anyhow::anyhow!("No valid sort columns found"));
-    }
-
-    // Read all records
-    let mut records: Vec<StringRecord> = rdr.records().collect::<Result<_, _>>()?;
-
-    // Sort records in parallel
-    records.par_sort_by(|a, b| {
-        for &idx in &column_indices {
-            let a_val = a.get(idx).unwrap_or("");
-            let b_val = b.get(idx).unwrap_or("");
-            let cmp = a_val.cmp(b_val);
-            if cmp != std::cmp::Ordering::Equal {
-                return cmp;
-            }
+/// Parses a CSV delimiter, quote, escape or comment character: one ASCII character,
+/// `tab`, or a byte in hex such as `\x1F`.
+fn parse_csv_byte(value: &str) -> Result<u8> {
+    let byte = match value {
+        "tab" | "\\t" => b'\t',
+        _ if value.len() == 1 => value.as_bytes()[0],
+        _ => {
+            let hex = value
+                .strip_prefix("\\x")
+                .or_else(|| value.strip_prefix("0x"))
+                .with_context(|| format!("Expected a single character, not {:?}", value))?;
+            u8::from_str_radix(hex, 16).with_context(|| format!("Invalid hex byte: {:?}", value))?
         }
-        std::cmp::Ordering::Equal
-    });
-
-    // Write sorted records to output
-    let mut wtr = WriterBuilder::new()
-        .has_headers(true)
-        .from_path(output_path)
-        .context("Failed to create output file")?;
-
-    wtr.write_record(headers.iter())?;
-    for record in records {
-        wtr.write_record(&record)?;
+    };
+    if !byte.is_ascii() {
+        anyhow::bail!("Expected an ASCII character, not {:?}", value);
     }
-
-    wtr.flush()?;
-
-    Ok(())
+    Ok(byte)
 }
 
-/// Merges multiple MT log files (fixed-width, no header) into one output file
-fn merge_mt_log_files(input_files: &[String], output_file: &str) -> Result<()> {
-    info!("Merging {} MT log files into {} (fixed-width mode)", input_files.len(), output_file);
-    let start_time = Instant::now();
-    let input_paths: Vec<PathBuf> = input_files.iter().map(PathBuf::from).collect();
-    let mut writer = BufWriter::new(File::create(output_file)?);
-    let mut total_lines = 0u64;
-    for path in &input_paths {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        for line in reader.lines() {
-            let line = line?;
-            writer.write_all(line.as_bytes())?;
-            writer.write_all(b"\n")?;
-            total_lines += 1;
-        }
-    }
-    writer.flush()?;
-    let elapsed = start_time.elapsed();
-    info!("✅ Merged {} MT log files ({} lines) into {} in {:.2?}", input_files.len(), total_lines, output_file, elapsed);
-    Ok(())
+/// Parses a record terminator: `lf`, `crlf` or a single character.
+fn parse_terminator(value: &str) -> Result<RecordTerminator> {
+    Ok(match value.to_ascii_lowercase().as_str() {
+        "lf" | "\\n" => RecordTerminator::Newline,
+        "crlf" | "\\r\\n" => RecordTerminator::Crlf,
+        _ => RecordTerminator::Byte(parse_csv_byte(value)?),
+    })
 }
 
 /// Parse mtlog sort columns from CLI (e.g. 0:date,1:time,5:num)
 fn parse_mtlog_sort_cols(cols: &[String]) -> Result<Vec<split_merge_hub_demo::parallel_merge::MTLogSortColumn>> {
+    use split_merge_hub_demo::mt_log::mt_log_record::FIELDS;
     use split_merge_hub_demo::parallel_merge::{MTLogSortColumn, MTLogSortType};
+    let parse_index = |index: &str| -> Result<usize> {
+        let idx = index.parse::<usize>().map_err(|_| anyhow::anyhow!("Invalid column index: {}", index))?;
+        if idx >= FIELDS.len() {
+            anyhow::bail!("MT log column index {} is out of range (0-{})", idx, FIELDS.len() - 1);
+        }
+        Ok(idx)
+    };
     let mut result = Vec::new();
     for col in cols {
         let parts: Vec<&str> = col.split(':').collect();
         if parts.len() == 2 {
-            let idx = parts[0].parse::<usize>().map_err(|_| anyhow::anyhow!("Invalid column index: {}", parts[0]))?;
+            let idx = parse_index(parts[0])?;
             let col_type = match parts[1].to_lowercase().as_str() {
                 "date" => MTLogSortType::Date,
                 "time" => MTLogSortType::Time,
@@ -384,7 +774,7 @@ fn parse_mtlog_sort_cols(cols: &[String]) -> Result<Vec<split_merge_hub_demo::pa
             };
             result.push(MTLogSortColumn { index: idx, col_type });
         } else if parts.len() == 1 {
-            let idx = parts[0].parse::<usize>().map_err(|_| anyhow::anyhow!("Invalid column index: {}", parts[0]))?;
+            let idx = parse_index(parts[0])?;
             result.push(MTLogSortColumn { index: idx, col_type: MTLogSortType::Str });
         } else {
             return Err(anyhow::anyhow!("Invalid sort column format: {}", col));
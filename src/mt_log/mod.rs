@@ -1 +1,2 @@
 pub mod mt_log_record;
+pub mod reader;
@@ -1,6 +1,9 @@
-use std::str::FromStr;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+/// A fixed-width MT log record. Serialized with its field names as keys; fields missing
+/// when deserializing are left blank (zero for numbers).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MTLogRecord {
     pub milog_rec_sys_date: u64,
     pub milog_rec_sys_time: String,
@@ -134,7 +137,178 @@ pub struct MTLogRecord {
     pub filler_log: String,
 }
 
-const TOTAL_LENGTH: usize = 4310;
+/// Length of a fixed-width MT log record, without its trailing newline.
+pub const TOTAL_LENGTH: usize = 4310;
+
+/// Version of the record layout above, recorded in output metadata; bump it whenever
+/// fields move or change width, so files in the old layout are not merged with new ones.
+pub const LAYOUT_VERSION: u32 = 1;
+
+/// Type of an [`MTLogRecord`] field, as parsed by [`MTLogRecord::parse_from_fixed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MTLogFieldType {
+    Str,
+    U64,
+    I64,
+}
+
+/// A field of the fixed-width layout: its [`MTLogRecord`] name, byte range and type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MTLogField {
+    pub name: &'static str,
+    pub start: usize,
+    pub end: usize,
+    pub field_type: MTLogFieldType,
+}
+
+/// Every field of the layout, in record order. Field `i` is also what MT log sort column `i` refers to.
+pub const FIELDS: &[MTLogField] = &[
+    MTLogField { name: "milog_rec_sys_date", start: 0, end: 8, field_type: MTLogFieldType::U64 },
+    MTLogField { name: "milog_rec_sys_time", start: 8, end: 14, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_rec_taskno", start: 14, end: 21, field_type: MTLogFieldType::U64 },
+    MTLogField { name: "milog_channel_code", start: 21, end: 25, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_rec_rectype", start: 25, end: 26, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_ts_ext_tran_code", start: 26, end: 34, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_tran_type", start: 34, end: 35, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_record_status", start: 35, end: 36, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_atm_cardnumber", start: 36, end: 52, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_terminal_id", start: 52, end: 68, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_terminal_recno", start: 68, end: 74, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_ts_teller_id", start: 74, end: 82, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_ts_tran_serno", start: 82, end: 88, field_type: MTLogFieldType::U64 },
+    MTLogField { name: "milog_ts_proc_date", start: 88, end: 96, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_eib_tranid", start: 96, end: 100, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_eib_termid", start: 100, end: 104, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_cics_applid", start: 104, end: 108, field_type: MTLogFieldType::Str },
+    MTLogField { name: "milog_next_day_flag", start: 108, end: 109, field_type: MTLogFieldType::Str },
+    MTLogField { name: "filler_r1", start: 109, end: 110, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_isc_cics_tran_code", start: 110, end: 114, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_isc_func_code", start: 114, end: 122, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_isc_front_end_login_id", start: 122, end: 130, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_isc_front_end_tran_serno", start: 130, end: 136, field_type: MTLogFieldType::U64 },
+    MTLogField { name: "mit_isc_reversal_flag", start: 136, end: 137, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_isc_tran_time", start: 137, end: 143, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_isc_tran_posting_date", start: 143, end: 151, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_isc_tran_branch_code", start: 151, end: 155, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_isc_channel_code", start: 155, end: 159, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_isc_front_end_term_id", start: 159, end: 175, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_isc_front_end_term_recno", start: 175, end: 181, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_isc_repeat_ind", start: 181, end: 182, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_mq_channel", start: 182, end: 186, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_mq_trans_id", start: 186, end: 190, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_mq_trans_desc", start: 190, end: 210, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_mq_rquid", start: 210, end: 246, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_acct1_acctnum", start: 246, end: 266, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_acct2_acctnum", start: 266, end: 286, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_acct3_acctnum", start: 286, end: 296, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_acct3_filler", start: 296, end: 304, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bank_cd", start: 304, end: 306, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_drcr_ind", start: 306, end: 307, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_financial_type", start: 307, end: 311, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_cheque_number", start: 311, end: 321, field_type: MTLogFieldType::U64 },
+    MTLogField { name: "mit_cheque_clrg_type", start: 321, end: 323, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_dr_tran_amount", start: 323, end: 338, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_dr_tran_ccy", start: 338, end: 341, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_dr_user_tran_code", start: 341, end: 345, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_dr_ats_company_id", start: 345, end: 351, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_dr_ats_desc", start: 351, end: 354, field_type: MTLogFieldType::Str },
+    MTLogField { name: "filler_r2", start: 354, end: 358, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_cr_tran_amount", start: 358, end: 373, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_cr_tran_ccy", start: 373, end: 376, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_cr_user_tran_code", start: 376, end: 380, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_cr_ats_company_id", start: 380, end: 386, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_cr_ats_desc", start: 386, end: 389, field_type: MTLogFieldType::Str },
+    MTLogField { name: "filler_r3", start: 389, end: 393, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_chg_tran_amount", start: 393, end: 408, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_chg_tran_ccy", start: 408, end: 411, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_chg_user_tran_code", start: 411, end: 415, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_chg_tran_desc", start: 415, end: 428, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fee_process_ind", start: 428, end: 430, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fee_type_01", start: 430, end: 434, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fee_amount_01", start: 434, end: 449, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_fee_type_02", start: 449, end: 453, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fee_amount_02", start: 453, end: 468, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_fee_type_03", start: 468, end: 472, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fee_amount_03", start: 472, end: 487, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_fee_type_04", start: 487, end: 491, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fee_amount_04", start: 491, end: 506, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_fee_type_05", start: 506, end: 510, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fee_amount_05", start: 510, end: 525, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_fee_type_06", start: 525, end: 529, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fee_amount_06", start: 529, end: 544, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_fee_type_07", start: 544, end: 548, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fee_amount_07", start: 548, end: 563, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_fee_type_08", start: 563, end: 567, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fee_amount_08", start: 567, end: 582, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_fee_type_09", start: 582, end: 586, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fee_amount_09", start: 586, end: 601, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_fee_type_10", start: 601, end: 605, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fee_amount_10", start: 605, end: 620, field_type: MTLogFieldType::I64 },
+    MTLogField { name: "mit_bpay_extra_flag", start: 620, end: 621, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_extra_data_1", start: 621, end: 641, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_extra_data_2", start: 641, end: 661, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_extra_data_3", start: 661, end: 681, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_value_date", start: 681, end: 689, field_type: MTLogFieldType::Str },
+    MTLogField { name: "filler_r4", start: 689, end: 704, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_stop_release_function", start: 704, end: 740, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_wthd_fx_dep_no", start: 740, end: 743, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_wthd_fx_reason", start: 743, end: 745, field_type: MTLogFieldType::Str },
+    MTLogField { name: "filler_r5", start: 745, end: 815, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_stmt_chn_desc_acct1", start: 815, end: 865, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_stmt_chn_desc_acct2", start: 865, end: 915, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_partner_acct", start: 915, end: 935, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_reconcile_ref", start: 935, end: 949, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_interbr_region", start: 949, end: 950, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_biller_postdate", start: 950, end: 956, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_charge_type", start: 956, end: 957, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_biller_code", start: 957, end: 974, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_tran_code_1", start: 974, end: 978, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_tran_code_2", start: 978, end: 982, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_tran_code_3", start: 982, end: 986, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_tran_code_4", start: 986, end: 990, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_udt_1", start: 990, end: 1050, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_udt_2", start: 1050, end: 1110, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_udt_3", start: 1110, end: 1170, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_total_ccy", start: 1170, end: 1173, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_ref3", start: 1173, end: 1193, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_send_bank", start: 1193, end: 1196, field_type: MTLogFieldType::Str },
+    MTLogField { name: "filler_r6", start: 1196, end: 1223, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fin_annotation_text", start: 1223, end: 1273, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_mcn_verify_flag", start: 1273, end: 1274, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_bpay_mcn_confirm_flag", start: 1274, end: 1275, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fin_accum_debit", start: 1275, end: 1276, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fin_accum_credit", start: 1276, end: 1277, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fin_accum_service_type", start: 1277, end: 1280, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fin_original_rquid", start: 1280, end: 1316, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_stmt_chn_desc_acct3", start: 1316, end: 1366, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_2nd_trans_amt", start: 1366, end: 1381, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_2nd_trans_amt_purposed", start: 1381, end: 1382, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_2nd_related_ref_no", start: 1382, end: 1398, field_type: MTLogFieldType::Str },
+    MTLogField { name: "filler_r7", start: 1398, end: 1427, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_cr_udt_1", start: 1427, end: 1487, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_cr_udt_2", start: 1487, end: 1547, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_cr_udt_3", start: 1547, end: 1607, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_fe_udt_1", start: 1607, end: 1667, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_fe_udt_2", start: 1667, end: 1727, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fcd_fe_udt_3", start: 1727, end: 1787, field_type: MTLogFieldType::Str },
+    MTLogField { name: "mit_fe_user_tran_code", start: 1787, end: 1791, field_type: MTLogFieldType::Str },
+    MTLogField { name: "filler_log", start: 1791, end: 4310, field_type: MTLogFieldType::Str },
+];
+
+impl MTLogField {
+    /// Looks a field up by its name (case-insensitive) or by its index in [`FIELDS`].
+    pub fn find(name_or_index: &str) -> Option<&'static MTLogField> {
+        match name_or_index.parse::<usize>() {
+            Ok(index) => FIELDS.get(index),
+            Err(_) => FIELDS.iter().find(|f| f.name.eq_ignore_ascii_case(name_or_index)),
+        }
+    }
+
+    /// The trimmed bytes of this field in `line` (empty if the line is too short).
+    pub fn bytes<'a>(&self, line: &'a [u8]) -> &'a [u8] {
+        line.get(self.start..self.end).unwrap_or(&[]).trim_ascii()
+    }
+}
 
 impl MTLogRecord {
     pub fn parse_from_fixed(input: &str) -> Result<Self, String> {
@@ -411,4 +585,27 @@ impl MTLogRecord {
         s.truncate(TOTAL_LENGTH);
         s
     }
+
+    /// Same as [`Self::to_fixed_string`], but fails instead of shifting or cutting fields:
+    /// every value must be ASCII and fit its field, and numbers must not be negative.
+    pub fn to_fixed_checked(&self) -> Result<String, String> {
+        let values = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        for field in FIELDS {
+            let text = match &values[field.name] {
+                serde_json::Value::String(s) => s.clone(),
+                value => value.to_string(),
+            };
+            let width = field.end - field.start;
+            if !text.is_ascii() {
+                return Err(format!("{} is not ASCII: {:?}", field.name, text));
+            }
+            if text.len() > width {
+                return Err(format!("{} does not fit in {} bytes: {:?}", field.name, width, text));
+            }
+            if field.field_type != MTLogFieldType::Str && text.starts_with('-') {
+                return Err(format!("{} is negative: {}", field.name, text));
+            }
+        }
+        Ok(self.to_fixed_string())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,207 @@
+// --- Byte-oriented MT log record reader ---
+
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Initial buffer size for streamed inputs; grows if a record does not fit.
+const STREAM_BUF_SIZE: usize = 8 * 1024 * 1024;
+
+/// Reads newline-terminated MT log records as byte slices, without allocating or
+/// validating UTF-8 per record.
+///
+/// Regular files are memory-mapped. Gzip-compressed files (detected by their magic
+/// bytes) and non-regular files such as pipes are read through a reusable `Vec<u8>`
+/// buffer instead.
+///
+/// ```rust,no_run
+/// use split_merge_hub_demo::mt_log::reader::MTLogReader;
+///
+/// let mut reader = MTLogReader::open("large_files/mt_log01".as_ref())?;
+/// while reader.next_record()? {
+///     let record: &[u8] = reader.record();
+///     println!("{}", record.len());
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct MTLogReader {
+    source: Source,
+    /// Current record within the mapped range or the stream buffer.
+    current: (usize, usize),
+    /// Byte offset of the next record from the start of the input.
+    position: u64,
+}
+
+enum Source {
+    Mapped {
+        map: Mmap,
+        next: usize,
+        end: usize,
+    },
+    Stream {
+        reader: Box<dyn Read + Send>,
+        buf: Vec<u8>,
+        start: usize,
+        filled: usize,
+        eof: bool,
+    },
+}
+
+impl MTLogReader {
+    /// Opens `path`, memory-mapping it when it is an uncompressed regular file.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path).with_context(|| format!("Failed to open MT log file: {}", path.display()))?;
+        if is_gzip(&mut file)? {
+            return Ok(Self::from_reader(MultiGzDecoder::new(file)));
+        }
+        if !file.metadata()?.is_file() {
+            return Ok(Self::from_reader(file));
+        }
+        let len = file.metadata()?.len();
+        Self::map(&file, 0, len)
+            .with_context(|| format!("Failed to memory-map MT log file: {}", path.display()))
+    }
+
+    /// Opens the records in bytes `start..end` of an uncompressed regular file.
+    ///
+    /// `start` must be a record boundary; the range ends at `end` (or at the end of the file).
+    pub fn open_range(path: &Path, start: u64, end: u64) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open MT log file: {}", path.display()))?;
+        Self::map(&file, start, end)
+            .with_context(|| format!("Failed to memory-map MT log file: {}", path.display()))
+    }
+
+    /// Reads records from any stream, e.g. stdin or a decompressor.
+    pub fn from_reader(reader: impl Read + Send + 'static) -> Self {
+        Self {
+            source: Source::Stream {
+                reader: Box::new(reader),
+                buf: vec![0; STREAM_BUF_SIZE],
+                start: 0,
+                filled: 0,
+                eof: false,
+            },
+            current: (0, 0),
+            position: 0,
+        }
+    }
+
+    fn map(file: &File, start: u64, end: u64) -> Result<Self> {
+        let len = file.metadata()?.len();
+        let end = end.min(len);
+        if len == 0 || start >= end {
+            // Empty files (and empty ranges) cannot be mapped
+            return Ok(Self::from_reader(std::io::empty()));
+        }
+        // SAFETY: chunk and output files are not modified while they are being read;
+        // a concurrent writer truncating an input is outside what this tool supports.
+        let map = unsafe { Mmap::map(file)? };
+        #[cfg(unix)]
+        {
+            let _ = map.advise(memmap2::Advice::Sequential);
+        }
+        Ok(Self {
+            source: Source::Mapped {
+                map,
+                next: start as usize,
+                end: end as usize,
+            },
+            current: (0, 0),
+            position: start,
+        })
+    }
+
+    /// Whether the input is memory-mapped (and so supports [`MTLogReader::open_range`]).
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.source, Source::Mapped { .. })
+    }
+
+    /// Byte offset of the next record from the start of the input.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Advances to the next record, returning `false` at the end of the input.
+    pub fn next_record(&mut self) -> Result<bool> {
+        match &mut self.source {
+            Source::Mapped { map, next, end } => {
+                if *next >= *end {
+                    return Ok(false);
+                }
+                let rest = &map[*next..*end];
+                let (len, consumed) = match memchr::memchr(b'\n', rest) {
+                    Some(i) => (i, i + 1),
+                    None => (rest.len(), rest.len()),
+                };
+                self.current = (*next, *next + len);
+                *next += consumed;
+                self.position += consumed as u64;
+                Ok(true)
+            }
+            Source::Stream { reader, buf, start, filled, eof } => loop {
+                if let Some(i) = memchr::memchr(b'\n', &buf[*start..*filled]) {
+                    self.current = (*start, *start + i);
+                    *start += i + 1;
+                    self.position += i as u64 + 1;
+                    return Ok(true);
+                }
+                if *eof {
+                    if *start == *filled {
+                        return Ok(false);
+                    }
+                    // Last record without a trailing newline
+                    self.current = (*start, *filled);
+                    self.position += (*filled - *start) as u64;
+                    *start = *filled;
+                    return Ok(true);
+                }
+                // Move the partial record to the front, growing the buffer if it is full
+                buf.copy_within(*start..*filled, 0);
+                *filled -= *start;
+                *start = 0;
+                if *filled == buf.len() {
+                    buf.resize(buf.len() * 2, 0);
+                }
+                let n = reader.read(&mut buf[*filled..])?;
+                if n == 0 {
+                    *eof = true;
+                }
+                *filled += n;
+            },
+        }
+    }
+
+    /// The current record, without its trailing newline.
+    pub fn record(&self) -> &[u8] {
+        let (start, end) = self.current;
+        match &self.source {
+            Source::Mapped { map, .. } => &map[start..end],
+            Source::Stream { buf, .. } => &buf[start..end],
+        }
+    }
+}
+
+/// Checks the gzip magic bytes and rewinds `file`.
+fn is_gzip(file: &mut File) -> Result<bool> {
+    use std::io::{Seek, SeekFrom};
+    if !file.metadata()?.is_file() {
+        return Ok(false);
+    }
+    let mut magic = [0u8; 2];
+    let n = file.read(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(n == 2 && magic == [0x1f, 0x8b])
+}
+
+/// Whether `path` can be memory-mapped and read by byte range (an uncompressed regular file).
+pub fn supports_ranges(path: &Path) -> bool {
+    // Checked before opening: opening a FIFO would take the data meant for its real read
+    if !std::fs::metadata(path).is_ok_and(|m| m.is_file()) {
+        return false;
+    }
+    let Ok(mut file) = File::open(path) else { return false };
+    !is_gzip(&mut file).unwrap_or(true)
+}
@@ -0,0 +1,54 @@
+// --- Cooperative cancellation ---
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// How many records the merge loops process between cancellation checks.
+pub(crate) const CANCEL_CHECK_INTERVAL: usize = 4096;
+
+/// Error returned by library functions when a run is stopped through a [`CancellationToken`].
+///
+/// Callers can tell a cancellation apart from a real failure with
+/// `err.downcast_ref::<Cancelled>().is_some()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "operation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// A cheaply clonable flag shared between the caller and a running merge.
+///
+/// The library checks it between chunks, at the start of every rayon task and
+/// every few thousand records inside the merge loops, then unwinds with [`Cancelled`].
+/// Temp chunk files live in temp directories that are removed on the way out.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation of every run holding a clone of this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns `Err(Cancelled)` if cancellation was requested.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.is_cancelled() {
+            Err(Cancelled.into())
+        } else {
+            Ok(())
+        }
+    }
+}
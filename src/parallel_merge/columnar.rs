@@ -0,0 +1,655 @@
+// --- Parquet and Arrow IPC output ---
+//
+// A columnar output is written while the final merge streams, like a CSV output: records
+// are buffered a row group at a time and written as one Arrow record batch. CSV columns
+// get the types inferred from the first row group (integers, decimals, booleans and ISO
+// dates, text otherwise), and a later value that does not fit its column's type fails the
+// run instead of being written as null. MT log fields are UInt64, Int64 or Utf8 by their
+// field type, blank numbers being zero as in `convert`.
+
+use anyhow::{Context, Result};
+use arrow_array::builder::{BooleanBuilder, Date32Builder, Float64Builder, Int64Builder, StringBuilder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::{FileWriter, IpcWriteOptions};
+use arrow_ipc::CompressionType;
+use arrow_schema::{DataType, Field, Schema as ArrowSchema, SchemaRef};
+use chrono::NaiveDate;
+use csv::StringRecord;
+use log::info;
+use num_format::{Locale, ToFormattedString};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, GzipLevel, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::mtlog::MTLogSink;
+use super::options::RunOptions;
+use crate::mt_log::mt_log_record::{MTLogField, MTLogFieldType, FIELDS, TOTAL_LENGTH};
+
+/// A columnar file format the final output can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnarFormat {
+    Parquet,
+    /// The Arrow IPC file format (Feather v2).
+    ArrowIpc,
+}
+
+impl std::str::FromStr for ColumnarFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format.trim().to_ascii_lowercase().as_str() {
+            "parquet" => Ok(Self::Parquet),
+            "arrow" | "ipc" | "arrow-ipc" | "feather" => Ok(Self::ArrowIpc),
+            _ => anyhow::bail!("Unknown columnar format {:?} (parquet or arrow)", format),
+        }
+    }
+}
+
+impl ColumnarFormat {
+    /// The format of `path` by its extension: `.parquet`, or `.arrow`, `.ipc` or `.feather`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("parquet") => Some(Self::Parquet),
+            Some("arrow" | "ipc" | "feather") => Some(Self::ArrowIpc),
+            _ => None,
+        }
+    }
+}
+
+/// The codec column data is compressed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnarCompression {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    Lz4,
+    Zstd,
+}
+
+impl std::str::FromStr for ColumnarCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(codec: &str) -> Result<Self> {
+        match codec.trim().to_ascii_lowercase().as_str() {
+            "none" | "uncompressed" => Ok(Self::Uncompressed),
+            "snappy" => Ok(Self::Snappy),
+            "gzip" => Ok(Self::Gzip),
+            "lz4" => Ok(Self::Lz4),
+            "zstd" => Ok(Self::Zstd),
+            _ => anyhow::bail!("Unknown compression {:?} (none, snappy, gzip, lz4 or zstd)", codec),
+        }
+    }
+}
+
+/// How a Parquet or Arrow IPC output is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColumnarOptions {
+    pub format: ColumnarFormat,
+    /// Rows per Parquet row group, or per Arrow IPC record batch. CSV column types are
+    /// inferred from the first one.
+    pub row_group_rows: usize,
+    /// Codec of the column data [default: snappy for Parquet, none for Arrow IPC].
+    /// Arrow IPC only supports lz4 and zstd.
+    pub compression: Option<ColumnarCompression>,
+    /// Dictionary-encode Parquet columns. Arrow IPC columns are never dictionary-encoded.
+    pub dictionary: bool,
+    /// Infer the types of CSV columns; otherwise every column is written as text.
+    pub infer_types: bool,
+}
+
+impl ColumnarOptions {
+    pub const DEFAULT_ROW_GROUP_ROWS: usize = 128 * 1024;
+
+    pub fn new(format: ColumnarFormat) -> Self {
+        Self {
+            format,
+            row_group_rows: Self::DEFAULT_ROW_GROUP_ROWS,
+            compression: None,
+            dictionary: true,
+            infer_types: true,
+        }
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.row_group_rows == 0 {
+            anyhow::bail!("The row group size must be greater than zero");
+        }
+        self.ipc_compression()?;
+        Ok(())
+    }
+
+    fn parquet_properties(&self) -> WriterProperties {
+        let compression = match self.compression.unwrap_or(ColumnarCompression::Snappy) {
+            ColumnarCompression::Uncompressed => Compression::UNCOMPRESSED,
+            ColumnarCompression::Snappy => Compression::SNAPPY,
+            ColumnarCompression::Gzip => Compression::GZIP(GzipLevel::default()),
+            ColumnarCompression::Lz4 => Compression::LZ4_RAW,
+            ColumnarCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+        };
+        WriterProperties::builder()
+            .set_max_row_group_size(self.row_group_rows)
+            .set_dictionary_enabled(self.dictionary)
+            .set_compression(compression)
+            .build()
+    }
+
+    fn ipc_compression(&self) -> Result<Option<CompressionType>> {
+        if self.format != ColumnarFormat::ArrowIpc {
+            return Ok(None);
+        }
+        match self.compression {
+            None | Some(ColumnarCompression::Uncompressed) => Ok(None),
+            Some(ColumnarCompression::Lz4) => Ok(Some(CompressionType::LZ4_FRAME)),
+            Some(ColumnarCompression::Zstd) => Ok(Some(CompressionType::ZSTD)),
+            Some(codec) => anyhow::bail!("Arrow IPC output supports lz4 and zstd compression, not {:?}", codec),
+        }
+    }
+}
+
+/// Fails if `options` ask for a columnar output along with something only CSV and MT log outputs have.
+pub(crate) fn check_columnar(options: &RunOptions) -> Result<()> {
+    let Some(columnar) = &options.columnar else { return Ok(()) };
+    columnar.check()?;
+    if options.output.partitioned {
+        anyhow::bail!("Partitioned output is written as CSV or MT log files, not {:?}", columnar.format);
+    }
+    Ok(())
+}
+
+/// Receives the records of a final CSV merge: a CSV writer, or a [`ColumnarWriter`].
+pub trait RecordSink {
+    fn write_record(&mut self, record: &StringRecord) -> Result<()>;
+
+    /// Passes the records written so far on to the underlying writer, if the sink writes
+    /// record by record; sinks that write whole batches keep buffering.
+    fn flush(&mut self) -> Result<()>;
+
+    /// Writes out whatever is buffered. No records may be written after it.
+    fn finish(&mut self) -> Result<()>;
+}
+
+impl<W: Write> RecordSink for csv::Writer<W> {
+    fn write_record(&mut self, record: &StringRecord) -> Result<()> {
+        Ok(csv::Writer::write_record(self, record)?)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(csv::Writer::flush(self)?)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(csv::Writer::flush(self)?)
+    }
+}
+
+/// The type of a column of a columnar output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnType {
+    Utf8,
+    Int64,
+    UInt64,
+    Float64,
+    Boolean,
+    Date32,
+}
+
+impl ColumnType {
+    fn data_type(self) -> DataType {
+        match self {
+            Self::Utf8 => DataType::Utf8,
+            Self::Int64 => DataType::Int64,
+            Self::UInt64 => DataType::UInt64,
+            Self::Float64 => DataType::Float64,
+            Self::Boolean => DataType::Boolean,
+            Self::Date32 => DataType::Date32,
+        }
+    }
+
+    /// Whether a non-blank CSV `value` can be written to a column of this type.
+    fn fits(self, value: &[u8]) -> bool {
+        let Ok(text) = std::str::from_utf8(value) else { return self == Self::Utf8 };
+        match self {
+            Self::Utf8 => true,
+            Self::Int64 => parse_int::<i64>(text, false).is_some(),
+            Self::UInt64 => parse_int::<u64>(text, false).is_some(),
+            Self::Float64 => parse_float(text).is_some(),
+            Self::Boolean => parse_bool(text).is_some(),
+            Self::Date32 => parse_date(text).is_some(),
+        }
+    }
+
+    /// The narrowest type every non-blank value fits, text if there are none.
+    fn infer<'a>(values: impl Iterator<Item = &'a [u8]>) -> Self {
+        const CANDIDATES: [ColumnType; 4] = [ColumnType::Int64, ColumnType::Float64, ColumnType::Boolean, ColumnType::Date32];
+        let mut fits = [true; 4];
+        let mut seen = false;
+        for value in values.filter(|v| !v.is_empty()) {
+            seen = true;
+            for (fit, candidate) in fits.iter_mut().zip(CANDIDATES) {
+                *fit = *fit && candidate.fits(value);
+            }
+            if !fits.contains(&true) {
+                return Self::Utf8;
+            }
+        }
+        match seen {
+            true => CANDIDATES.into_iter().zip(fits).find(|(_, fit)| *fit).map_or(Self::Utf8, |(t, _)| t),
+            false => Self::Utf8,
+        }
+    }
+}
+
+enum ColumnBuilder {
+    Utf8(StringBuilder),
+    Int64(Int64Builder),
+    UInt64(UInt64Builder),
+    Float64(Float64Builder),
+    Boolean(BooleanBuilder),
+    Date32(Date32Builder),
+}
+
+impl ColumnBuilder {
+    fn new(column_type: ColumnType, capacity: usize) -> Self {
+        match column_type {
+            ColumnType::Utf8 => Self::Utf8(StringBuilder::with_capacity(capacity, capacity * 8)),
+            ColumnType::Int64 => Self::Int64(Int64Builder::with_capacity(capacity)),
+            ColumnType::UInt64 => Self::UInt64(UInt64Builder::with_capacity(capacity)),
+            ColumnType::Float64 => Self::Float64(Float64Builder::with_capacity(capacity)),
+            ColumnType::Boolean => Self::Boolean(BooleanBuilder::with_capacity(capacity)),
+            ColumnType::Date32 => Self::Date32(Date32Builder::with_capacity(capacity)),
+        }
+    }
+
+    /// Appends `value`, returning `false` if it does not fit the column's type. A blank
+    /// value is null, or zero for a `fixed_width` (MT log) number.
+    fn append(&mut self, value: &[u8], fixed_width: bool) -> bool {
+        if let Self::Utf8(builder) = self {
+            builder.append_value(String::from_utf8_lossy(value));
+            return true;
+        }
+        let Ok(text) = std::str::from_utf8(value) else { return false };
+        if text.is_empty() {
+            match (self, fixed_width) {
+                (Self::Int64(builder), true) => builder.append_value(0),
+                (Self::UInt64(builder), true) => builder.append_value(0),
+                (Self::Int64(b), _) => b.append_null(),
+                (Self::UInt64(b), _) => b.append_null(),
+                (Self::Float64(b), _) => b.append_null(),
+                (Self::Boolean(b), _) => b.append_null(),
+                (Self::Date32(b), _) => b.append_null(),
+                (Self::Utf8(_), _) => unreachable!(),
+            }
+            return true;
+        }
+        let appended = match self {
+            Self::Int64(builder) => parse_int(text, fixed_width).map(|n| builder.append_value(n)),
+            Self::UInt64(builder) => parse_int(text, fixed_width).map(|n| builder.append_value(n)),
+            Self::Float64(builder) => parse_float(text).map(|n| builder.append_value(n)),
+            Self::Boolean(builder) => parse_bool(text).map(|b| builder.append_value(b)),
+            Self::Date32(builder) => parse_date(text).map(|d| builder.append_value(d)),
+            Self::Utf8(_) => unreachable!(),
+        };
+        appended.is_some()
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::Utf8(builder) => Arc::new(builder.finish()),
+            Self::Int64(builder) => Arc::new(builder.finish()),
+            Self::UInt64(builder) => Arc::new(builder.finish()),
+            Self::Float64(builder) => Arc::new(builder.finish()),
+            Self::Boolean(builder) => Arc::new(builder.finish()),
+            Self::Date32(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+/// An integer; zero-padded text such as `007` is an identifier rather than a number,
+/// except in a `fixed_width` (MT log) field.
+fn parse_int<T: std::str::FromStr>(text: &str, fixed_width: bool) -> Option<T> {
+    text.parse().ok().filter(|_| fixed_width || !has_leading_zero(text))
+}
+
+fn parse_float(text: &str) -> Option<f64> {
+    let number = text.bytes().any(|b| b.is_ascii_digit()) && !has_leading_zero(text);
+    text.parse().ok().filter(|_| number)
+}
+
+fn parse_bool(text: &str) -> Option<bool> {
+    match text.to_ascii_lowercase().as_str() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Days since 1970-01-01 of a `YYYY-MM-DD` date.
+fn parse_date(text: &str) -> Option<i32> {
+    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+    Some((date - NaiveDate::from_ymd_opt(1970, 1, 1)?).num_days() as i32)
+}
+
+fn has_leading_zero(text: &str) -> bool {
+    let digits = text.trim_start_matches(['+', '-']);
+    digits.len() > 1 && digits.starts_with('0') && !digits[1..].starts_with('.')
+}
+
+/// MT log fields written as columns.
+#[derive(Clone)]
+pub(crate) struct MTLogColumns {
+    fields: Vec<&'static MTLogField>,
+    schema: SchemaRef,
+}
+
+impl MTLogColumns {
+    /// Every field, under its own name.
+    pub(crate) fn all() -> Self {
+        let names: StringRecord = FIELDS.iter().map(|f| f.name).collect();
+        Self::new(&names, FIELDS.iter().collect())
+    }
+
+    /// `fields`, under `names`.
+    pub(crate) fn new(names: &StringRecord, fields: Vec<&'static MTLogField>) -> Self {
+        let schema = names
+            .iter()
+            .zip(&fields)
+            .map(|(name, field)| Field::new(name, Self::column_type(field).data_type(), false))
+            .collect::<Vec<_>>();
+        Self { fields, schema: Arc::new(ArrowSchema::new(schema)) }
+    }
+
+    fn column_type(field: &MTLogField) -> ColumnType {
+        match field.field_type {
+            MTLogFieldType::Str => ColumnType::Utf8,
+            MTLogFieldType::U64 => ColumnType::UInt64,
+            MTLogFieldType::I64 => ColumnType::Int64,
+        }
+    }
+
+    /// A record batch of whole MT log `records`, named by `locate(i)` in errors.
+    pub(crate) fn batch(&self, records: &[&[u8]], locate: impl Fn(usize) -> String) -> Result<RecordBatch> {
+        if let Some(i) = records.iter().position(|r| r.len() != TOTAL_LENGTH) {
+            anyhow::bail!("Cannot write {}: it is {} bytes long, expected {}", locate(i), records[i].len(), TOTAL_LENGTH);
+        }
+        let columns = self
+            .fields
+            .iter()
+            .map(|field| {
+                let mut builder = ColumnBuilder::new(Self::column_type(field), records.len());
+                for (i, record) in records.iter().enumerate() {
+                    let value = field.bytes(record);
+                    if !builder.append(value, true) {
+                        anyhow::bail!(
+                            "Cannot write {}: {} is not a number: {:?}",
+                            locate(i),
+                            field.name,
+                            String::from_utf8_lossy(value)
+                        );
+                    }
+                }
+                Ok(builder.finish())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(RecordBatch::try_new(self.schema.clone(), columns)?)
+    }
+}
+
+enum Columns {
+    /// CSV columns, typed once the first row group has been inferred (from `inferred_from` records).
+    Csv { names: Vec<String>, types: Option<Vec<ColumnType>>, inferred_from: usize },
+    MtLog(MTLogColumns),
+}
+
+enum WriterState<W: Write + Send> {
+    /// Not opened yet, as CSV columns are only typed with the first row group.
+    Pending(W),
+    Parquet(ArrowWriter<W>),
+    /// Batches not yet written are held back until they add up to a row group.
+    Ipc(FileWriter<W>, Vec<RecordBatch>),
+    Finished,
+}
+
+/// Writes records as Parquet or Arrow IPC to a sink, a row group at a time.
+///
+/// [`ColumnarWriter::finish`] must be called to write the file footer; an unfinished
+/// output is not a valid file.
+pub struct ColumnarWriter<W: Write + Send> {
+    options: ColumnarOptions,
+    columns: Columns,
+    /// Fields (CSV) or whole records (MT log) of the buffered rows; value `i` ends at `ends[i]`.
+    data: Vec<u8>,
+    ends: Vec<usize>,
+    rows: usize,
+    written: usize,
+    state: WriterState<W>,
+}
+
+impl<W: Write + Send> ColumnarWriter<W> {
+    /// A writer of CSV records with the columns of `headers`.
+    pub fn for_csv(headers: &StringRecord, options: &ColumnarOptions, sink: W) -> Result<Self> {
+        let types = (!options.infer_types).then(|| vec![ColumnType::Utf8; headers.len()]);
+        let names = headers.iter().map(str::to_string).collect();
+        Self::new(Columns::Csv { names, types, inferred_from: 0 }, options, sink)
+    }
+
+    /// A writer of MT log records, one column per field of `columns`.
+    pub(crate) fn for_mtlog(columns: MTLogColumns, options: &ColumnarOptions, sink: W) -> Result<Self> {
+        Self::new(Columns::MtLog(columns), options, sink)
+    }
+
+    fn new(columns: Columns, options: &ColumnarOptions, sink: W) -> Result<Self> {
+        options.check()?;
+        Ok(Self {
+            options: *options,
+            columns,
+            data: Vec::new(),
+            ends: Vec::new(),
+            rows: 0,
+            written: 0,
+            state: WriterState::Pending(sink),
+        })
+    }
+
+    /// Writes a batch built by the caller, e.g. from [`MTLogColumns::batch`].
+    pub(crate) fn write_batch(&mut self, batch: RecordBatch) -> Result<()> {
+        if let WriterState::Pending(_) = self.state {
+            self.open(batch.schema())?;
+        }
+        match &mut self.state {
+            WriterState::Parquet(writer) => writer.write(&batch)?,
+            WriterState::Ipc(writer, pending) => {
+                pending.push(batch);
+                write_ipc_batches(writer, pending, self.options.row_group_rows, false)?;
+            }
+            _ => anyhow::bail!("Columnar output written after it was finished"),
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered rows and the file footer, and flushes the sink.
+    pub fn finish(&mut self) -> Result<()> {
+        self.flush_rows()?;
+        if let WriterState::Pending(_) = self.state {
+            self.open(self.schema())?;
+        }
+        match std::mem::replace(&mut self.state, WriterState::Finished) {
+            WriterState::Parquet(mut writer) => {
+                writer.finish().context("Failed to finish the Parquet output")?;
+                writer.inner_mut().flush()?;
+            }
+            WriterState::Ipc(mut writer, mut pending) => {
+                write_ipc_batches(&mut writer, &mut pending, self.options.row_group_rows, true)?;
+                writer.finish().context("Failed to finish the Arrow IPC output")?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn push_row(&mut self) -> Result<()> {
+        self.rows += 1;
+        if self.rows >= self.options.row_group_rows {
+            self.flush_rows()?;
+        }
+        Ok(())
+    }
+
+    fn flush_rows(&mut self) -> Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let batch = self.buffered_batch()?;
+        self.written += self.rows;
+        self.rows = 0;
+        self.data.clear();
+        self.ends.clear();
+        self.write_batch(batch)
+    }
+
+    fn buffered_batch(&mut self) -> Result<RecordBatch> {
+        let (data, ends, rows, written) = (&self.data, &self.ends, self.rows, self.written);
+        let value = |i: usize| &data[if i == 0 { 0 } else { ends[i - 1] }..ends[i]];
+        match &mut self.columns {
+            Columns::Csv { names, types, inferred_from } => {
+                let width = names.len();
+                let types = types.get_or_insert_with(|| {
+                    *inferred_from = rows;
+                    (0..width).map(|c| ColumnType::infer((0..rows).map(|r| value(r * width + c)))).collect()
+                });
+                let mut columns = Vec::with_capacity(width);
+                for (c, column_type) in types.iter().enumerate() {
+                    let mut builder = ColumnBuilder::new(*column_type, rows);
+                    for r in 0..rows {
+                        let field = value(r * width + c);
+                        if !builder.append(field, false) {
+                            anyhow::bail!(
+                                "Column '{}' was inferred as {} from the first {} records, but record {} has {:?}; \
+                                 turn off type inference to write every column as text",
+                                names[c],
+                                column_type.data_type(),
+                                fmtnum(*inferred_from),
+                                fmtnum(written + r + 1),
+                                String::from_utf8_lossy(field)
+                            );
+                        }
+                    }
+                    columns.push(builder.finish());
+                }
+                Ok(RecordBatch::try_new(csv_schema(names, types), columns)?)
+            }
+            Columns::MtLog(columns) => {
+                let records: Vec<&[u8]> = (0..rows).map(value).collect();
+                columns.batch(&records, |i| format!("merged record {}", fmtnum(written + i + 1)))
+            }
+        }
+    }
+
+    fn open(&mut self, schema: SchemaRef) -> Result<()> {
+        let WriterState::Pending(sink) = std::mem::replace(&mut self.state, WriterState::Finished) else {
+            return Ok(());
+        };
+        let columns: Vec<String> = schema.fields().iter().map(|f| format!("{}: {}", f.name(), f.data_type())).collect();
+        info!("[columnar] Writing {:?} with columns {}", self.options.format, columns.join(", "));
+        self.state = match self.options.format {
+            ColumnarFormat::Parquet => {
+                let writer = ArrowWriter::try_new(sink, schema, Some(self.options.parquet_properties()))
+                    .context("Failed to start the Parquet output")?;
+                WriterState::Parquet(writer)
+            }
+            ColumnarFormat::ArrowIpc => {
+                let ipc_options = IpcWriteOptions::default().try_with_compression(self.options.ipc_compression()?)?;
+                let writer = FileWriter::try_new_with_options(sink, &schema, ipc_options)
+                    .context("Failed to start the Arrow IPC output")?;
+                WriterState::Ipc(writer, Vec::new())
+            }
+        };
+        Ok(())
+    }
+
+    fn schema(&self) -> SchemaRef {
+        match &self.columns {
+            Columns::Csv { names, types, .. } => match types {
+                Some(types) => csv_schema(names, types),
+                None => csv_schema(names, &vec![ColumnType::Utf8; names.len()]),
+            },
+            Columns::MtLog(columns) => columns.schema.clone(),
+        }
+    }
+}
+
+fn csv_schema(names: &[String], types: &[ColumnType]) -> SchemaRef {
+    let fields: Vec<Field> = names
+        .iter()
+        .zip(types)
+        .map(|(name, column_type)| Field::new(name, column_type.data_type(), *column_type != ColumnType::Utf8))
+        .collect();
+    Arc::new(ArrowSchema::new(fields))
+}
+
+/// Writes `pending` batches as record batches of `rows`, keeping back a shorter remainder
+/// unless this is the `last` call.
+fn write_ipc_batches<W: Write>(writer: &mut FileWriter<W>, pending: &mut Vec<RecordBatch>, rows: usize, last: bool) -> Result<()> {
+    let buffered: usize = pending.iter().map(RecordBatch::num_rows).sum();
+    if buffered == 0 || (buffered < rows && !last) {
+        return Ok(());
+    }
+    let batch = arrow_select::concat::concat_batches(&pending[0].schema(), pending.iter())?;
+    pending.clear();
+    let mut start = 0;
+    while batch.num_rows() - start >= rows {
+        writer.write(&batch.slice(start, rows))?;
+        start += rows;
+    }
+    if start < batch.num_rows() {
+        let rest = batch.slice(start, batch.num_rows() - start);
+        match last {
+            true => writer.write(&rest)?,
+            false => pending.push(rest),
+        }
+    }
+    Ok(())
+}
+
+impl<W: Write + Send> RecordSink for ColumnarWriter<W> {
+    fn write_record(&mut self, record: &StringRecord) -> Result<()> {
+        if let Columns::Csv { names, .. } = &self.columns {
+            if record.len() != names.len() {
+                anyhow::bail!(
+                    "Record {} has {} fields, expected {}",
+                    fmtnum(self.written + self.rows + 1),
+                    record.len(),
+                    names.len()
+                );
+            }
+        }
+        for field in record.iter() {
+            self.data.extend_from_slice(field.as_bytes());
+            self.ends.push(self.data.len());
+        }
+        self.push_row()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        // Rows are only written as whole row groups or batches
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        ColumnarWriter::finish(self)
+    }
+}
+
+impl<W: Write + Send> MTLogSink for ColumnarWriter<W> {
+    fn write_mtlog(&mut self, record: &[u8]) -> Result<()> {
+        self.data.extend_from_slice(record);
+        self.ends.push(self.data.len());
+        self.push_row()
+    }
+}
+
+fn fmtnum(n: usize) -> String {
+    n.to_formatted_string(&Locale::en)
+}
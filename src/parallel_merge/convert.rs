@@ -0,0 +1,395 @@
+// --- Converting MT log files to and from CSV and JSON Lines ---
+//
+// Exports read every field's trimmed bytes straight from the fixed-width record and write
+// them as CSV, under a header of field names, or as JSON Lines with numbers for the
+// numeric fields. Imports deserialize an `MTLogRecord` from every CSV record or JSON line
+// and format it with `to_fixed_checked`, so a value that does not fit its field fails the
+// conversion instead of shifting the fields after it. Inputs are read a block of whole
+// records at a time; each batch of blocks is converted on rayon workers and written in
+// input order. Parquet and Arrow IPC exports build one record batch per block instead.
+
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use log::info;
+use num_format::{Locale, ToFormattedString};
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use super::columnar::{ColumnarFormat, ColumnarOptions, ColumnarWriter, MTLogColumns};
+use super::csv_stream::CsvStream;
+use super::dialect::CsvDialect;
+use super::options::RunOptions;
+use super::output::{is_stdio, Target};
+use crate::mt_log::mt_log_record::{MTLogField, MTLogFieldType, MTLogRecord, FIELDS, TOTAL_LENGTH};
+use crate::mt_log::reader::MTLogReader;
+
+/// Bytes of input records converted together on one worker.
+const BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// A file format `convert` reads or writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertFormat {
+    /// Fixed-width MT log records, one per line.
+    MtLog,
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+    /// Written only, like Arrow IPC.
+    Parquet,
+    ArrowIpc,
+}
+
+impl std::str::FromStr for ConvertFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format.trim().to_ascii_lowercase().as_str() {
+            "mtlog" | "mt-log" => Ok(Self::MtLog),
+            "csv" => Ok(Self::Csv),
+            "jsonl" | "ndjson" => Ok(Self::Jsonl),
+            _ => match format.parse::<ColumnarFormat>() {
+                Ok(columnar) => Ok(columnar.into()),
+                Err(_) => anyhow::bail!("Unknown format {:?} (mtlog, csv, jsonl, parquet or arrow)", format),
+            },
+        }
+    }
+}
+
+impl ConvertFormat {
+    /// The format of `path` by its extension: `.csv`, `.jsonl` or `.ndjson`, `.parquet`,
+    /// `.arrow`, and an MT log otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        if let Some(columnar) = ColumnarFormat::from_path(path) {
+            return columnar.into();
+        }
+        match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("csv") => Self::Csv,
+            Some("jsonl" | "ndjson") => Self::Jsonl,
+            _ => Self::MtLog,
+        }
+    }
+
+    fn columnar(self) -> Option<ColumnarFormat> {
+        match self {
+            Self::Parquet => Some(ColumnarFormat::Parquet),
+            Self::ArrowIpc => Some(ColumnarFormat::ArrowIpc),
+            _ => None,
+        }
+    }
+}
+
+impl From<ColumnarFormat> for ConvertFormat {
+    fn from(format: ColumnarFormat) -> Self {
+        match format {
+            ColumnarFormat::Parquet => Self::Parquet,
+            ColumnarFormat::ArrowIpc => Self::ArrowIpc,
+        }
+    }
+}
+
+/// Converts `input` (`-` for stdin) from `from` to `to`, writing `output` (`-` for stdout).
+/// One side must be an MT log. CSV is read in `options.input_dialect` and written in
+/// `options.output_dialect`; CSV columns are matched to MT log fields by name, or by
+/// position without a header. Parquet and Arrow IPC are only written, with the settings
+/// of `options.columnar` if given.
+pub fn convert_file(
+    input: &Path,
+    output: &Path,
+    from: ConvertFormat,
+    to: ConvertFormat,
+    options: &RunOptions,
+) -> Result<()> {
+    if (from == ConvertFormat::MtLog) == (to == ConvertFormat::MtLog) {
+        anyhow::bail!("Either the input or the output must be an MT log, not {:?} to {:?}", from, to);
+    }
+    let timer = Instant::now();
+    let target = Target::Path(output);
+    target.check(&options.output)?;
+    let mut source = BlockSource::open(input, from, &options.input_dialect)?;
+    let headers = match &source {
+        BlockSource::Csv(stream) => csv_import_headers(stream.headers(), &options.input_dialect)?,
+        _ => None,
+    };
+
+    let out = target.open(&options.output)?;
+    let mut writer = BufWriter::with_capacity(BLOCK_SIZE, &out);
+    let records = if let Some(format) = to.columnar() {
+        let columnar = ColumnarOptions { format, ..options.columnar.unwrap_or(ColumnarOptions::new(format)) };
+        let columns = MTLogColumns::all();
+        let mut writer = ColumnarWriter::for_mtlog(columns.clone(), &columnar, writer)?;
+        let convert = |block: &[u8], offset: u64| {
+            let lines: Vec<(usize, &[u8])> = lines(block).collect();
+            let records: Vec<&[u8]> = lines.iter().map(|(_, line)| *line).collect();
+            let batch = columns.batch(&records, |i| format!("the MT log record at byte {}", offset + lines[i].0 as u64))?;
+            Ok((batch, records.len()))
+        };
+        let records = convert_blocks(&mut source, options, convert, |batch| writer.write_batch(batch))?;
+        writer.finish()?;
+        records
+    } else {
+        let output_dialect = &options.output_dialect;
+        if to == ConvertFormat::Csv && output_dialect.has_header {
+            let mut wtr = output_dialect.writer().from_writer(&mut writer);
+            wtr.write_record(FIELDS.iter().map(|f| f.name))?;
+            wtr.flush()?;
+        }
+        let convert = |block: &[u8], offset: u64| match (from, to) {
+            (ConvertFormat::MtLog, ConvertFormat::Csv) => mtlog_to_csv(block, offset, output_dialect),
+            (ConvertFormat::MtLog, _) => mtlog_to_jsonl(block, offset),
+            (ConvertFormat::Csv, _) => csv_to_mtlog(block, offset, headers.as_ref(), &options.input_dialect),
+            (_, _) => jsonl_to_mtlog(block, offset),
+        };
+        let records = convert_blocks(&mut source, options, convert, |data| Ok(writer.write_all(&data)?))?;
+        writer.flush()?;
+        drop(writer);
+        records
+    };
+    out.commit(&options.output, None)?;
+    info!(
+        "[convert] Converted {} records from {:?} to {:?} -> {:?} in {:.2?}",
+        records.to_formatted_string(&Locale::en),
+        from,
+        to,
+        output,
+        timer.elapsed()
+    );
+    Ok(())
+}
+
+/// Reads `source` in batches of blocks that are converted on rayon workers, then passed
+/// to `write` in input order. Returns the number of records converted.
+fn convert_blocks<T: Send>(
+    source: &mut BlockSource,
+    options: &RunOptions,
+    convert: impl Fn(&[u8], u64) -> Result<(T, usize)> + Sync,
+    mut write: impl FnMut(T) -> Result<()>,
+) -> Result<usize> {
+    let batch = rayon::current_num_threads().max(1);
+    let mut blocks: Vec<(Vec<u8>, u64)> = Vec::with_capacity(batch);
+    let mut offset = 0u64;
+    let mut records = 0usize;
+    loop {
+        options.cancel.check()?;
+        blocks.clear();
+        while blocks.len() < batch {
+            let mut block = Vec::new();
+            if !source.next_block(&mut block)? {
+                break;
+            }
+            let len = block.len() as u64;
+            blocks.push((block, offset));
+            offset += len;
+        }
+        if blocks.is_empty() {
+            break;
+        }
+        let converted = blocks.par_iter().map(|(block, offset)| convert(block, *offset)).collect::<Result<Vec<_>>>()?;
+        for (data, count) in converted {
+            write(data)?;
+            records += count;
+        }
+    }
+    Ok(records)
+}
+
+/// An input read as blocks of whole records.
+enum BlockSource {
+    MtLog(MTLogReader),
+    Csv(CsvStream),
+    Lines(Box<dyn BufRead + Send>),
+}
+
+impl BlockSource {
+    fn open(input: &Path, format: ConvertFormat, dialect: &CsvDialect) -> Result<Self> {
+        Ok(match format {
+            ConvertFormat::MtLog if is_stdio(input) => Self::MtLog(MTLogReader::from_reader(std::io::stdin())),
+            ConvertFormat::MtLog => Self::MtLog(MTLogReader::open(input)?),
+            ConvertFormat::Csv => Self::Csv(CsvStream::open(input, dialect)?),
+            ConvertFormat::Parquet | ConvertFormat::ArrowIpc => anyhow::bail!("{:?} can only be written, not read", format),
+            ConvertFormat::Jsonl => {
+                let reader: Box<dyn Read + Send> = match is_stdio(input) {
+                    true => Box::new(std::io::stdin()),
+                    false => Box::new(
+                        File::open(input).with_context(|| format!("Failed to open input file: {}", input.display()))?,
+                    ),
+                };
+                Self::Lines(Box::new(BufReader::with_capacity(BLOCK_SIZE, reader)))
+            }
+        })
+    }
+
+    /// Replaces `block` with about [`BLOCK_SIZE`] bytes of records, each ending in a
+    /// newline for MT logs and JSON Lines. Returns `false` at the end of the input.
+    fn next_block(&mut self, block: &mut Vec<u8>) -> Result<bool> {
+        block.clear();
+        match self {
+            Self::MtLog(reader) => {
+                while block.len() < BLOCK_SIZE && reader.next_record()? {
+                    block.extend_from_slice(reader.record());
+                    block.push(b'\n');
+                }
+            }
+            Self::Csv(stream) => return stream.next_block(BLOCK_SIZE, block),
+            Self::Lines(reader) => {
+                while block.len() < BLOCK_SIZE && reader.read_until(b'\n', block)? > 0 {}
+            }
+        }
+        Ok(!block.is_empty())
+    }
+}
+
+/// The header to deserialize CSV records with: the MT log field names of its columns, or `None`
+/// to take the fields by position.
+fn csv_import_headers(headers: &StringRecord, dialect: &CsvDialect) -> Result<Option<StringRecord>> {
+    if !dialect.has_header {
+        return Ok(None);
+    }
+    let names = headers
+        .iter()
+        .map(|name| {
+            let field = MTLogField::find(name.trim())
+                .filter(|f| f.name.eq_ignore_ascii_case(name.trim()))
+                .with_context(|| format!("CSV column '{}' is not an MT log field", name))?;
+            Ok(field.name)
+        })
+        .collect::<Result<StringRecord>>()?;
+    Ok(Some(names))
+}
+
+/// The lines of `block` with their offsets in it, without line terminators.
+fn lines(block: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    let mut pos = 0;
+    block.split_inclusive(|&b| b == b'\n').map(move |line| {
+        let start = pos;
+        pos += line.len();
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        (start, line.strip_suffix(b"\r").unwrap_or(line))
+    })
+}
+
+enum FieldValue<'a> {
+    Str(&'a [u8]),
+    U64(u64),
+    I64(i64),
+}
+
+/// The fields of an MT log `line` at byte `offset` of the input, numbers parsed (blank is zero).
+fn field_values(line: &[u8], offset: u64) -> Result<impl Iterator<Item = (&'static MTLogField, FieldValue<'_>)>> {
+    if line.len() != TOTAL_LENGTH {
+        anyhow::bail!("MT log record at byte {} is {} bytes long, expected {}", offset, line.len(), TOTAL_LENGTH);
+    }
+    let values = FIELDS
+        .iter()
+        .map(|field| {
+            let bytes = field.bytes(line);
+            let value = match field.field_type {
+                MTLogFieldType::Str => FieldValue::Str(bytes),
+                MTLogFieldType::U64 => FieldValue::U64(parse_number(field, bytes, offset)?),
+                MTLogFieldType::I64 => FieldValue::I64(parse_number(field, bytes, offset)?),
+            };
+            Ok((field, value))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(values.into_iter())
+}
+
+fn parse_number<T: std::str::FromStr>(field: &MTLogField, bytes: &[u8], offset: u64) -> Result<T> {
+    let text = std::str::from_utf8(bytes).ok().filter(|t| !t.is_empty()).unwrap_or("0");
+    text.parse().ok().with_context(|| {
+        format!("{} of the MT log record at byte {} is not a number: {:?}", field.name, offset, text)
+    })
+}
+
+fn mtlog_to_csv(block: &[u8], offset: u64, dialect: &CsvDialect) -> Result<(Vec<u8>, usize)> {
+    let mut wtr = dialect.writer().from_writer(Vec::with_capacity(block.len()));
+    let mut count = 0;
+    for (pos, line) in lines(block) {
+        for (_, value) in field_values(line, offset + pos as u64)? {
+            match value {
+                FieldValue::Str(bytes) => wtr.write_field(bytes)?,
+                FieldValue::U64(n) => wtr.write_field(n.to_string())?,
+                FieldValue::I64(n) => wtr.write_field(n.to_string())?,
+            }
+        }
+        wtr.write_record(None::<&[u8]>)?;
+        count += 1;
+    }
+    Ok((wtr.into_inner().map_err(|e| e.into_error())?, count))
+}
+
+fn mtlog_to_jsonl(block: &[u8], offset: u64) -> Result<(Vec<u8>, usize)> {
+    let mut out = Vec::with_capacity(block.len());
+    let mut count = 0;
+    for (pos, line) in lines(block) {
+        out.push(b'{');
+        for (i, (field, value)) in field_values(line, offset + pos as u64)?.enumerate() {
+            if i > 0 {
+                out.push(b',');
+            }
+            serde_json::to_writer(&mut out, field.name)?;
+            out.push(b':');
+            match value {
+                FieldValue::Str(bytes) => serde_json::to_writer(&mut out, &String::from_utf8_lossy(bytes))?,
+                FieldValue::U64(n) => write!(out, "{}", n)?,
+                FieldValue::I64(n) => write!(out, "{}", n)?,
+            }
+        }
+        out.extend_from_slice(b"}\n");
+        count += 1;
+    }
+    Ok((out, count))
+}
+
+fn csv_to_mtlog(
+    block: &[u8],
+    offset: u64,
+    headers: Option<&StringRecord>,
+    dialect: &CsvDialect,
+) -> Result<(Vec<u8>, usize)> {
+    let data = dialect.decode(block);
+    let mut rdr = dialect.reader().has_headers(false).from_reader(&data[..]);
+    let mut out = Vec::with_capacity(block.len());
+    let mut count = 0;
+    for result in rdr.records() {
+        let mut record = result.with_context(|| format!("Invalid CSV record in the block at byte {}", offset))?;
+        let at = offset + record.position().map_or(0, |p| p.byte());
+        record.trim();
+        let parsed: MTLogRecord =
+            record.deserialize(headers).map_err(|e| anyhow::anyhow!("CSV record at byte {}: {}", at, e))?;
+        push_fixed(&mut out, &parsed, at)?;
+        count += 1;
+    }
+    Ok((out, count))
+}
+
+fn jsonl_to_mtlog(block: &[u8], offset: u64) -> Result<(Vec<u8>, usize)> {
+    let mut out = Vec::with_capacity(block.len());
+    let mut count = 0;
+    for (pos, line) in lines(block) {
+        let at = offset + pos as u64;
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        let object: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(line).map_err(|e| anyhow::anyhow!("JSON line at byte {}: {}", at, e))?;
+        if let Some(key) = object.keys().find(|key| !FIELDS.iter().any(|f| f.name == *key)) {
+            anyhow::bail!("JSON line at byte {}: '{}' is not an MT log field", at, key);
+        }
+        let parsed: MTLogRecord = serde_json::from_value(serde_json::Value::Object(object))
+            .map_err(|e| anyhow::anyhow!("JSON line at byte {}: {}", at, e))?;
+        push_fixed(&mut out, &parsed, at)?;
+        count += 1;
+    }
+    Ok((out, count))
+}
+
+/// Appends `record` as a fixed-width line, failing on a value that does not fit.
+fn push_fixed(out: &mut Vec<u8>, record: &MTLogRecord, at: u64) -> Result<()> {
+    let fixed = record.to_fixed_checked().map_err(|e| anyhow::anyhow!("Record at byte {}: {}", at, e))?;
+    out.extend_from_slice(fixed.as_bytes());
+    out.push(b'\n');
+    Ok(())
+}
@@ -0,0 +1,418 @@
+// --- Byte-comparable CSV sort keys ---
+
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use log::{debug, info, warn};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::Arc;
+
+use super::dialect::CsvDialect;
+use super::output::sidecar_path;
+use super::partition::open_range;
+
+/// How a CSV sort column is interpreted when building its key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvSortType {
+    /// Integers (`i64`) in numeric order, followed by everything else as text.
+    Auto,
+    /// Numbers (`f64`, so decimals too) in numeric order, followed by non-numbers as text.
+    Num,
+    /// Plain text, byte-wise.
+    Str,
+}
+
+/// A resolved sort column: header index, type and direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvSortColumn {
+    pub index: usize,
+    pub col_type: CsvSortType,
+    pub descending: bool,
+}
+
+/// Splits a sort column spec `name[:auto|num|str][:asc|desc]` into its parts.
+///
+/// Unknown suffixes are kept as part of the name, so header names containing `:` still work.
+pub fn parse_sort_spec(spec: &str) -> (&str, CsvSortType, bool) {
+    let mut name = spec.trim();
+    let mut descending = false;
+    let mut col_type = CsvSortType::Auto;
+    if let Some((rest, dir)) = name.rsplit_once(':') {
+        match dir.trim().to_ascii_lowercase().as_str() {
+            "desc" => (name, descending) = (rest, true),
+            "asc" => name = rest,
+            _ => {}
+        }
+    }
+    if let Some((rest, ty)) = name.rsplit_once(':') {
+        match ty.trim().to_ascii_lowercase().as_str() {
+            "auto" => name = rest,
+            "num" => (name, col_type) = (rest, CsvSortType::Num),
+            "str" => (name, col_type) = (rest, CsvSortType::Str),
+            _ => {}
+        }
+    }
+    (name.trim(), col_type, descending)
+}
+
+/// Resolves sort column specs against `headers` (names are matched case-insensitively).
+pub(crate) fn resolve_sort_columns(headers: &StringRecord, sort_columns: &[&str]) -> Vec<CsvSortColumn> {
+    let mut columns = Vec::new();
+    let header_vec: Vec<&str> = headers.iter().collect();
+    info!("Available columns in CSV: {:?}", header_vec);
+    info!("Requested sort columns: {:?}", sort_columns);
+    for spec in sort_columns {
+        let (col_trimmed, col_type, descending) = parse_sort_spec(spec);
+        match headers
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(col_trimmed))
+        {
+            Some(index) => {
+                info!(
+                    "Sorting by column: '{}' (index {}, {:?}{})",
+                    col_trimmed,
+                    index,
+                    col_type,
+                    if descending { ", descending" } else { "" }
+                );
+                columns.push(CsvSortColumn { index, col_type, descending });
+            }
+            None => {
+                warn!(
+                    "Warning: Sort column '{}' not found in headers",
+                    col_trimmed
+                );
+                let similar: Vec<&str> = headers
+                    .iter()
+                    .filter(|h| {
+                        h.trim()
+                            .to_lowercase()
+                            .contains(&col_trimmed.to_lowercase())
+                    })
+                    .collect();
+                if !similar.is_empty() {
+                    warn!("  Did you mean one of these? {:?}", similar);
+                }
+            }
+        }
+    }
+    if columns.is_empty() {
+        warn!(
+            "No valid sort columns found. Available columns: {:?}",
+            header_vec
+        );
+    }
+    columns
+}
+
+/// Normalized, memcmp-able sort key for a CSV record.
+///
+/// Comparing two keys byte-wise orders the records by `columns`. Numbers are encoded as
+/// fixed-width big-endian values behind a `0x01` tag, text behind a `0x02` tag (plain
+/// `Str` columns have no tag) with `0x00` escaped as `0x00 0xFF` and a `0x00 0x00`
+/// terminator. Descending columns have their bytes inverted.
+pub fn csv_sort_key(record: &StringRecord, columns: &[CsvSortColumn]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(columns.len() * 12);
+    encode_sort_key(record, columns, &mut key);
+    key
+}
+
+/// Appends the key for `record` to `key`.
+pub(crate) fn encode_sort_key(record: &StringRecord, columns: &[CsvSortColumn], key: &mut Vec<u8>) {
+    for col in columns {
+        let start = key.len();
+        let value = record.get(col.index).unwrap_or("");
+        match col.col_type {
+            CsvSortType::Auto => match value.parse::<i64>() {
+                Ok(n) => {
+                    key.push(0x01);
+                    key.extend_from_slice(&((n as u64) ^ (1 << 63)).to_be_bytes());
+                }
+                Err(_) => {
+                    key.push(0x02);
+                    encode_text(value.as_bytes(), key);
+                }
+            },
+            CsvSortType::Num => match value.trim().parse::<f64>() {
+                Ok(n) if !n.is_nan() => {
+                    // -0.0 and 0.0 compare equal
+                    let bits = if n == 0.0 { 0.0f64.to_bits() } else { n.to_bits() };
+                    let ordered = if bits >> 63 == 1 { !bits } else { bits | (1 << 63) };
+                    key.push(0x01);
+                    key.extend_from_slice(&ordered.to_be_bytes());
+                }
+                _ => {
+                    key.push(0x02);
+                    encode_text(value.as_bytes(), key);
+                }
+            },
+            CsvSortType::Str => encode_text(value.as_bytes(), key),
+        }
+        if col.descending {
+            for b in &mut key[start..] {
+                *b = !*b;
+            }
+        }
+    }
+}
+
+fn encode_text(value: &[u8], key: &mut Vec<u8>) {
+    for &b in value {
+        key.push(b);
+        if b == 0 {
+            key.push(0xFF);
+        }
+    }
+    key.extend_from_slice(&[0, 0]);
+}
+
+// --- Keys sidecar ---
+//
+// Sorted chunk files get a `<chunk>.keys` sidecar holding the key of every data record,
+// in record order, as `u32` little-endian length + key bytes. It starts with a header
+// naming the sort columns, so a sidecar built for a different sort is ignored.
+//
+// Every `INDEX_INTERVAL` records, a `<chunk>.keys.idx` entry records the byte offset of
+// the next record in the chunk and of its key in the sidecar (two `u64` little-endian),
+// so the partitioned merge can binary-search a chunk instead of reading it.
+
+const KEYS_MAGIC: &[u8] = b"SMHKEYS1";
+
+const INDEX_INTERVAL: u64 = 1024;
+
+pub(crate) fn keys_path(chunk: &Path) -> PathBuf {
+    sidecar_path(chunk, "keys")
+}
+
+fn index_path(chunk: &Path) -> PathBuf {
+    sidecar_path(chunk, "keys.idx")
+}
+
+fn keys_header(columns: &[CsvSortColumn]) -> Vec<u8> {
+    let spec = format!("{:?}", columns);
+    let mut header = KEYS_MAGIC.to_vec();
+    header.extend_from_slice(&(spec.len() as u32).to_le_bytes());
+    header.extend_from_slice(spec.as_bytes());
+    header
+}
+
+/// Writes the keys sidecar and index of a sorted chunk.
+///
+/// The chunk must be written through [`KeyWriter::counting`], so the index knows its offsets.
+pub(crate) struct KeyWriter {
+    writer: BufWriter<File>,
+    index: BufWriter<File>,
+    chunk_offset: Arc<AtomicU64>,
+    key_offset: u64,
+    records: u64,
+}
+
+impl KeyWriter {
+    pub(crate) fn create(chunk: &Path, columns: &[CsvSortColumn]) -> Result<Self> {
+        let path = keys_path(chunk);
+        let file = File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut writer = BufWriter::with_capacity(1024 * 1024, file);
+        let header = keys_header(columns);
+        writer.write_all(&header)?;
+        let path = index_path(chunk);
+        let index = File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(Self {
+            writer,
+            index: BufWriter::new(index),
+            chunk_offset: Arc::new(AtomicU64::new(0)),
+            key_offset: header.len() as u64,
+            records: 0,
+        })
+    }
+
+    /// Wraps the chunk's writer so the bytes written to the chunk are counted.
+    pub(crate) fn counting<W: Write>(&self, inner: W) -> CountingWriter<W> {
+        CountingWriter {
+            inner,
+            written: Arc::clone(&self.chunk_offset),
+        }
+    }
+
+    /// Writes the key of the record just written to the chunk. Every `INDEX_INTERVAL`
+    /// records, `flush` must push the chunk writer's buffered bytes through
+    /// [`KeyWriter::counting`], so the offset of the next record can be indexed.
+    pub(crate) fn write(&mut self, key: &[u8], flush: impl FnOnce() -> Result<()>) -> Result<()> {
+        self.writer.write_all(&(key.len() as u32).to_le_bytes())?;
+        self.writer.write_all(key)?;
+        self.key_offset += 4 + key.len() as u64;
+        self.records += 1;
+        if self.records.is_multiple_of(INDEX_INTERVAL) {
+            flush()?;
+            self.index.write_all(&self.chunk_offset.load(AtomicOrdering::Relaxed).to_le_bytes())?;
+            self.index.write_all(&self.key_offset.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.index.flush()?;
+        Ok(())
+    }
+}
+
+/// A writer that counts the bytes written through it, for [`KeyWriter`].
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    written: Arc<AtomicU64>,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written.fetch_add(n as u64, AtomicOrdering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Opens the keys sidecar of `chunk` if it exists and was built for `columns`.
+///
+/// Returns the reader positioned at the first key and the offset of that key.
+pub(crate) fn open_keys(chunk: &Path, columns: &[CsvSortColumn], buf_size: usize) -> Result<Option<(BufReader<File>, u64)>> {
+    let path = keys_path(chunk);
+    let Ok(file) = File::open(&path) else { return Ok(None) };
+    let expected = keys_header(columns);
+    let mut reader = BufReader::with_capacity(buf_size, file);
+    let mut header = vec![0u8; expected.len()];
+    if reader.read_exact(&mut header).is_err() || header != expected {
+        debug!("[merge] Ignoring keys sidecar {} built for another sort", path.display());
+        return Ok(None);
+    }
+    Ok(Some((reader, expected.len() as u64)))
+}
+
+/// A CSV reader that yields each record with its sort key, read from a keys sidecar
+/// when there is one and computed from the record otherwise.
+pub(crate) struct KeyedCsvReader<'a, R: Read, K: Read> {
+    rdr: csv::Reader<R>,
+    keys: Option<K>,
+    columns: &'a [CsvSortColumn],
+    key_offset: u64,
+}
+
+impl<'a, R: Read, K: Read> KeyedCsvReader<'a, R, K> {
+    /// `keys`, if any, must be positioned at the key of the next record, at `key_offset`.
+    pub(crate) fn new(rdr: csv::Reader<R>, keys: Option<K>, key_offset: u64, columns: &'a [CsvSortColumn]) -> Self {
+        Self { rdr, keys, columns, key_offset }
+    }
+
+    /// Offset in the keys sidecar of the next record's key.
+    pub(crate) fn key_offset(&self) -> u64 {
+        self.key_offset
+    }
+
+    /// Reads the next record into `record` and its key into `key`.
+    pub(crate) fn read(&mut self, record: &mut StringRecord, key: &mut Vec<u8>) -> Result<bool> {
+        if !self.rdr.read_record(record)? {
+            return Ok(false);
+        }
+        key.clear();
+        match &mut self.keys {
+            Some(keys) => {
+                let mut len = [0u8; 4];
+                keys.read_exact(&mut len).context("Keys sidecar is shorter than its chunk")?;
+                let len = u32::from_le_bytes(len) as usize;
+                key.resize(len, 0);
+                keys.read_exact(key).context("Keys sidecar is shorter than its chunk")?;
+                self.key_offset += 4 + len as u64;
+            }
+            None => encode_sort_key(record, self.columns, key),
+        }
+        Ok(true)
+    }
+}
+
+/// Reads the keys index of `chunk`: `(record offset, key offset)` of every
+/// `INDEX_INTERVAL`-th record. Only valid if the chunk's keys sidecar is.
+pub(crate) fn read_keys_index(chunk: &Path) -> Result<Option<Vec<(u64, u64)>>> {
+    let Ok(bytes) = std::fs::read(index_path(chunk)) else { return Ok(None) };
+    let entries = bytes
+        .chunks_exact(16)
+        .map(|entry| {
+            let record = u64::from_le_bytes(entry[..8].try_into().unwrap());
+            let key = u64::from_le_bytes(entry[8..].try_into().unwrap());
+            (record, key)
+        })
+        .collect();
+    Ok(Some(entries))
+}
+
+/// Finds where every key range starts in a sorted chunk with a keys index.
+///
+/// Each splitter is binary-searched over the indexed keys, then at most `INDEX_INTERVAL`
+/// records are read from the entry before it. `start` and `end` are the `(chunk, sidecar)`
+/// offsets of the first record and of the end; returns the same bounds as a `BoundaryScan`.
+pub(crate) fn search_keyed_bounds(
+    chunk: &Path,
+    index: &[(u64, u64)],
+    start: (u64, u64),
+    end: (u64, u64),
+    splitters: &[Vec<u8>],
+    columns: &[CsvSortColumn],
+    dialect: &CsvDialect,
+) -> Result<Vec<(u64, u64)>> {
+    let path = keys_path(chunk);
+    let mut keys = File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+    // A chunk whose record count is a multiple of the interval has a last entry at its end
+    let index = &index[..index.partition_point(|&(_, key_offset)| key_offset < end.1)];
+    let mut key = Vec::new();
+    let mut bounds = vec![start];
+    let mut lo = 0;
+    for splitter in splitters {
+        // Entries before the previous bound are not after this splitter either
+        let mut hi = index.len();
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            read_key_at(&mut keys, index[mid].1, &mut key)?;
+            if key <= *splitter {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        let previous = *bounds.last().unwrap();
+        let from = match lo {
+            0 => previous,
+            _ => index[lo - 1].max(previous),
+        };
+        let rdr = dialect
+            .reader()
+            .has_headers(false)
+            .from_reader(open_range(chunk, from.0, end.0, 64 * 1024)?);
+        let sidecar = open_range(&path, from.1, end.1, 64 * 1024)?;
+        let mut rdr = KeyedCsvReader::new(rdr, Some(sidecar), from.1, columns);
+        let mut record = StringRecord::new();
+        let bound = loop {
+            let key_offset = rdr.key_offset();
+            if !rdr.read(&mut record, &mut key)? {
+                break end;
+            }
+            if key > *splitter {
+                break (from.0 + record.position().map_or(0, |p| p.byte()), key_offset);
+            }
+        };
+        bounds.push(bound);
+    }
+    bounds.push(end);
+    Ok(bounds)
+}
+
+fn read_key_at(keys: &mut File, offset: u64, key: &mut Vec<u8>) -> Result<()> {
+    keys.seek(SeekFrom::Start(offset))?;
+    let mut len = [0u8; 4];
+    keys.read_exact(&mut len).context("Keys index points past its sidecar")?;
+    key.resize(u32::from_le_bytes(len) as usize, 0);
+    keys.read_exact(key).context("Keys index points past its sidecar")?;
+    Ok(())
+}
@@ -0,0 +1,109 @@
+// --- CSV inputs read as streams ---
+
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use std::io::Read;
+use std::path::Path;
+
+use super::dialect::CsvDialect;
+use super::output::is_stdio;
+use super::ranges::{last_record_end, CsvSyntax};
+
+/// Bytes read from the stream at a time.
+const READ_SIZE: usize = 1024 * 1024;
+
+/// A CSV input that can only be read once, front to back (stdin, a pipe, ...).
+///
+/// The header is parsed when the stream is opened; the data is then handed out in
+/// blocks that end at a record boundary, so every block parses on its own. Blocks are
+/// handed out as read, in the stream's encoding.
+pub(crate) struct CsvStream {
+    reader: Box<dyn Read + Send>,
+    buf: Vec<u8>,
+    eof: bool,
+    headers: StringRecord,
+    bytes_read: u64,
+    syntax: CsvSyntax,
+}
+
+impl CsvStream {
+    /// Opens stdin for `-`, or the file at `path` otherwise.
+    pub(crate) fn open(path: &Path, dialect: &CsvDialect) -> Result<Self> {
+        if is_stdio(path) {
+            return Self::new(Box::new(std::io::stdin()), dialect);
+        }
+        let file = std::fs::File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+        Self::new(Box::new(file), dialect)
+    }
+
+    /// Reads up to the end of the header record (or peeks at the first record of a
+    /// stream without a header, to name its columns).
+    pub(crate) fn new(reader: Box<dyn Read + Send>, dialect: &CsvDialect) -> Result<Self> {
+        let syntax = dialect.syntax();
+        let mut stream =
+            Self { reader, buf: Vec::new(), eof: false, headers: StringRecord::new(), bytes_read: 0, syntax };
+        let header_end = loop {
+            if let Some(end) = syntax.first_record_end(&stream.buf) {
+                break end;
+            }
+            if stream.eof {
+                break stream.buf.len();
+            }
+            stream.fill(stream.buf.len() + READ_SIZE)?;
+        };
+        let header = dialect.decode(&stream.buf[..header_end]);
+        stream.headers = dialect.read_headers(&mut dialect.reader().from_reader(&header[..]))?;
+        if dialect.has_header {
+            stream.buf.drain(..header_end);
+        }
+        Ok(stream)
+    }
+
+    pub(crate) fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    /// Bytes read from the stream so far.
+    pub(crate) fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Replaces `block` with the next records, about `target` bytes of them (more if a
+    /// single record is larger). Returns `false` at the end of the stream.
+    pub(crate) fn next_block(&mut self, target: usize, block: &mut Vec<u8>) -> Result<bool> {
+        let mut searched = 0;
+        let end = loop {
+            if self.buf.len() >= target.max(1) {
+                // Quote parity is only known from the start of the buffer, so rescan it all
+                if let Some(end) = last_record_end(&self.buf, &self.syntax) {
+                    break end;
+                }
+                searched = self.buf.len();
+            }
+            if self.eof {
+                break self.buf.len();
+            }
+            self.fill(target.max(searched + READ_SIZE))?;
+        };
+        if end == 0 {
+            return Ok(false);
+        }
+        std::mem::swap(block, &mut self.buf);
+        self.buf.clear();
+        self.buf.extend_from_slice(&block[end..]);
+        block.truncate(end);
+        Ok(true)
+    }
+
+    /// Reads until the buffer holds `target` bytes or the stream ends.
+    fn fill(&mut self, target: usize) -> Result<()> {
+        if self.buf.len() < target && !self.eof {
+            let wanted = target - self.buf.len();
+            self.buf.reserve(wanted);
+            let n = (&mut self.reader).take(wanted as u64).read_to_end(&mut self.buf)?;
+            self.bytes_read += n as u64;
+            self.eof = n < wanted;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+// --- CSV dialects ---
+//
+// How CSV files are read and written: delimiter, quoting, record terminator, comment
+// lines, whether there is a header row, and the text encoding of inputs. Inputs are
+// parsed in the input dialect and decoded to UTF-8 block by block, so record boundaries
+// are still found in the raw bytes; chunk and intermediate merge files use the default
+// dialect, and the final output is written in the output dialect.
+
+use anyhow::Result;
+use csv::{ReaderBuilder, StringRecord, Terminator, WriterBuilder};
+use encoding_rs::{CoderResult, Decoder, Encoding, UTF_8};
+use std::borrow::Cow;
+use std::io::Read;
+
+use super::ranges::CsvSyntax;
+
+/// Bytes read from the source at a time by [`CsvDialect::decode_reader`].
+const DECODE_BUF_SIZE: usize = 64 * 1024;
+
+/// A CSV dialect.
+///
+/// The default is RFC 4180 with a header row: `,` delimited, `"` quoted (doubled inside
+/// quoted fields), records ending in `\n` or `\r\n`, UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    /// Escapes quotes inside quoted fields (e.g. `\"`) instead of doubling them.
+    pub escape: Option<u8>,
+    pub terminator: RecordTerminator,
+    /// Records starting with this byte are skipped when reading.
+    pub comment: Option<u8>,
+    /// Whether the first record is a header. Without one, columns are named by
+    /// position (`1`, `2`, ...) for sort keys and partition columns.
+    pub has_header: bool,
+    /// Encoding of an input, decoded to UTF-8 when read. Outputs are always UTF-8.
+    pub encoding: &'static Encoding,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            terminator: RecordTerminator::Newline,
+            comment: None,
+            has_header: true,
+            encoding: UTF_8,
+        }
+    }
+}
+
+/// Fails unless `encoding` is UTF-8 or single-byte. Record boundaries are found in the raw
+/// bytes, and multibyte encodings such as Shift_JIS, GBK or Big5 have trail bytes that can
+/// be `\` or `|`, so a range could start in the middle of a character.
+fn check_encoding(encoding: &'static Encoding) -> Result<()> {
+    if encoding != UTF_8 && !encoding.is_single_byte() {
+        anyhow::bail!(
+            "Encoding {} is not supported: inputs must be UTF-8 or a single-byte encoding such as TIS-620",
+            encoding.name()
+        );
+    }
+    Ok(())
+}
+
+/// How CSV records end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordTerminator {
+    /// `\n` or `\r\n` when reading, `\n` when writing.
+    #[default]
+    Newline,
+    /// `\n` or `\r\n` when reading, `\r\n` when writing.
+    Crlf,
+    /// A single byte, e.g. `;` or `\x1E`.
+    Byte(u8),
+}
+
+impl CsvDialect {
+    /// The encoding for a WHATWG label such as `tis-620`, `windows-874` or `latin1`; only
+    /// UTF-8 and single-byte encodings are accepted.
+    pub fn encoding_for_label(label: &str) -> Result<&'static Encoding> {
+        let encoding = Encoding::for_label(label.trim().as_bytes())
+            .ok_or_else(|| anyhow::anyhow!("Unknown encoding: {}", label))?;
+        check_encoding(encoding)?;
+        Ok(encoding)
+    }
+
+    /// Fails if the dialect cannot be read or written unambiguously.
+    pub fn check(&self) -> Result<()> {
+        let terminator = match self.terminator {
+            RecordTerminator::Byte(b) => Some(b),
+            _ => None,
+        };
+        let special = [Some(self.delimiter), Some(self.quote), self.escape, terminator, self.comment];
+        let special: Vec<u8> = special.into_iter().flatten().collect();
+        if let Some(b) = special.iter().find(|b| !b.is_ascii()) {
+            anyhow::bail!("CSV delimiters, quotes and terminators must be ASCII, not 0x{:02X}", b);
+        }
+        for (i, b) in special.iter().enumerate() {
+            if special[..i].contains(b) || (terminator.is_none() && (*b == b'\n' || *b == b'\r')) {
+                anyhow::bail!("{:?} is used for more than one thing in the CSV dialect", *b as char);
+            }
+        }
+        check_encoding(self.encoding)
+    }
+
+    /// The dialect of a file written in this dialect, as read back: no comments, UTF-8.
+    pub(crate) fn as_written(&self) -> Self {
+        Self { comment: None, encoding: UTF_8, ..*self }
+    }
+
+    /// A reader for this dialect; it skips the header row if there is one.
+    pub fn reader(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .comment(self.comment)
+            .has_headers(self.has_header);
+        if let Some(escape) = self.escape {
+            builder.escape(Some(escape)).double_quote(false);
+        }
+        if let RecordTerminator::Byte(b) = self.terminator {
+            builder.terminator(Terminator::Any(b));
+        }
+        builder
+    }
+
+    /// A writer for this dialect. It never writes a header by itself: callers write
+    /// the header record if `has_header` is set.
+    pub fn writer(&self) -> WriterBuilder {
+        let mut builder = WriterBuilder::new();
+        builder.delimiter(self.delimiter).quote(self.quote).has_headers(false);
+        if let Some(escape) = self.escape {
+            builder.escape(escape).double_quote(false);
+        }
+        match self.terminator {
+            RecordTerminator::Newline => {}
+            RecordTerminator::Crlf => {
+                builder.terminator(Terminator::CRLF);
+            }
+            RecordTerminator::Byte(b) => {
+                builder.terminator(Terminator::Any(b));
+            }
+        }
+        builder
+    }
+
+    /// Reads the header of `rdr`, a reader from [`Self::reader`]. Without a header row,
+    /// the first record only gives the number of columns and is still read as data.
+    pub fn read_headers<R: Read>(&self, rdr: &mut csv::Reader<R>) -> Result<StringRecord> {
+        let first = rdr.headers()?;
+        Ok(match self.has_header {
+            true => first.clone(),
+            false => positional_headers(first.len()),
+        })
+    }
+
+    /// The bytes that delimit records, for finding record boundaries without parsing.
+    pub(crate) fn syntax(&self) -> CsvSyntax {
+        let terminator = match self.terminator {
+            RecordTerminator::Byte(b) => b,
+            _ => b'\n',
+        };
+        CsvSyntax { quote: self.quote, escape: self.escape, terminator, comment: self.comment }
+    }
+
+    /// `data` decoded to UTF-8. Must start and end at a record boundary.
+    pub(crate) fn decode<'a>(&self, data: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.encoding == UTF_8 {
+            // Left to the CSV reader to validate
+            return Cow::Borrowed(data);
+        }
+        match self.encoding.decode_without_bom_handling(data).0 {
+            Cow::Borrowed(text) => Cow::Borrowed(text.as_bytes()),
+            Cow::Owned(text) => Cow::Owned(text.into_bytes()),
+        }
+    }
+
+    /// `source`, decoded to UTF-8 as it is read.
+    pub fn decode_reader<'a, R: Read + 'a>(&self, source: R) -> Box<dyn Read + 'a> {
+        if self.encoding == UTF_8 {
+            return Box::new(source);
+        }
+        Box::new(DecodeReader {
+            source,
+            decoder: self.encoding.new_decoder_without_bom_handling(),
+            raw: vec![0; DECODE_BUF_SIZE],
+            decoded: Vec::new(),
+            pos: 0,
+            done: false,
+        })
+    }
+}
+
+/// Column names for a file without a header row: `1`, `2`, ...
+pub(crate) fn positional_headers(columns: usize) -> StringRecord {
+    (1..=columns).map(|i| i.to_string()).collect()
+}
+
+struct DecodeReader<R> {
+    source: R,
+    decoder: Decoder,
+    raw: Vec<u8>,
+    decoded: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read> Read for DecodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.decoded.len() && !self.done {
+            let n = self.source.read(&mut self.raw)?;
+            self.done = n == 0;
+            let max = self.decoder.max_utf8_buffer_length(n).unwrap_or(n * 3 + 16);
+            self.decoded.resize(max, 0);
+            let (result, read, written, _) = self.decoder.decode_to_utf8(&self.raw[..n], &mut self.decoded, self.done);
+            debug_assert!(result == CoderResult::InputEmpty && read == n);
+            self.decoded.truncate(written);
+            self.pos = 0;
+        }
+        let n = buf.len().min(self.decoded.len() - self.pos);
+        buf[..n].copy_from_slice(&self.decoded[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
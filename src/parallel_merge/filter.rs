@@ -0,0 +1,513 @@
+// --- Row filter expressions ---
+//
+// A run can keep only the records that match a `--where` expression. Filters are
+// evaluated in the chunk phase, as records are parsed, so filtered records are never
+// sorted or spilled. An expression is parsed once and then bound to the columns of a
+// CSV header or to MT log fields, so column names are checked before any record is read.
+//
+//   expr      := and (OR and)*
+//   and       := unary (AND unary)*
+//   unary     := NOT unary | '(' expr ')' | predicate
+//   predicate := column [':' (num|str|date)]
+//                ( op literal | [NOT] IN '(' literal, ... ')' | [NOT] BETWEEN literal AND literal
+//                | [NOT] LIKE 'pattern' | [NOT] MATCHES 'regex' )
+//   column    := name | "quoted name" | number
+//   literal   := number | 'string' | DATE 'yyyy-mm-dd' | TODAY [(+|-) days]
+//
+// Without a type suffix a column is compared as its literals' type.
+
+use anyhow::{Context, Result};
+use chrono::{Days, NaiveDate};
+use csv::StringRecord;
+use regex::bytes::Regex;
+use std::cmp::Ordering;
+
+use crate::mt_log::mt_log_record::{MTLogField, FIELDS};
+
+/// A parsed row filter, not yet bound to the columns of an input.
+#[derive(Clone)]
+pub struct Filter {
+    source: String,
+    expr: Expr<String>,
+}
+
+impl Filter {
+    /// Parses a filter expression, e.g. `branch IN ('001', '002') AND amount >= 1000`.
+    pub fn parse(source: &str) -> Result<Self> {
+        let expr = tokenize(source)
+            .and_then(|tokens| Parser { tokens, pos: 0 }.parse())
+            .map_err(|e| anyhow::anyhow!("Invalid filter {:?}: {}", source, e))?;
+        Ok(Self { source: source.to_string(), expr })
+    }
+
+    /// Binds the filter to records with `headers` (names are matched case-insensitively).
+    pub fn for_csv(&self, headers: &StringRecord) -> Result<RecordFilter> {
+        let expr = self.expr.bind(&mut |name| {
+            headers
+                .iter()
+                .position(|h| h.trim().eq_ignore_ascii_case(name))
+                .with_context(|| format!("Filter column '{}' not found in headers {:?}", name, headers))
+        })?;
+        Ok(RecordFilter { expr })
+    }
+
+    /// Binds the filter to MT log fields (names as for [`MTLogField::find`]).
+    pub fn for_mtlog(&self) -> Result<RecordFilter> {
+        let expr = self.expr.bind(&mut |name| {
+            let field = MTLogField::find(name).with_context(|| format!("Unknown MT log field: {}", name))?;
+            Ok(FIELDS.iter().position(|f| f.name == field.name).unwrap_or_default())
+        })?;
+        Ok(RecordFilter { expr })
+    }
+}
+
+impl std::fmt::Debug for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Filter").field(&self.source).finish()
+    }
+}
+
+/// A [`Filter`] bound to the columns of an input.
+#[derive(Debug, Clone)]
+pub struct RecordFilter {
+    expr: Expr<usize>,
+}
+
+impl RecordFilter {
+    /// Whether a record matches, given its fields by column index.
+    pub fn matches<'a>(&self, field: impl Fn(usize) -> &'a [u8]) -> bool {
+        self.expr.eval(&|i| field(i).trim_ascii())
+    }
+
+    /// Whether a CSV record matches.
+    pub fn matches_record(&self, record: &StringRecord) -> bool {
+        self.matches(|i| record.get(i).unwrap_or_default().as_bytes())
+    }
+
+    /// Whether an MT log line matches.
+    pub(crate) fn matches_mtlog(&self, line: &[u8]) -> bool {
+        self.matches(|i| FIELDS[i].bytes(line))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr<C> {
+    And(Box<Expr<C>>, Box<Expr<C>>),
+    Or(Box<Expr<C>>, Box<Expr<C>>),
+    Not(Box<Expr<C>>),
+    Test(C, Test),
+}
+
+#[derive(Debug, Clone)]
+enum Test {
+    Compare(Ordering, bool, Value),
+    In(Vec<Value>),
+    Between(Value, Value),
+    Like(Vec<u8>),
+    Matches(Regex),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueType {
+    Num,
+    Str,
+    Date,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Num(f64),
+    Str(String),
+    Date(NaiveDate),
+}
+
+impl<C> Expr<C> {
+    fn bind(&self, resolve: &mut impl FnMut(&C) -> Result<usize>) -> Result<Expr<usize>> {
+        Ok(match self {
+            Expr::And(a, b) => Expr::And(Box::new(a.bind(resolve)?), Box::new(b.bind(resolve)?)),
+            Expr::Or(a, b) => Expr::Or(Box::new(a.bind(resolve)?), Box::new(b.bind(resolve)?)),
+            Expr::Not(a) => Expr::Not(Box::new(a.bind(resolve)?)),
+            Expr::Test(column, test) => Expr::Test(resolve(column)?, test.clone()),
+        })
+    }
+}
+
+impl Expr<usize> {
+    fn eval<'a>(&self, field: &impl Fn(usize) -> &'a [u8]) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(field) && b.eval(field),
+            Expr::Or(a, b) => a.eval(field) || b.eval(field),
+            Expr::Not(a) => !a.eval(field),
+            Expr::Test(column, test) => test.eval(field(*column)),
+        }
+    }
+}
+
+impl Test {
+    fn eval(&self, field: &[u8]) -> bool {
+        match self {
+            // `<=` is "not greater", `!=` is "not equal"
+            Test::Compare(ordering, negated, value) => {
+                value.compare(field).is_some_and(|o| (o == *ordering) != *negated)
+            }
+            Test::In(values) => values.iter().any(|v| v.compare(field) == Some(Ordering::Equal)),
+            Test::Between(low, high) => {
+                low.compare(field).is_some_and(|o| o != Ordering::Less)
+                    && high.compare(field).is_some_and(|o| o != Ordering::Greater)
+            }
+            Test::Like(pattern) => like(field, pattern),
+            Test::Matches(regex) => regex.is_match(field),
+        }
+    }
+}
+
+impl Value {
+    /// How `field` compares to this value, or `None` if it is not of the value's type.
+    fn compare(&self, field: &[u8]) -> Option<Ordering> {
+        match self {
+            Value::Num(n) => std::str::from_utf8(field).ok()?.parse::<f64>().ok()?.partial_cmp(n),
+            Value::Str(s) => Some(field.cmp(s.as_bytes())),
+            Value::Date(d) => Some(parse_date(std::str::from_utf8(field).ok()?)?.cmp(d)),
+        }
+    }
+}
+
+/// Dates as `YYYYMMDD`, `YYYY-MM-DD` or `YYYY/MM/DD`; a time after the date is ignored.
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    let compact = text.get(..8).filter(|d| d.bytes().all(|b| b.is_ascii_digit()));
+    let (y, m, d) = match compact {
+        Some(d) => (&d[..4], &d[4..6], &d[6..8]),
+        None => {
+            let d = text.get(..10)?;
+            let sep = d.as_bytes()[4];
+            if !(sep == b'-' || sep == b'/') || d.as_bytes()[7] != sep {
+                return None;
+            }
+            (&d[..4], &d[5..7], &d[8..10])
+        }
+    };
+    NaiveDate::from_ymd_opt(y.parse().ok()?, m.parse().ok()?, d.parse().ok()?)
+}
+
+/// SQL `LIKE`: `%` matches any run of bytes, `_` any one byte.
+fn like(field: &[u8], pattern: &[u8]) -> bool {
+    let (mut f, mut p) = (0, 0);
+    // Where the last `%` was, and the field position it currently stands for
+    let mut backtrack = None;
+    while f < field.len() {
+        match pattern.get(p) {
+            Some(b'%') => {
+                backtrack = Some((p, f));
+                p += 1;
+            }
+            Some(&c) if c == b'_' || c == field[f] => {
+                p += 1;
+                f += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bf)) => {
+                    backtrack = Some((bp, bf + 1));
+                    p = bp + 1;
+                    f = bf + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'%')
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A bare word: a column name or keyword.
+    Word(String),
+    /// A `"quoted"` column name.
+    Quoted(String),
+    /// A `'quoted'` string literal.
+    Str(String),
+    Num(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &["<=", ">=", "<>", "!=", "=", "<", ">", "(", ")", ",", ":", "+", "-"];
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c == '\'' || c == '"' {
+            // A doubled quote stands for itself
+            let mut text = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, q)) if q == c => {
+                        if rest[i + 2..].starts_with(c) {
+                            text.push(c);
+                            chars.next();
+                        } else {
+                            break i + 2;
+                        }
+                    }
+                    Some((_, ch)) => text.push(ch),
+                    None => anyhow::bail!("unterminated quote in {}", rest),
+                }
+            };
+            tokens.push(if c == '\'' { Token::Str(text) } else { Token::Quoted(text) });
+            rest = &rest[end..];
+        } else if c.is_ascii_digit() || c == '.' {
+            let end = rest.find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '.')).unwrap_or(rest.len());
+            tokens.push(Token::Num(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest.find(|ch: char| !(ch.is_alphanumeric() || ch == '_' || ch == '.')).unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|s| rest.starts_with(**s))
+                .with_context(|| format!("unexpected {:?}", c))?;
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn parse(&mut self) -> Result<Expr<String>> {
+        let expr = self.or()?;
+        match self.tokens.get(self.pos) {
+            None => Ok(expr),
+            Some(token) => anyhow::bail!("unexpected {} after the expression", describe(Some(token))),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<()> {
+        if !self.symbol(symbol) {
+            anyhow::bail!("expected '{}', found {}", symbol, describe(self.peek()));
+        }
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<Expr<String>> {
+        let mut expr = self.and()?;
+        while self.keyword("OR") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr<String>> {
+        let mut expr = self.unary()?;
+        while self.keyword("AND") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr<String>> {
+        if self.keyword("NOT") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.symbol("(") {
+            let expr = self.or()?;
+            self.expect_symbol(")")?;
+            return Ok(expr);
+        }
+        self.predicate()
+    }
+
+    fn predicate(&mut self) -> Result<Expr<String>> {
+        // A number is a column too: the positional name of a headerless input or an MT log field index
+        let column = match self.next() {
+            Some(Token::Word(name) | Token::Quoted(name) | Token::Num(name)) => name,
+            token => anyhow::bail!("expected a column, found {}", describe(token.as_ref())),
+        };
+        let column_type = match self.symbol(":") {
+            true => Some(match self.next() {
+                Some(Token::Word(t)) if t.eq_ignore_ascii_case("num") => ValueType::Num,
+                Some(Token::Word(t)) if t.eq_ignore_ascii_case("str") => ValueType::Str,
+                Some(Token::Word(t)) if t.eq_ignore_ascii_case("date") => ValueType::Date,
+                token => anyhow::bail!("expected num, str or date after '{}:', found {}", column, describe(token.as_ref())),
+            }),
+            false => None,
+        };
+        let negated = self.keyword("NOT");
+        let test = if self.keyword("IN") {
+            self.expect_symbol("(")?;
+            let mut values = vec![self.literal()?];
+            while self.symbol(",") {
+                values.push(self.literal()?);
+            }
+            self.expect_symbol(")")?;
+            Test::In(typed(&column, column_type, values)?)
+        } else if self.keyword("BETWEEN") {
+            let low = self.literal()?;
+            if !self.keyword("AND") {
+                anyhow::bail!("expected AND in BETWEEN, found {}", describe(self.peek()));
+            }
+            let high = self.literal()?;
+            let mut values = typed(&column, column_type, vec![low, high])?;
+            let high = values.pop().context("BETWEEN needs two values")?;
+            let low = values.pop().context("BETWEEN needs two values")?;
+            Test::Between(low, high)
+        } else if self.keyword("LIKE") {
+            Test::Like(self.pattern()?.into_bytes())
+        } else if self.keyword("MATCHES") {
+            let pattern = self.pattern()?;
+            Test::Matches(Regex::new(&pattern).with_context(|| format!("invalid regex {:?}", pattern))?)
+        } else if negated {
+            anyhow::bail!("expected IN, BETWEEN, LIKE or MATCHES after NOT, found {}", describe(self.peek()));
+        } else {
+            let (ordering, negated) = match self.next() {
+                Some(Token::Symbol("=")) => (Ordering::Equal, false),
+                Some(Token::Symbol("!=" | "<>")) => (Ordering::Equal, true),
+                Some(Token::Symbol("<")) => (Ordering::Less, false),
+                Some(Token::Symbol(">=")) => (Ordering::Less, true),
+                Some(Token::Symbol(">")) => (Ordering::Greater, false),
+                Some(Token::Symbol("<=")) => (Ordering::Greater, true),
+                token => anyhow::bail!("expected a comparison after '{}', found {}", column, describe(token.as_ref())),
+            };
+            let value = typed(&column, column_type, vec![self.literal()?])?.remove(0);
+            return Ok(Expr::Test(column, Test::Compare(ordering, negated, value)));
+        };
+        let expr = Expr::Test(column, test);
+        Ok(if negated { Expr::Not(Box::new(expr)) } else { expr })
+    }
+
+    fn pattern(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Str(pattern)) => Ok(pattern),
+            token => anyhow::bail!("expected a quoted pattern, found {}", describe(token.as_ref())),
+        }
+    }
+
+    /// A literal, numbers kept as text until the column's type is known.
+    fn literal(&mut self) -> Result<Literal> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Literal::Num(n)),
+            Some(Token::Symbol("-")) => match self.next() {
+                Some(Token::Num(n)) => Ok(Literal::Num(format!("-{}", n))),
+                token => anyhow::bail!("expected a number after '-', found {}", describe(token.as_ref())),
+            },
+            Some(Token::Str(s)) => Ok(Literal::Str(s)),
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("DATE") => match self.next() {
+                Some(Token::Str(s)) => match parse_date(&s) {
+                    Some(date) if s.len() <= 10 => Ok(Literal::Date(date)),
+                    _ => anyhow::bail!("invalid date {:?} (expected YYYY-MM-DD)", s),
+                },
+                token => anyhow::bail!("expected a quoted date after DATE, found {}", describe(token.as_ref())),
+            },
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("TODAY") => {
+                let today = chrono::Local::now().date_naive();
+                let later = if self.symbol("+") {
+                    true
+                } else if self.symbol("-") {
+                    false
+                } else {
+                    return Ok(Literal::Date(today));
+                };
+                let days = match self.next() {
+                    Some(Token::Num(n)) => n.parse::<u64>().ok(),
+                    _ => None,
+                }
+                .context("expected a number of days after TODAY")?;
+                let date = match later {
+                    true => today.checked_add_days(Days::new(days)),
+                    false => today.checked_sub_days(Days::new(days)),
+                };
+                Ok(Literal::Date(date.context("date out of range")?))
+            }
+            token => anyhow::bail!("expected a value, found {}", describe(token.as_ref())),
+        }
+    }
+}
+
+enum Literal {
+    Num(String),
+    Str(String),
+    Date(NaiveDate),
+}
+
+/// Converts the literals a column is compared with to the column's type: its declared
+/// one, or else the literals' own, which must then agree.
+fn typed(column: &str, column_type: Option<ValueType>, literals: Vec<Literal>) -> Result<Vec<Value>> {
+    let column_type = match column_type {
+        Some(t) => t,
+        None => {
+            let types: Vec<ValueType> = literals
+                .iter()
+                .map(|l| match l {
+                    Literal::Num(_) => ValueType::Num,
+                    Literal::Str(_) => ValueType::Str,
+                    Literal::Date(_) => ValueType::Date,
+                })
+                .collect();
+            if types.iter().any(|t| *t != types[0]) {
+                anyhow::bail!("'{}' is compared with values of different types (give it a type, e.g. {}:num)", column, column);
+            }
+            types[0]
+        }
+    };
+    literals
+        .into_iter()
+        .map(|literal| {
+            Ok(match (column_type, literal) {
+                (ValueType::Num, Literal::Num(n) | Literal::Str(n)) => {
+                    Value::Num(n.trim().parse().ok().with_context(|| format!("'{}' is not a number", n))?)
+                }
+                (ValueType::Str, Literal::Num(s) | Literal::Str(s)) => Value::Str(s),
+                (ValueType::Date, Literal::Str(s) | Literal::Num(s)) => {
+                    Value::Date(parse_date(s.trim()).with_context(|| format!("'{}' is not a date", s))?)
+                }
+                (ValueType::Date, Literal::Date(d)) => Value::Date(d),
+                (_, Literal::Date(_)) => anyhow::bail!("'{}' is not a date column but is compared with a date", column),
+            })
+        })
+        .collect()
+}
+
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        None => "the end".to_string(),
+        Some(Token::Word(w) | Token::Num(w)) => format!("'{}'", w),
+        Some(Token::Quoted(q)) => format!("\"{}\"", q),
+        Some(Token::Str(s)) => format!("'{}'", s),
+        Some(Token::Symbol(s)) => format!("'{}'", s),
+    }
+}
@@ -0,0 +1,281 @@
+// --- Sort metadata sidecar ---
+//
+// Every sorted output file gets a `<output>.meta.json` describing how it was sorted, so
+// later runs (`merge --into`, presorted merges) and downstream readers do not have to be
+// told the keys again, and can refuse a file that is sorted differently.
+
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::csv_key::{parse_sort_spec, CsvSortColumn, CsvSortType};
+use super::mtlog::{MTLogSortColumn, MTLogSortType};
+use super::output::{is_stdio, sha256_file, sidecar_path, write_small_file_atomic, OutputOptions};
+use crate::mt_log::mt_log_record::LAYOUT_VERSION;
+
+/// Record format of a sorted output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetaFormat {
+    Csv,
+    Mtlog,
+}
+
+/// One sort key of a sorted output, e.g. `{"column": "amount", "type": "num", "descending": true}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetaSortKey {
+    /// The CSV header name, or the MT log field index.
+    pub column: String,
+    #[serde(rename = "type")]
+    pub key_type: String,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+/// How an output file is sorted, kept in `<output>.meta.json`.
+///
+/// [`OutputMeta::csv`] and [`OutputMeta::mtlog`] describe the sort a caller expects;
+/// [`OutputMeta::check_matches`] refuses a file whose metadata says otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputMeta {
+    pub format: MetaFormat,
+    /// Version of the record layout (`mt_log_record::LAYOUT_VERSION` for MT log, 1 for CSV).
+    #[serde(default = "first_layout_version")]
+    pub layout_version: u32,
+    /// The CSV header, if known.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub columns: Vec<String>,
+    pub sort_keys: Vec<MetaSortKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_count: Option<u64>,
+    /// `sha256:<hex digest>` of the file's contents, if it was written with a checksum.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_version: Option<String>,
+    /// When the file was written, RFC 3339 in UTC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+}
+
+fn first_layout_version() -> u32 {
+    1
+}
+
+/// `<output>.meta.json`.
+pub fn meta_path(output: &Path) -> PathBuf {
+    sidecar_path(output, "meta.json")
+}
+
+impl OutputMeta {
+    fn new(format: MetaFormat, layout_version: u32, sort_keys: Vec<MetaSortKey>) -> Self {
+        Self {
+            format,
+            layout_version,
+            columns: Vec::new(),
+            sort_keys,
+            record_count: None,
+            fingerprint: None,
+            tool_version: None,
+            created_at: None,
+        }
+    }
+
+    /// Metadata for a CSV output sorted by `sort_columns` specs (`name[:type][:asc|desc]`).
+    pub fn csv(sort_columns: &[&str]) -> Self {
+        let sort_keys = sort_columns
+            .iter()
+            .map(|spec| {
+                let (name, col_type, descending) = parse_sort_spec(spec);
+                csv_key(name, col_type, descending)
+            })
+            .collect();
+        Self::new(MetaFormat::Csv, 1, sort_keys)
+    }
+
+    /// Metadata for an MT log output sorted by `sort_columns`, in the current record layout.
+    pub fn mtlog(sort_columns: &[MTLogSortColumn]) -> Self {
+        let sort_keys = sort_columns
+            .iter()
+            .map(|col| {
+                let key_type = match col.col_type {
+                    MTLogSortType::Date => "date",
+                    MTLogSortType::Time => "time",
+                    MTLogSortType::Num => "num",
+                    MTLogSortType::Str => "str",
+                };
+                MetaSortKey { column: col.index.to_string(), key_type: key_type.to_string(), descending: false }
+            })
+            .collect();
+        Self::new(MetaFormat::Mtlog, LAYOUT_VERSION, sort_keys)
+    }
+
+    /// Metadata for a merged CSV output of `records` records, sorted by the resolved `columns`.
+    pub(crate) fn for_csv(headers: &StringRecord, columns: &[CsvSortColumn], records: usize) -> Self {
+        let sort_keys = columns
+            .iter()
+            .map(|c| csv_key(headers.get(c.index).unwrap_or_default(), c.col_type, c.descending))
+            .collect();
+        Self {
+            columns: headers.iter().map(str::to_string).collect(),
+            record_count: Some(records as u64),
+            ..Self::new(MetaFormat::Csv, 1, sort_keys)
+        }
+    }
+
+    /// Metadata for a merged MT log output of `records` records.
+    pub(crate) fn for_mtlog(sort_columns: &[MTLogSortColumn], records: usize) -> Self {
+        Self { record_count: Some(records as u64), ..Self::mtlog(sort_columns) }
+    }
+
+    /// Reads the metadata of `output`, if it has any.
+    pub fn load(output: &Path) -> Result<Option<Self>> {
+        let path = meta_path(output);
+        let json = match std::fs::read(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let meta = serde_json::from_slice(&json).with_context(|| format!("Invalid metadata in {}", path.display()))?;
+        Ok(Some(meta))
+    }
+
+    /// Writes the metadata of `output` next to it, stamped with the output's sha256 (if
+    /// it was computed), the tool version and the current time.
+    pub(crate) fn write(mut self, output: &Path, sha256: Option<&str>) -> Result<()> {
+        self.fingerprint = sha256.map(|sha256| format!("sha256:{}", sha256));
+        self.tool_version = Some(env!("CARGO_PKG_VERSION").to_string());
+        self.created_at = Some(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+        let mut json = serde_json::to_vec_pretty(&self)?;
+        json.push(b'\n');
+        let path = meta_path(output);
+        write_small_file_atomic(&path, &json)?;
+        info!("[output] Wrote metadata {}", path.display());
+        Ok(())
+    }
+
+    /// Fails unless `output`, described by `self`, is sorted the way `expected` asks for:
+    /// same format, record layout and sort keys, and the same CSV header if both know it.
+    /// CSV column names are compared case-insensitively, like sort columns are resolved.
+    pub fn check_matches(&self, expected: &OutputMeta, output: &Path) -> Result<()> {
+        if self.format != expected.format || self.layout_version != expected.layout_version {
+            anyhow::bail!(
+                "{} is {} (layout version {}), but this run expects {} (layout version {})",
+                output.display(),
+                self.format.name(),
+                self.layout_version,
+                expected.format.name(),
+                expected.layout_version
+            );
+        }
+        let same_key = |a: &MetaSortKey, b: &MetaSortKey| {
+            a.column.eq_ignore_ascii_case(&b.column) && a.key_type == b.key_type && a.descending == b.descending
+        };
+        let same_keys = self.sort_keys.len() == expected.sort_keys.len()
+            && self.sort_keys.iter().zip(&expected.sort_keys).all(|(a, b)| same_key(a, b));
+        if !same_keys {
+            anyhow::bail!(
+                "{} is sorted by {}, but this run sorts by {}",
+                output.display(),
+                describe_keys(&self.sort_keys),
+                describe_keys(&expected.sort_keys)
+            );
+        }
+        if !self.columns.is_empty() && !expected.columns.is_empty() && self.columns != expected.columns {
+            anyhow::bail!(
+                "{} has columns {:?}, but this run expects {:?}",
+                output.display(),
+                self.columns,
+                expected.columns
+            );
+        }
+        Ok(())
+    }
+
+    /// Re-reads `output` and fails if it no longer matches the recorded fingerprint.
+    pub fn verify_fingerprint(&self, output: &Path) -> Result<()> {
+        let Some(expected) = &self.fingerprint else {
+            anyhow::bail!("{} has no recorded fingerprint (it was written without a checksum)", output.display());
+        };
+        let actual = format!("sha256:{}", sha256_file(output)?);
+        if &actual != expected {
+            anyhow::bail!(
+                "{} was modified after it was written (fingerprint {}, recorded {})",
+                output.display(),
+                actual,
+                expected
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Checks the metadata of presorted `inputs`, where they have any, against `expected`.
+/// An input sorted differently fails the run, or with `fallback` returns `false` so the
+/// inputs are sorted in full instead.
+pub(crate) fn inputs_match_meta(inputs: &[PathBuf], expected: &OutputMeta, fallback: bool) -> Result<bool> {
+    for input in inputs {
+        let Some(meta) = OutputMeta::load(input)? else { continue };
+        if let Err(e) = meta.check_matches(expected, input) {
+            if !fallback {
+                return Err(e);
+            }
+            warn!("{:#}, sorting the inputs in full", e);
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Checks that new records can be merged into `existing`, an output sorted as `expected`
+/// says. Returns `false` if `existing` does not exist yet.
+pub(crate) fn check_merge_into(existing: &Path, expected: &OutputMeta, options: &OutputOptions) -> Result<bool> {
+    if is_stdio(existing) {
+        anyhow::bail!("Cannot merge into stdout, only into an existing output file");
+    }
+    if options.partitioned {
+        anyhow::bail!("Cannot merge into partitioned output");
+    }
+    if !existing.exists() {
+        info!("{} does not exist yet, sorting the inputs into it", existing.display());
+        return Ok(false);
+    }
+    match OutputMeta::load(existing)? {
+        Some(meta) => meta.check_matches(expected, existing)?,
+        None => warn!(
+            "{} has no {}, assuming it is sorted by the given keys",
+            existing.display(),
+            meta_path(existing).display()
+        ),
+    }
+    Ok(true)
+}
+
+impl MetaFormat {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Csv => "CSV",
+            Self::Mtlog => "MT log",
+        }
+    }
+}
+
+fn csv_key(name: &str, col_type: CsvSortType, descending: bool) -> MetaSortKey {
+    let key_type = match col_type {
+        CsvSortType::Auto => "auto",
+        CsvSortType::Num => "num",
+        CsvSortType::Str => "str",
+    };
+    MetaSortKey { column: name.to_string(), key_type: key_type.to_string(), descending }
+}
+
+/// E.g. `[amount:num:desc, id:auto]`.
+fn describe_keys(keys: &[MetaSortKey]) -> String {
+    let keys: Vec<String> = keys
+        .iter()
+        .map(|k| format!("{}:{}{}", k.column, k.key_type, if k.descending { ":desc" } else { "" }))
+        .collect();
+    format!("[{}]", keys.join(", "))
+}
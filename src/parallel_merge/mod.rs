@@ -1,29 +1,31 @@
 // --- Imports ---
 use anyhow::{Context, Result};
-use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use csv::{StringRecord, WriterBuilder};
 use log::{debug, error, info, warn};
 use num_format::{Locale, ToFormattedString};
 use rayon::prelude::*;
 use std::cmp::Ordering;
 use std::fs::File;
-use std::io::{BufRead, BufWriter, Write, BufReader};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
 use std::time::Instant;
 use tempfile::TempDir;
 
 // --- MergeRecord struct for heap ---
 #[derive(Debug)]
 struct MergeRecord {
+    key: Vec<u8>,
     record: StringRecord,
     source_index: usize,
-    sort_indices: Arc<Vec<usize>>,
 }
 
 impl Ord for MergeRecord {
     fn cmp(&self, other: &Self) -> Ordering {
-        // ใช้ compare_records ตาม sort_indices
-        compare_records(&self.record, &other.record, &self.sort_indices).reverse()
+        // เทียบ sort key แบบ byte; ties go to the earlier source so the merge is stable
+        self.key
+            .cmp(&other.key)
+            .then_with(|| self.source_index.cmp(&other.source_index))
+            .reverse()
     }
 }
 
@@ -35,115 +37,52 @@ impl PartialOrd for MergeRecord {
 
 impl PartialEq for MergeRecord {
     fn eq(&self, other: &Self) -> bool {
-        self.record == other.record
+        self.cmp(other) == Ordering::Equal
     }
 }
 
 impl Eq for MergeRecord {}
 
-// --- Compare records helper ---
-fn compare_records(
-    a: &StringRecord,
-    b: &StringRecord,
-    sort_indices: &[usize],
-) -> std::cmp::Ordering {
-    for &idx in sort_indices {
-        let a_val = a.get(idx).unwrap_or("");
-        let b_val = b.get(idx).unwrap_or("");
-        // Try numeric comparison first
-        let ord = match (a_val.parse::<i64>(), b_val.parse::<i64>()) {
-            (Ok(a_num), Ok(b_num)) => a_num.cmp(&b_num),
-            _ => a_val.cmp(b_val),
-        };
-        if ord != std::cmp::Ordering::Equal {
-            return ord;
-        }
-    }
-    std::cmp::Ordering::Equal
-}
-
 // --- Header validation ---
-fn validate_headers(input_paths: &[PathBuf]) -> Result<StringRecord> {
-    let mut headers: Option<StringRecord> = None;
-    for path in input_paths {
-        let file =
-            File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
-        let mut rdr = ReaderBuilder::new()
-            .has_headers(true)
-            .from_reader(BufReader::new(file));
-        let current_headers = rdr.headers()?.clone();
-        match &headers {
-            None => headers = Some(current_headers),
-            Some(expected_headers) if expected_headers != &current_headers => {
-                return Err(anyhow::anyhow!(
-                    "Header mismatch in file {}:\nExpected: {:?}\nFound:    {:?}",
-                    path.display(),
-                    expected_headers.iter().collect::<Vec<_>>(),
-                    current_headers.iter().collect::<Vec<_>>()
-                ));
-            }
-            _ => {}
-        }
+/// Reads the header of every source and reconciles them by `options.schema`, setting
+/// each source's projection. Returns the merged header.
+fn validate_headers(sources: &mut [CsvSource], dialect: &CsvDialect, options: &RunOptions) -> Result<StringRecord> {
+    let headers = source_headers(sources, dialect)?;
+    let headers: Vec<(&Path, StringRecord)> = sources.iter().map(|s| s.name.as_path()).zip(headers).collect();
+    let (merged, projections) = reconcile_headers(&headers, &options.schema)?;
+    for (source, projection) in sources.iter_mut().zip(projections) {
+        source.projection = Some(projection);
     }
-    headers.ok_or_else(|| anyhow::anyhow!("No input files provided"))
+    Ok(merged)
 }
 
-// --- Sort column index resolution ---
-fn get_sort_column_indices(headers: &StringRecord, sort_columns: &[&str]) -> Vec<usize> {
-    let mut indices = Vec::new();
-    let header_vec: Vec<&str> = headers.iter().collect();
-    info!("Available columns in CSV: {:?}", header_vec);
-    info!("Requested sort columns: {:?}", sort_columns);
-    for col in sort_columns.iter() {
-        let col_trimmed = col.trim();
-        match headers
-            .iter()
-            .position(|h| h.trim().eq_ignore_ascii_case(col_trimmed))
-        {
-            Some(idx) => {
-                info!("Sorting by column: '{}' (index {})", col_trimmed, idx);
-                indices.push(idx);
-            }
+/// The header of every source, read in `dialect`.
+fn source_headers(sources: &[CsvSource], dialect: &CsvDialect) -> Result<Vec<StringRecord>> {
+    let mut headers = Vec::with_capacity(sources.len());
+    for source in sources {
+        let current_headers = match &source.stream {
+            // A stream can only be read once, so its header was read up front
+            Some(stream) => stream.headers().clone(),
             None => {
-                warn!(
-                    "Warning: Sort column '{}' not found in headers",
-                    col_trimmed
-                );
-                let similar: Vec<&str> = headers
-                    .iter()
-                    .filter(|h| {
-                        h.trim()
-                            .to_lowercase()
-                            .contains(&col_trimmed.to_lowercase())
-                    })
-                    .collect();
-                if !similar.is_empty() {
-                    warn!("  Did you mean one of these? {:?}", similar);
-                }
+                let file = File::open(&source.name)
+                    .with_context(|| format!("Failed to open file: {}", source.name.display()))?;
+                let mut rdr = dialect.reader().from_reader(dialect.decode_reader(BufReader::new(file)));
+                dialect.read_headers(&mut rdr)?
             }
-        }
-    }
-    if indices.is_empty() {
-        warn!(
-            "No valid sort columns found. Available columns: {:?}",
-            header_vec
-        );
+        };
+        headers.push(current_headers);
     }
-    indices
+    Ok(headers)
 }
 
-// --- Get first record of a file (for header detection) ---
-fn get_first_record(path: &Path) -> Result<StringRecord> {
-    let file =
-        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
-    let mut rdr = ReaderBuilder::new()
-        .has_headers(false)
-        .from_reader(BufReader::new(file));
-    match rdr.records().next() {
-        Some(Ok(record)) => Ok(record),
-        Some(Err(e)) => Err(anyhow::anyhow!("Failed to read record: {}", e)),
-        None => Err(anyhow::anyhow!("File is empty: {}", path.display())),
-    }
+/// A CSV input: a file read by path, or a stream (stdin, a caller's reader) whose
+/// header has already been read.
+struct CsvSource {
+    /// The path, or a name for a stream; chunk files are named after it.
+    name: PathBuf,
+    stream: Option<CsvStream>,
+    /// Maps its records onto the merged header; set by [`validate_headers`].
+    projection: Option<Projection>,
 }
 
 /// Splits a large CSV file into multiple smaller chunks, processes them in parallel, and sorts the records
@@ -161,25 +100,26 @@ fn get_first_record(path: &Path) -> Result<StringRecord> {
 /// or an error if the operation fails at any step.
 ///
 /// # Behavior
-/// - Reads and validates the input CSV file.
-/// - Splits the file into chunks based on the desired size, ensuring that field lengths are taken into account.
-/// - Sorts the records within each chunk based on the specified columns.
+/// - Memory-maps the input CSV file and splits it into byte ranges of about `chunk_size_mb`, each starting
+///   at a record boundary (newlines inside quoted fields are skipped).
+/// - Reads, validates and sorts the records of each range based on the specified columns.
 /// - Writes the sorted records of each chunk into separate CSV files in the provided temporary directory.
 /// - Processes the chunks in parallel for efficiency.
 ///
 /// # Logging
 /// - Comprehensive log messages provide detailed insights, including:
-///   - Pre-scan details (file size, record count, chunk count, etc.).
-///   - Processing status for each chunk (e.g., sorting time, writing time, total processing time).
+///   - Pre-scan details (file size, chunk count, etc.).
+///   - Processing status for each chunk (e.g., reading time, sorting time, writing time, total processing time).
 ///   - Examples of first/last few rows in each chunk for debugging.
 /// - Logs errors related to inconsistent record lengths or parsing issues in the input CSV.
 ///
 /// # Heuristics
-/// - The chunk size is determined based on both the specified size in MB and an estimate of ~16 bytes per field.
-/// - Ensures that each chunk contains at least one record to prevent empty chunks.
+/// - Quotes are assumed to appear only in quoted fields (escaped as `""`), so their parity tells whether a
+///   range boundary falls inside a quoted field.
+/// - Inputs that are not regular files (e.g. pipes) are read into memory before being split.
 ///
 /// # Threading
-/// - Uses parallel processing (`rayon::into_par_iter()`) to distribute chunks across threads for faster processing.
+/// - Uses parallel processing (`rayon::par_iter()`) so each range is read, parsed and sorted on its own thread.
 ///
 /// # Errors
 /// Returns an error in cases such as:
@@ -188,8 +128,9 @@ fn get_first_record(path: &Path) -> Result<StringRecord> {
 /// - Issues with writing chunk files to the temporary directory.
 ///
 /// # Example
-/// ```rust
+/// ```rust,no_run
 /// use csv::StringRecord;
+/// use split_merge_hub_demo::parallel_merge::parallel_split_file_to_chunks;
 /// use std::path::Path;
 /// use tempfile::TempDir;
 ///
@@ -232,79 +173,97 @@ pub fn parallel_split_file_to_chunks(
     chunk_size_mb: usize,
     headers: &StringRecord,
 ) -> Result<Vec<PathBuf>> {
-    use std::io::{BufRead, Seek, SeekFrom};
-    use std::thread;
-    use std::time::Duration;
-    // Start total timer
-    let total_start = Instant::now();
-    let file_size = std::fs::metadata(file_path)?.len();
+    parallel_split_file_to_chunks_with(
+        file_path,
+        temp_dir,
+        sort_columns,
+        chunk_size_mb,
+        headers,
+        &RunOptions::default(),
+    )
+}
+
+/// Same as [`parallel_split_file_to_chunks`], reporting progress through `options`.
+///
+/// The input is read in `options.input_dialect`; chunk files use the default dialect.
+pub fn parallel_split_file_to_chunks_with(
+    file_path: &Path,
+    temp_dir: &TempDir,
+    sort_columns: &[&str],
+    chunk_size_mb: usize,
+    headers: &StringRecord,
+    options: &RunOptions,
+) -> Result<Vec<PathBuf>> {
+    let chunk_dialect = CsvDialect::default();
+    let projection = Projection::identity(headers.len());
+    split_file_to_chunks(file_path, temp_dir, sort_columns, chunk_size_mb, headers, &projection, &chunk_dialect, options)
+}
+
+/// Splits an input into sorted chunk files written in `chunk_dialect`, its records mapped
+/// onto `headers` by `projection`.
+#[allow(clippy::too_many_arguments)]
+fn split_file_to_chunks(
+    file_path: &Path,
+    temp_dir: &TempDir,
+    sort_columns: &[&str],
+    chunk_size_mb: usize,
+    headers: &StringRecord,
+    projection: &Projection,
+    chunk_dialect: &CsvDialect,
+    options: &RunOptions,
+) -> Result<Vec<PathBuf>> {
+    // Regular files are mapped and cut into record-aligned ranges that are parsed and
+    // sorted concurrently; stdin (`-`) and other streams are read block by block
+    let dialect = &options.input_dialect;
+    let map = if is_stdio(file_path) { None } else { map_input(file_path)? };
+    let Some(data) = map else {
+        let stream = CsvStream::open(file_path, dialect)?;
+        return split_csv_stream_to_chunks(
+            stream,
+            file_path,
+            temp_dir,
+            sort_columns,
+            chunk_size_mb,
+            headers,
+            projection,
+            chunk_dialect,
+            options,
+        );
+    };
+    let file_size = data.len() as u64;
     let prescan_start = Instant::now();
-    let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(file_path)?;
-    let all_records: Vec<StringRecord> = rdr
-        .records()
-        .filter_map(|r| match r {
-            Ok(rec) if rec.len() == headers.len() => Some(rec),
-            Ok(rec) => {
-                error!(
-                    "CSV format error: expected {} fields, found {} fields. Record: {:?}",
-                    headers.len(),
-                    rec.len(),
-                    rec
-                );
-                None
-            }
-            Err(e) => {
-                error!("CSV parse error: {}", e);
-                None
-            }
-        })
-        .collect();
-    let prescan_elapsed = prescan_start.elapsed();
-    let chunk_size = chunk_size_mb * 1024 * 1024 / (headers.len() * 16).max(1); // heuristic: ~16 bytes per field
-    let chunk_size = chunk_size.max(1);
-    let chunk_count = (all_records.len() + chunk_size - 1) / chunk_size;
+    let syntax = dialect.syntax();
+    let data_start = match dialect.has_header {
+        true => syntax.first_record_end(&data).unwrap_or(data.len()),
+        false => 0,
+    };
+    let range_size = (chunk_size_mb * 1024 * 1024).max(1);
+    let ranges = csv_record_ranges(&data, data_start, range_size, &syntax);
+    let chunk_count = ranges.len();
     info!(
-        "[split] Pre-scan complete. File: {:?}, Size: {} bytes, Records: {}, Chunks: {}, ChunkSize: {} (records), Pre-scan Time: {:.2?}",
-        file_path, fmtnum(file_size), fmtnum(all_records.len()), fmtnum(chunk_count), fmtnum(chunk_size), prescan_elapsed
+        "[split] Pre-scan complete. File: {:?}, Size: {} bytes, Chunks: {}, ChunkSize: {} MB, Pre-scan Time: {:.2?}",
+        file_path, fmtnum(file_size), fmtnum(chunk_count), fmtnum(chunk_size_mb), prescan_start.elapsed()
     );
     info!(
-        "[split] Processing {} chunks in parallel...",
+        "[split] Reading and sorting {} chunks in parallel...",
         fmtnum(chunk_count)
     );
     let chunk_timer = Instant::now();
-    let chunk_paths: Result<Vec<PathBuf>> = (0..chunk_count).into_par_iter().map(|i| -> Result<PathBuf> {
+    let chunk_progress = WorkerProgress::new(options.progress.as_ref(), ProgressPhase::Chunk, file_size);
+    let columns = resolve_sort_columns(headers, sort_columns);
+    info!("[SPLIT] Using sort columns: {:?} ({:?})", sort_columns, columns);
+    let filter = options.filter.as_ref().map(|f| f.for_csv(headers)).transpose()?;
+    let chunk_paths: Result<Vec<PathBuf>> = ranges.par_iter().enumerate().map(|(i, &(start, end))| -> Result<PathBuf> {
+        options.cancel.check()?;
         let chunk_start_time = Instant::now();
-        let start = i * chunk_size;
-        let end = ((i + 1) * chunk_size).min(all_records.len());
-        let mut records = all_records[start..end].to_vec();
-        let sort_indices = get_sort_column_indices(headers, sort_columns);
-        info!("[SPLIT] Using sort columns: {:?} (indices: {:?})", sort_columns, sort_indices);
-        let sort_start = Instant::now();
-        records.sort_by(|a, b| compare_records(a, b, &sort_indices));
-        let sort_elapsed = sort_start.elapsed();
-        if !records.is_empty() {
-            debug!("[SPLIT] Chunk {} first 3 rows: {:?}", i, &records.iter().take(3).collect::<Vec<_>>());
-            debug!("[SPLIT] Chunk {} last 3 rows: {:?}", i, &records.iter().rev().take(3).collect::<Vec<_>>());
-        }
-        let file_stem = file_path.file_stem().and_then(|s| s.to_str()).unwrap_or("input");
-        let tmp = tempfile::NamedTempFile::new()?;
-        {
-            let mut writer = WriterBuilder::new()
-                .has_headers(false)
-                .from_writer(BufWriter::with_capacity(8 * 1024 * 1024, tmp.as_file()));
-            writer.write_record(headers)?;
-            for rec in &records {
-                writer.write_record(rec)?;
-            }
-            writer.flush()?;
-        }
-        let chunk_path = temp_dir.path().join(format!("chunk_parallel_{}_{}.csv", file_stem, i));
-        tmp.persist(&chunk_path)?;
+        let records = sort_csv_block(&data[start..end], projection, filter.as_ref(), &columns, dialect, options)?;
+        let sort_elapsed = chunk_start_time.elapsed();
+        let chunk_path = chunk_file_path(temp_dir, file_path, i);
+        write_csv_chunk(&chunk_path, temp_dir, headers, &columns, &records, chunk_dialect)?;
+        chunk_progress.add((end - start) as u64, records.len() as u64);
         let chunk_elapsed = chunk_start_time.elapsed();
         info!(
-            "[split] Chunk {}/{} | Records: {} | Path: {:?} | Sort: {:.2?} | Write: {:.2?} | Total: {:.2?}",
+            "[split] Chunk {}/{} | Records: {} | Path: {:?} | Read+Sort: {:.2?} | Write: {:.2?} | Total: {:.2?}",
             i + 1, fmtnum(chunk_count), fmtnum(records.len()), chunk_path, sort_elapsed, chunk_elapsed - sort_elapsed, chunk_elapsed
         );
         Ok(chunk_path)
@@ -324,6 +283,219 @@ pub fn parallel_split_file_to_chunks(
     chunk_paths
 }
 
+/// Same as [`parallel_split_file_to_chunks_with`], for a CSV stream such as an object-storage
+/// download. Its header must match `headers`; chunk files are named after `name`, so every
+/// source split into the same `temp_dir` needs its own.
+pub fn parallel_split_reader_to_chunks<R: Read + Send + 'static>(
+    source: R,
+    name: &str,
+    temp_dir: &TempDir,
+    sort_columns: &[&str],
+    chunk_size_mb: usize,
+    headers: &StringRecord,
+    options: &RunOptions,
+) -> Result<Vec<PathBuf>> {
+    let stream = CsvStream::new(Box::new(source), &options.input_dialect)?;
+    if stream.headers() != headers {
+        return Err(anyhow::anyhow!(
+            "Header mismatch in stream {}:\nExpected: {:?}\nFound:    {:?}",
+            name,
+            headers.iter().collect::<Vec<_>>(),
+            stream.headers().iter().collect::<Vec<_>>()
+        ));
+    }
+    let chunk_dialect = CsvDialect::default();
+    let projection = Projection::identity(headers.len());
+    split_csv_stream_to_chunks(
+        stream,
+        Path::new(name),
+        temp_dir,
+        sort_columns,
+        chunk_size_mb,
+        headers,
+        &projection,
+        &chunk_dialect,
+        options,
+    )
+}
+
+/// A block of whole CSV records read from a stream, and its sorted records.
+#[derive(Default)]
+struct CsvBlock {
+    data: Vec<u8>,
+    sorted: Vec<(Vec<u8>, StringRecord)>,
+}
+
+impl PipelineChunk for CsvBlock {
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.sorted.clear();
+    }
+}
+
+/// Splits a CSV stream into sorted chunks: blocks of about `chunk_size_mb` are read on
+/// one thread while earlier blocks are parsed and sorted, and written, on others.
+#[allow(clippy::too_many_arguments)]
+fn split_csv_stream_to_chunks(
+    stream: CsvStream,
+    name: &Path,
+    temp_dir: &TempDir,
+    sort_columns: &[&str],
+    chunk_size_mb: usize,
+    headers: &StringRecord,
+    projection: &Projection,
+    chunk_dialect: &CsvDialect,
+    options: &RunOptions,
+) -> Result<Vec<PathBuf>> {
+    let chunk_timer = Instant::now();
+    let columns = resolve_sort_columns(headers, sort_columns);
+    let filter = options.filter.as_ref().map(|f| f.for_csv(headers)).transpose()?;
+    info!("[SPLIT] Streaming {:?} in {} MB blocks, sort columns: {:?} ({:?})", name, fmtnum(chunk_size_mb), sort_columns, columns);
+    let block_size = (chunk_size_mb * 1024 * 1024).max(1);
+    let chunk_progress = WorkerProgress::new(options.progress.as_ref(), ProgressPhase::Chunk, 0);
+    let workers = rayon::current_num_threads().max(1);
+    let pipeline = Pipeline { readers: 1, sorters: workers, buffers: workers + 2 };
+    let stream = std::sync::Mutex::new(stream);
+    let mut chunk_index = 0;
+    let (chunk_paths, stats) = pipeline.run(
+        std::slice::from_ref(&stream),
+        |_, stream, block: &mut CsvBlock, feed| {
+            let mut stream = stream.lock().unwrap_or_else(|e| e.into_inner());
+            loop {
+                options.cancel.check()?;
+                if !stream.next_block(block_size, &mut block.data)? {
+                    return Ok(());
+                }
+                feed.submit(block)?;
+            }
+        },
+        |block| match sort_csv_block(&block.data, projection, filter.as_ref(), &columns, &options.input_dialect, options) {
+            Ok(sorted) => block.sorted = sorted,
+            // Cancelled: the reader stops at its next block
+            Err(_) => block.sorted.clear(),
+        },
+        |block| {
+            options.cancel.check()?;
+            let chunk_path = chunk_file_path(temp_dir, name, chunk_index);
+            chunk_index += 1;
+            write_csv_chunk(&chunk_path, temp_dir, headers, &columns, &block.sorted, chunk_dialect)?;
+            chunk_progress.add_for(0, block.data.len() as u64, block.sorted.len() as u64);
+            info!("[split] Chunk {} | Records: {} | Path: {:?}", chunk_index, fmtnum(block.sorted.len()), chunk_path);
+            Ok(chunk_path)
+        },
+    )?;
+    options.cancel.check()?;
+    let bytes_read = stream.into_inner().unwrap_or_else(|e| e.into_inner()).bytes_read();
+    info!(
+        "[split] ALL DONE. Stream: {:?}, Size: {} bytes, Chunks: {}, Time: {:.2?}, utilization: {}",
+        name,
+        fmtnum(bytes_read),
+        fmtnum(chunk_paths.len()),
+        chunk_timer.elapsed(),
+        stats.utilization()
+    );
+    Ok(chunk_paths)
+}
+
+/// Parses the CSV records in `data`, whole records in `dialect`, maps them onto the merged
+/// header by `projection`, keeps those matching `filter` and sorts them by their keys.
+///
+/// Records with the wrong number of fields, and unparsable ones, are logged and skipped.
+fn sort_csv_block(
+    data: &[u8],
+    projection: &Projection,
+    filter: Option<&RecordFilter>,
+    columns: &[CsvSortColumn],
+    dialect: &CsvDialect,
+    options: &RunOptions,
+) -> Result<Vec<(Vec<u8>, StringRecord)>> {
+    let data = dialect.decode(data);
+    let mut rdr = dialect.reader().has_headers(false).flexible(true).from_reader(&data[..]);
+    // Keys are built once here and persisted next to the chunk, so merges only compare bytes
+    let mut records: Vec<(Vec<u8>, StringRecord)> = Vec::new();
+    let identity = projection.is_identity();
+    for r in rdr.records() {
+        match r {
+            Ok(rec) if rec.len() == projection.input_len() => {
+                if records.len().is_multiple_of(CANCEL_CHECK_INTERVAL) {
+                    options.cancel.check()?;
+                }
+                let rec = match identity {
+                    true => rec,
+                    false => {
+                        let mut projected = StringRecord::new();
+                        projection.apply(&rec, &mut projected);
+                        projected
+                    }
+                };
+                if filter.is_some_and(|f| !f.matches_record(&rec)) {
+                    continue;
+                }
+                records.push((csv_sort_key(&rec, columns), rec));
+            }
+            Ok(rec) => {
+                error!(
+                    "CSV format error: expected {} fields, found {} fields. Record: {:?}",
+                    projection.input_len(),
+                    rec.len(),
+                    rec
+                );
+            }
+            Err(e) => {
+                error!("CSV parse error: {}", e);
+            }
+        }
+    }
+    records.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(records)
+}
+
+fn chunk_file_path(temp_dir: &TempDir, input: &Path, index: usize) -> PathBuf {
+    let file_stem = if is_stdio(input) {
+        "stdin"
+    } else {
+        input.file_stem().and_then(|s| s.to_str()).unwrap_or("input")
+    };
+    temp_dir.path().join(format!("chunk_parallel_{}_{}.csv", file_stem, index))
+}
+
+/// Writes sorted records in `dialect` (with the header if it has one) to `chunk_path`,
+/// and their keys to its sidecar.
+fn write_csv_chunk(
+    chunk_path: &Path,
+    temp_dir: &TempDir,
+    headers: &StringRecord,
+    columns: &[CsvSortColumn],
+    records: &[(Vec<u8>, StringRecord)],
+    dialect: &CsvDialect,
+) -> Result<()> {
+    if let (Some((_, first)), Some((_, last))) = (records.first(), records.last()) {
+        debug!("[SPLIT] Chunk {:?} first row: {:?}, last row: {:?}", chunk_path, first, last);
+    }
+    let tmp = tempfile::NamedTempFile::new_in(temp_dir.path())?;
+    {
+        let mut keys = KeyWriter::create(chunk_path, columns)?;
+        let mut writer = dialect
+            .writer()
+            .from_writer(keys.counting(BufWriter::with_capacity(8 * 1024 * 1024, tmp.as_file())));
+        if dialect.has_header {
+            writer.write_record(headers)?;
+        }
+        for (key, rec) in records {
+            writer.write_record(rec)?;
+            keys.write(key, || Ok(writer.flush()?))?;
+        }
+        writer.flush()?;
+        keys.finish()?;
+    }
+    tmp.persist(chunk_path)?;
+    Ok(())
+}
+
 /// This function performs a parallel merge sort on large files, splitting them into manageable chunks, sorting them based on specified columns, 
 /// and merging them into a single sorted output file.
 ///
@@ -380,8 +552,9 @@ pub fn parallel_split_file_to_chunks(
 ///
 /// # Examples
 ///
-/// ```
+/// ```no_run
 /// use std::path::PathBuf;
+/// use split_merge_hub_demo::parallel_merge::parallel_merge_sort;
 ///
 /// let input_files = vec![PathBuf::from("file1.csv"), PathBuf::from("file2.csv")];
 /// let output_file = PathBuf::from("sorted_output.csv");
@@ -394,38 +567,198 @@ pub fn parallel_merge_sort(
     input_paths: &[PathBuf],
     output_path: impl AsRef<Path>,
     sort_columns: &[&str],
+) -> Result<()> {
+    parallel_merge_sort_with(input_paths, output_path, sort_columns, &RunOptions::default())
+}
+
+/// Same as [`parallel_merge_sort`], reporting progress through `options`.
+pub fn parallel_merge_sort_with(
+    input_paths: &[PathBuf],
+    output_path: impl AsRef<Path>,
+    sort_columns: &[&str],
+    options: &RunOptions,
+) -> Result<()> {
+    check_single_stdin(input_paths)?;
+    let target = Target::Path(output_path.as_ref());
+    target.check(&options.output)?;
+    let sources = input_paths
+        .iter()
+        .map(|path| -> Result<CsvSource> {
+            // Stdin can only be read once, so its header is read up front
+            let stream = if is_stdio(path) { Some(CsvStream::open(path, &options.input_dialect)?) } else { None };
+            Ok(CsvSource { name: path.clone(), stream, projection: None })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    merge_sort_csv(sources, target, sort_columns, options)
+}
+
+/// Same as [`parallel_merge_sort_with`], reading CSV `sources` (each with the same header)
+/// and writing the sorted output, with its header, to `sink`.
+///
+/// Sources are read front to back once, in blocks that are sorted while the next one is
+/// read. Chunks and merge passes still spill to the directories `options.spill` creates.
+/// Sidecars and partitioned output need a file, so `options.output` must not ask for them.
+pub fn parallel_merge_sort_streams<R, W>(
+    sources: Vec<R>,
+    sink: W,
+    sort_columns: &[&str],
+    options: &RunOptions,
+) -> Result<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send,
+{
+    let target = Target::Writer(Box::new(sink));
+    target.check(&options.output)?;
+    let sources = sources
+        .into_iter()
+        .enumerate()
+        .map(|(i, source)| -> Result<CsvSource> {
+            let stream = CsvStream::new(Box::new(source), &options.input_dialect)?;
+            Ok(CsvSource { name: PathBuf::from(format!("stream{:05}", i)), stream: Some(stream), projection: None })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    merge_sort_csv(sources, target, sort_columns, options)
+}
+
+/// Merges new CSV `input_paths` into `existing`, an output already sorted by `sort_columns`.
+///
+/// Only the new inputs are chunked and sorted; they are then merged with `existing` in a
+/// single streaming pass whose result replaces it atomically. Records of `existing` come
+/// before new records with equal keys, and its order is checked while merging. The sort
+/// keys are checked against `<existing>.meta.json`, which is rewritten along with the
+/// merged output. An `existing` that does not exist yet is created by a full sort.
+///
+/// `existing` is read in `options.output_dialect`, so the inputs and `existing` must both
+/// have a header row, or both lack one.
+pub fn parallel_merge_into(
+    existing: &Path,
+    input_paths: &[PathBuf],
+    sort_columns: &[&str],
+    options: &RunOptions,
 ) -> Result<()> {
     if input_paths.is_empty() {
         return Err(anyhow::anyhow!("No input files provided"));
     }
-    let mut input_paths_sorted = input_paths.to_vec();
-    input_paths_sorted.sort_by_key(|p| p.to_string_lossy().to_string());
-    let headers = validate_headers(&input_paths_sorted)?;
+    if input_paths.iter().any(|p| p == existing) {
+        return Err(anyhow::anyhow!("{} is both the merge target and an input", existing.display()));
+    }
+    if !options.select.is_empty() {
+        return Err(anyhow::anyhow!("Selected columns cannot be merged into an existing output"));
+    }
+    if options.columnar.is_some() {
+        return Err(anyhow::anyhow!("Only CSV outputs can be merged into, not Parquet or Arrow IPC"));
+    }
+    let meta = OutputMeta::csv(sort_columns);
+    if !check_merge_into(existing, &meta, &options.output)? {
+        return parallel_merge_sort_with(input_paths, existing, sort_columns, options);
+    }
+    check_single_stdin(input_paths)?;
+    // New records are chunked in the dialect of `existing`, so they can be merged together
+    let dialect = options.output_dialect.as_written();
+    if dialect.has_header != options.input_dialect.has_header {
+        return Err(anyhow::anyhow!("Cannot merge inputs and an existing output that differ in having a header row"));
+    }
+    let mut sources = Vec::new();
+    for path in input_paths {
+        let stream = if is_stdio(path) { Some(CsvStream::open(path, &options.input_dialect)?) } else { None };
+        sources.push(CsvSource { name: path.clone(), stream, projection: None });
+    }
+    // The new inputs are reconciled with the header of `existing`, which must not change
+    let existing_source = CsvSource { name: existing.to_path_buf(), stream: None, projection: None };
+    let mut all_headers = vec![(existing, source_headers(std::slice::from_ref(&existing_source), &dialect)?.remove(0))];
+    let new_headers = source_headers(&sources, &options.input_dialect)?;
+    all_headers.extend(sources.iter().map(|s| s.name.as_path()).zip(new_headers));
+    let (headers, mut projections) = reconcile_headers(&all_headers, &options.schema)?;
+    if !projections.remove(0).is_identity() {
+        return Err(anyhow::anyhow!(
+            "Merging would change the columns of {} to {:?}",
+            existing.display(),
+            headers.iter().collect::<Vec<_>>()
+        ));
+    }
+    for (source, projection) in sources.iter_mut().zip(projections) {
+        source.projection = Some(projection);
+    }
+    info!(
+        "Merging {} new files into {:?}",
+        fmtnum(input_paths.len()),
+        existing
+    );
+    let temp_dir = options.spill.create_dir()?;
+    let mut files = vec![existing.to_path_buf()];
+    files.extend(split_csv_sources(sources, &temp_dir, &headers, sort_columns, &dialect, options)?);
+    let k = files.len().max(2);
+    let options = &RunOptions { presorted: Presorted::Verify, ..options.clone() };
+    merge_csv_chunks(files, Target::Path(existing), sort_columns, k, &dialect, options)
+}
+
+fn merge_sort_csv(
+    mut sources: Vec<CsvSource>,
+    mut target: Target<'_>,
+    sort_columns: &[&str],
+    options: &RunOptions,
+) -> Result<()> {
+    if sources.is_empty() {
+        return Err(anyhow::anyhow!("No input files provided"));
+    }
+    check_columnar(options)?;
+    sources.sort_by_key(|s| s.name.to_string_lossy().to_string());
+    let headers = validate_headers(&mut sources, &options.input_dialect, options)?;
     info!(
         "Validated headers across all input files: {:?}",
         headers.iter().collect::<Vec<_>>()
     );
+    if options.presorted != Presorted::No {
+        if sources.iter().any(|s| s.stream.is_some()) {
+            info!("Streamed inputs cannot be merged as presorted, sorting them in full");
+        } else if sources.iter().any(|s| !s.projection.as_ref().is_some_and(Projection::is_identity)) {
+            info!("Inputs with differing columns cannot be merged as presorted, sorting them in full");
+        } else if options.filter.is_some() {
+            info!("Filtered inputs cannot be merged as presorted, sorting them in full");
+        } else if options.input_dialect.encoding != encoding_rs::UTF_8 {
+            info!("Inputs in {} cannot be merged as presorted, sorting them in full", options.input_dialect.encoding.name());
+        } else {
+            let dialect = &options.input_dialect;
+            let inputs: Vec<PathBuf> = sources.iter().map(|s| s.name.clone()).collect();
+            let k = inputs.len().max(2);
+            let fallback = options.presorted == Presorted::OrSort;
+            let expected = OutputMeta { columns: headers.iter().map(str::to_string).collect(), ..OutputMeta::csv(sort_columns) };
+            if inputs_match_meta(&inputs, &expected, fallback)? {
+                // Records sent to a stream cannot be taken back, so a fallback checks the inputs first
+                let mut unsorted = match fallback && target.is_stream() {
+                    true => find_unsorted_csv(&inputs, &headers, sort_columns, dialect, options)?,
+                    false => None,
+                };
+                if unsorted.is_none() {
+                    info!("Merging {} presorted files without sorting them", fmtnum(inputs.len()));
+                    match target {
+                        Target::Path(path) if fallback => {
+                            match merge_csv_chunks(inputs, Target::Path(path), sort_columns, k, dialect, options) {
+                                Err(e) if e.is::<Unsorted>() => unsorted = e.downcast().ok(),
+                                result => return result,
+                            }
+                            target = Target::Path(path);
+                        }
+                        target => return merge_csv_chunks(inputs, target, sort_columns, k, dialect, options),
+                    }
+                }
+                if let Some(unsorted) = unsorted {
+                    warn!("{}, sorting the inputs in full", unsorted);
+                }
+            }
+        }
+    }
     info!(
         "Starting parallel merge sort for {} files",
-        fmtnum(input_paths_sorted.len())
+        fmtnum(sources.len())
     );
-    let temp_dir = TempDir::new()?;
+    let options = &RunOptions { presorted: Presorted::No, ..options.clone() };
+    let temp_dir = options.spill.create_dir()?;
     let total_start = Instant::now();
     let split_start = Instant::now();
-    let chunk_size_mb = std::env::var("CHUNK_SIZE_MB")
-        .ok()
-        .and_then(|v| v.parse::<usize>().ok())
-        .unwrap_or(256);
-    // Split each input file deterministically and collect chunks in same order
-    let chunk_lists: Vec<_> = input_paths_sorted
-        .iter()
-        .map(|path| {
-            parallel_split_file_to_chunks(path, &temp_dir, sort_columns, chunk_size_mb, &headers)
-        })
-        .collect::<Result<Vec<_>>>()?;
-    let mut all_chunks: Vec<PathBuf> = chunk_lists.into_iter().flatten().collect();
-    // Sort chunk paths for deterministic merge order
-    all_chunks.sort_by_key(|p| p.to_string_lossy().to_string());
+    let chunk_dialect = CsvDialect::default();
+    let all_chunks = split_csv_sources(sources, &temp_dir, &headers, sort_columns, &chunk_dialect, options)?;
     info!("Split phase finished in: {:?}", split_start.elapsed());
 
     info!("Starting merge phase...");
@@ -442,18 +775,70 @@ pub fn parallel_merge_sort(
         Err(_) => 2,
     };
     info!("Using k-way merge: k={}", fmtnum(k));
-    parallel_merge_chunks(all_chunks, output_path.as_ref(), sort_columns, k)?;
+    merge_csv_chunks(all_chunks, target, sort_columns, k, &chunk_dialect, options)?;
     info!("Merge phase finished in: {:?}", merge_start.elapsed());
 
     info!("Total merge+sort finished in: {:?}", total_start.elapsed());
     Ok(())
 }
 
+mod cancel;
+mod columnar;
+mod convert;
+mod csv_key;
+mod csv_stream;
+mod dialect;
+mod filter;
+mod meta;
 mod mtlog;
+mod options;
+mod output;
+mod partition;
+mod pipeline;
+mod presorted;
+mod progress;
+mod schema;
+mod select;
+mod spill;
+mod split;
+mod ranges;
 
 pub use mtlog::{
-    MTLogSortType, MTLogSortColumn, parallel_merge_sort_mtlog, merge_k_files_mtlog
+    MTLogSortType, MTLogSortColumn, parallel_merge_sort_mtlog, merge_k_files_mtlog,
+    parallel_merge_sort_mtlog_with, merge_k_files_mtlog_with, mtlog_sort_key,
+    parallel_merge_sort_mtlog_streams, merge_k_files_mtlog_to_writer, parallel_merge_into_mtlog
+};
+pub use meta::{meta_path, MetaFormat, MetaSortKey, OutputMeta};
+use meta::{check_merge_into, inputs_match_meta};
+pub use cancel::{Cancelled, CancellationToken};
+pub use columnar::{ColumnarCompression, ColumnarFormat, ColumnarOptions, ColumnarWriter, RecordSink};
+use columnar::check_columnar;
+pub use convert::{convert_file, ConvertFormat};
+pub use csv_key::{csv_sort_key, parse_sort_spec, CsvSortColumn, CsvSortType};
+use csv_key::{keys_path, open_keys, read_keys_index, resolve_sort_columns, search_keyed_bounds, KeyWriter, KeyedCsvReader};
+use cancel::CANCEL_CHECK_INTERVAL;
+pub use dialect::{CsvDialect, RecordTerminator};
+pub use filter::{Filter, RecordFilter};
+pub use options::RunOptions;
+pub use output::{is_stdio, sidecar_path, AtomicOutput, OutputOptions};
+use output::{check_single_stdin, Target};
+pub use partition::partition_path;
+use partition::{get_merge_partitions, open_range, pick_splitters, read_line_after, sample_offsets, BoundaryScan};
+pub use presorted::{Presorted, Unsorted};
+pub use schema::{reconcile_headers, Projection, Schema, SchemaMode};
+pub use select::{select_columns, SelectColumn};
+use select::selected_sort_columns;
+use presorted::OrderCheck;
+pub use spill::{SpillStorage, TempDirSpill};
+pub use split::{
+    manifest_path, split_csv, split_mtlog, SplitFile, SplitManifest, SplitOptions, CSV_KEY_TEMPLATE, CSV_TEMPLATE,
+    MTLOG_KEY_TEMPLATE, MTLOG_TEMPLATE,
 };
+pub use progress::{NoopProgress, ProgressPhase, ProgressSink, ProgressUpdate};
+use progress::{ProgressReporter, WorkerProgress};
+use csv_stream::CsvStream;
+use pipeline::{Pipeline, PipelineChunk};
+use ranges::{csv_record_ranges, map_input};
 
 /// K-way parallel merge of sorted chunk files into a single sorted output CSV.
 /// - `chunk_paths`: paths to sorted chunk files (with header)
@@ -466,13 +851,53 @@ pub fn parallel_merge_chunks(
     sort_columns: &[&str],
     k: usize,
 ) -> Result<()> {
-    use std::collections::VecDeque;
-    use std::fs::File;
-    use std::io::BufReader;
+    parallel_merge_chunks_with(chunk_paths, output_path, sort_columns, k, &RunOptions::default())
+}
+
+/// Same as [`parallel_merge_chunks`], reporting progress through `options`.
+///
+/// While there are more than `k` chunks, groups of `k` are merged in parallel into
+/// intermediate files (each with a header, like the chunks). The remaining `<= k`
+/// files are then heap-merged into the output, which gets the header exactly once.
+/// Chunks are read in the default dialect; the output is written in `options.output_dialect`.
+pub fn parallel_merge_chunks_with(
+    chunk_paths: Vec<PathBuf>,
+    output_path: &Path,
+    sort_columns: &[&str],
+    k: usize,
+    options: &RunOptions,
+) -> Result<()> {
+    merge_csv_chunks(chunk_paths, Target::Path(output_path), sort_columns, k, &CsvDialect::default(), options)
+}
+
+/// Same as [`parallel_merge_chunks_with`], writing the merged output to `sink`.
+pub fn parallel_merge_chunks_to_writer<W: Write + Send>(
+    chunk_paths: Vec<PathBuf>,
+    sink: W,
+    sort_columns: &[&str],
+    k: usize,
+    options: &RunOptions,
+) -> Result<()> {
+    merge_csv_chunks(chunk_paths, Target::Writer(Box::new(sink)), sort_columns, k, &CsvDialect::default(), options)
+}
 
+/// Merges `chunk_paths`, read in `dialect` (intermediate files are written in it too),
+/// into `target` in the output dialect.
+fn merge_csv_chunks(
+    chunk_paths: Vec<PathBuf>,
+    target: Target<'_>,
+    sort_columns: &[&str],
+    k: usize,
+    dialect: &CsvDialect,
+    options: &RunOptions,
+) -> Result<()> {
+    target.check(&options.output)?;
+    check_columnar(options)?;
+    let output_path = target.name().to_path_buf();
     if chunk_paths.is_empty() {
         return Ok(());
     }
+    let k = k.max(2);
     info!(
         "[merge] Starting k-way merge: {} chunks -> {:?}",
         fmtnum(chunk_paths.len()),
@@ -482,245 +907,497 @@ pub fn parallel_merge_chunks(
 
     // Read headers from the first chunk
     let first_chunk = &chunk_paths[0];
-    let mut rdr = ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(first_chunk)?;
-    let headers = rdr.headers()?.clone();
+    let mut rdr = dialect.reader().from_path(first_chunk)?;
+    let headers = dialect.read_headers(&mut rdr)?;
     drop(rdr);
 
-    // Prepare output writer
-    let mut wtr = WriterBuilder::new()
-        .has_headers(false)
-        .from_path(output_path)?;
-    // Write header only if this is the final output
-    if output_path.extension().and_then(|s| s.to_str()) == Some("csv") {
-        wtr.write_record(headers.iter())?;
+    // Resolve sort columns
+    let columns = resolve_sort_columns(&headers, sort_columns);
+    if columns.is_empty() {
+        warn!("No sort indices found, output will not be sorted");
     }
 
-    // Determine sort indices
-    let sort_indices = Arc::new(get_sort_column_indices(&headers, sort_columns));
-    if sort_indices.is_empty() {
-        warn!("No sort indices found, output will not be sorted");
+    // Every intermediate pass rewrites all records once, plus the final merge into the output
+    let mut passes = 1u64;
+    let mut remaining = chunk_paths.len();
+    while remaining > k {
+        remaining = remaining.div_ceil(k);
+        passes += 1;
     }
+    let chunk_bytes = total_file_size(&chunk_paths);
+    let total_bytes = chunk_bytes * passes;
 
     // For large merges, do multi-pass k-way merge if chunk count > k
     let mut current_chunks = chunk_paths;
     let mut pass = 0;
     let mut _temp_dirs = Vec::new(); // <-- keep temp dirs alive
-    while current_chunks.len() > 1 {
+    while current_chunks.len() > k {
         pass += 1;
-        let mut next_chunks = Vec::new();
-        let temp_dir = tempfile::tempdir()?;
-        _temp_dirs.push(temp_dir); // <-- keep temp_dir alive
-        let temp_dir_ref = _temp_dirs.last().unwrap();
-        let groups = current_chunks.chunks(k).enumerate();
+        let temp_dir = options.spill.create_dir()?;
         info!(
             "[merge] Merge pass {}: {} groups of up to {} files",
             pass,
-            (current_chunks.len() + k - 1) / k,
+            current_chunks.len().div_ceil(k),
             fmtnum(k)
         );
-
-        for (group_idx, group) in groups {
-            let out_path = temp_dir_ref
-                .path()
-                .join(format!("merge_pass{}_group{}.csv", pass, group_idx));
-            info!(
-                "[merge]   Group {}: merging {} files -> {:?}",
-                group_idx,
-                fmtnum(group.len()),
-                out_path
-            );
-            merge_k_files(group, &out_path, &headers, &sort_indices)?;
-            next_chunks.push(out_path);
-        }
+        let pass_progress = WorkerProgress::new(options.progress.as_ref(), ProgressPhase::Merge, total_bytes);
+        let next_chunks = current_chunks
+            .par_chunks(k)
+            .enumerate()
+            .map(|(group_idx, group)| -> Result<PathBuf> {
+                options.cancel.check()?;
+                let out_path = temp_dir
+                    .path()
+                    .join(format!("merge_pass{}_group{}.csv", pass, group_idx));
+                info!(
+                    "[merge]   Group {}: merging {} files -> {:?}",
+                    group_idx,
+                    fmtnum(group.len()),
+                    out_path
+                );
+                let file = File::create(&out_path)
+                    .with_context(|| format!("Failed to create {}", out_path.display()))?;
+                // Intermediate outputs keep their keys for the next pass
+                let mut keys = KeyWriter::create(&out_path, &columns)?;
+                let mut wtr = dialect
+                    .writer()
+                    .from_writer(keys.counting(BufWriter::with_capacity(8 * 1024 * 1024, file)));
+                if dialect.has_header {
+                    wtr.write_record(headers.iter())?;
+                }
+                // Group merges report through pass_progress once the group is done
+                let mut group_progress = ProgressReporter::new(&NoopProgress, ProgressPhase::Merge, None, 0);
+                merge_k_files(group, &mut wtr, Some(&mut keys), None, &columns, dialect, options, &mut group_progress)?;
+                wtr.flush()?;
+                keys.finish()?;
+                pass_progress.add(total_file_size(group), 0);
+                Ok(out_path)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        _temp_dirs.push(temp_dir); // <-- keep temp_dir alive until the next pass has read it
         current_chunks = next_chunks;
     }
-    // Final merge (or only pass if <= k)
-    let final_chunks = if current_chunks.is_empty() {
-        vec![]
+
+    // Final merge of <= k files; the output only appears at `output_path` on commit
+    options.cancel.check()?;
+    info!(
+        "[merge] Final merge: {} files -> {:?}",
+        fmtnum(current_chunks.len()),
+        output_path
+    );
+    let mut merge_progress = ProgressReporter::new(
+        options.progress.as_ref(),
+        ProgressPhase::Merge,
+        None,
+        total_bytes,
+    );
+    merge_progress.add(chunk_bytes * (passes - 1), 0);
+    let merged = if options.output.partitioned {
+        let partitions = get_merge_partitions();
+        let merged =
+            merge_k_files_partitioned(&current_chunks, target.name(), &headers, &columns, partitions, dialect, options)?;
+        merge_progress.add(total_bytes - chunk_bytes * (passes - 1), merged as u64);
+        merged
     } else {
-        current_chunks
+        let (output_headers, select) = select_columns(&headers, &options.select)?;
+        let output = target.open(&options.output)?;
+        let sink = BufWriter::with_capacity(8 * 1024 * 1024, &output);
+        let mut wtr: Box<dyn RecordSink + '_> = match &options.columnar {
+            Some(columnar) => Box::new(ColumnarWriter::for_csv(&output_headers, columnar, sink)?),
+            None => {
+                let mut wtr = options.output_dialect.writer().from_writer(sink);
+                if options.output_dialect.has_header {
+                    wtr.write_record(output_headers.iter())?;
+                }
+                Box::new(wtr)
+            }
+        };
+        let select = (!select.is_identity()).then_some(&select);
+        let merged = merge_k_files(&current_chunks, &mut *wtr, None, select, &columns, dialect, options, &mut merge_progress)?;
+        wtr.finish()?;
+        drop(wtr);
+        let output_columns = match select {
+            Some(select) => selected_sort_columns(&columns, select),
+            None => columns,
+        };
+        // Sort metadata describes CSV outputs that can be merged into later
+        let meta = options.columnar.is_none().then(|| OutputMeta::for_csv(&output_headers, &output_columns, merged));
+        output.commit(&options.output, meta)?;
+        merged
     };
-    if !final_chunks.is_empty() {
-        info!(
-            "[merge] Final merge: {} files -> {:?}",
-            fmtnum(final_chunks.len()),
-            output_path
-        );
-        merge_k_files(&final_chunks, output_path, &headers, &sort_indices)?;
-    }
-    wtr.flush()?;
+    merge_progress.finish();
     info!(
-        "[merge] Merge complete: {:?} in {:.2?}",
+        "[merge] Merge complete: {} records -> {:?} in {:.2?}",
+        fmtnum(merged),
         output_path,
         merge_start.elapsed()
     );
     Ok(())
 }
 
-/// Merges multiple CSV files into a single sorted output file.
-///
-/// This function takes multiple input CSV file paths, reads their contents,
-/// and merges them into a single CSV file while maintaining the specified
-/// sorting order based on the given sort indices.
+/// Splits every source into sorted chunk files in `temp_dir`, written in `chunk_dialect`
+/// and returned in merge order.
+fn split_csv_sources(
+    sources: Vec<CsvSource>,
+    temp_dir: &TempDir,
+    headers: &StringRecord,
+    sort_columns: &[&str],
+    chunk_dialect: &CsvDialect,
+    options: &RunOptions,
+) -> Result<Vec<PathBuf>> {
+    let chunk_size_mb = std::env::var("CHUNK_SIZE_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(256);
+    // Split each input file deterministically and collect chunks in same order
+    let chunk_lists: Vec<_> = sources
+        .into_iter()
+        .map(|source| {
+            let projection = source.projection.unwrap_or_else(|| Projection::identity(headers.len()));
+            match source.stream {
+                Some(stream) => split_csv_stream_to_chunks(
+                    stream,
+                    &source.name,
+                    temp_dir,
+                    sort_columns,
+                    chunk_size_mb,
+                    headers,
+                    &projection,
+                    chunk_dialect,
+                    options,
+                ),
+                None => split_file_to_chunks(
+                    &source.name,
+                    temp_dir,
+                    sort_columns,
+                    chunk_size_mb,
+                    headers,
+                    &projection,
+                    chunk_dialect,
+                    options,
+                ),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut all_chunks: Vec<PathBuf> = chunk_lists.into_iter().flatten().collect();
+    // Sort chunk paths for deterministic merge order
+    all_chunks.sort_by_key(|p| p.to_string_lossy().to_string());
+    Ok(all_chunks)
+}
+
+/// Checks that every presorted CSV input is sorted, returning the first one found out of order.
+fn find_unsorted_csv(
+    inputs: &[PathBuf],
+    headers: &StringRecord,
+    sort_columns: &[&str],
+    dialect: &CsvDialect,
+    options: &RunOptions,
+) -> Result<Option<Unsorted>> {
+    let columns = resolve_sort_columns(headers, sort_columns);
+    let results: Vec<Result<usize>> = inputs
+        .par_iter()
+        .map(|input| {
+            let mut wtr = WriterBuilder::new().has_headers(false).from_writer(std::io::sink());
+            let mut progress = ProgressReporter::new(&NoopProgress, ProgressPhase::Merge, None, 0);
+            merge_k_files(std::slice::from_ref(input), &mut wtr, None, None, &columns, dialect, options, &mut progress)
+        })
+        .collect();
+    for result in results {
+        if let Err(e) = result {
+            return e.downcast::<Unsorted>().map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Sum of the sizes of `paths`, ignoring files that cannot be stat'ed.
+fn total_file_size(paths: &[PathBuf]) -> u64 {
+    paths
+        .iter()
+        .filter_map(|p| std::fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Streams a heap-based k-way merge of sorted CSV files into `wtr`.
 ///
 /// # Parameters
 ///
-/// - `files`: A slice of `PathBuf` representing the paths to the input CSV files.
-///   Each file is assumed to have a header row that will be skipped during merging.
-/// - `output_path`: A reference to a `Path` where the merged output file should be created.
-///   If the `output_path` has a `.csv` extension, headers will be written into the output.
-/// - `headers`: A reference to a `StringRecord` representing the header row to be written
-///   into the output file if applicable.
-/// - `sort_indices`: An `Arc`-wrapped `Vec<usize>` specifying indices of the fields in each
-///   record to use for sorting the data across files.
+/// - `files`: sorted CSV files in `dialect`; their header rows, if any, are skipped.
+/// - `wtr`: destination writer. Callers write the header to it, if any.
+/// - `keys_out`: if set, receives the sort key of every written record (for intermediate files).
+/// - `select`: if set, maps every record onto the selected output columns before it is written.
+/// - `columns`: the sort columns; keys come from each file's `.keys` sidecar, or are computed.
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` on success, or an error of type `Result` if the merging fails due to
-/// any IO issues or CSV parsing errors.
+/// The number of data records written.
 ///
 /// # Behavior
 ///
-/// 1. Reads each input file and skips its header row.
-/// 2. Initializes a binary heap (`BinaryHeap`) to keep track of the order of records across files.
-/// 3. Pushes the first record of each input file into the heap.
-/// 4. Writes records into the output file in sorted order, using the `sort_indices` as a
-///    reference for comparison.
-/// 5. Continues to fetch and sort records from the input files until all records are processed.
-/// 6. Writes the specified headers to the output file only if the file has a `.csv` extension.
+/// 1. Opens every input file and pushes its first record into a min-heap (`BinaryHeap` of `MergeRecord`).
+/// 2. Pops the smallest record, writes it, and pushes the next record from the same file.
+/// 3. Repeats until every file is exhausted. Records are ordered by their byte sort keys;
+///    ties are broken by file order, so the merge is stable.
 ///
-/// # Errors
-///
-/// Returns an error if:
-/// - Any input file cannot be opened.
-/// - Headers cannot be written to the output file.
-/// - Records cannot be read from the input files or written to the output file.
-/// - The output file path cannot be initialized or flushed.
-///
-/// # Example Usage
-///
-/// ```rust
-/// use csv::StringRecord;
-/// use std::path::{Path, PathBuf};
-/// use std::sync::Arc;
+/// Only one record per input file is held in memory at a time.
 ///
-/// let input_files = vec![PathBuf::from("file1.csv"), PathBuf::from("file2.csv")];
-/// let output_path = Path::new("merged_output.csv");
-/// let headers = StringRecord::from(vec!["Column1", "Column2", "Column3"]);
-/// let sort_indices = Arc::new(vec![0, 1]); // Sort by the first and second columns.
-///
-/// match merge_k_files(&input_files, output_path, &headers, &sort_indices) {
-///     Ok(()) => println!("Files merged successfully!"),
-///     Err(e) => eprintln!("Error merging files: {}", e),
-/// }
-/// ```
-///
-/// # Notes
+/// # Errors
 ///
-/// - The sorting is performed in-memory using a priority queue (`BinaryHeap`) for efficiency.
-/// - Ensure that input files are sorted correctly based on the `sort_indices` before calling this function.
-/// - The function expects all files to have consistent formats (same columns and order).
-/// - Large input files may cause high memory usage as the heap stores a record from each input file.
-fn merge_k_files(
+/// Returns an error if any input cannot be opened or parsed, a record cannot be written,
+/// or the run is cancelled.
+#[allow(clippy::too_many_arguments)]
+fn merge_k_files<S: RecordSink + ?Sized>(
     files: &[PathBuf],
-    output_path: &Path,
-    headers: &StringRecord,
-    sort_indices: &Arc<Vec<usize>>,
-) -> Result<()> {
-    use csv::StringRecord;
-    use std::collections::BinaryHeap;
-    use std::fs::File;
-    use std::io::BufReader;
-
+    wtr: &mut S,
+    keys_out: Option<&mut KeyWriter>,
+    select: Option<&Projection>,
+    columns: &[CsvSortColumn],
+    dialect: &CsvDialect,
+    options: &RunOptions,
+    progress: &mut ProgressReporter<'_>,
+) -> Result<usize> {
     if files.is_empty() {
-        return Ok(());
+        return Ok(0);
     }
-    info!(
-        "[merge] Starting k-way merge: {} chunks -> {:?}",
+    debug!(
+        "[merge] Heap-merging {} files: {:?}",
         fmtnum(files.len()),
-        output_path
+        files
     );
-    let merge_start = Instant::now();
+    let readers = files
+        .iter()
+        .map(|f| -> Result<_> {
+            let file = File::open(f).with_context(|| format!("Failed to open chunk file: {}", f.display()))?;
+            let rdr = dialect
+                .reader()
+                .from_reader(BufReader::with_capacity(get_merge_buf_size(), file));
+            let (keys, key_offset) = match open_keys(f, columns, get_merge_buf_size())? {
+                Some((keys, offset)) => (Some(keys), offset),
+                None => (None, 0),
+            };
+            Ok(KeyedCsvReader::new(rdr, keys, key_offset, columns))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let check = (options.presorted != Presorted::No).then(|| OrderCheck::new(files, None));
+    merge_readers(readers, wtr, keys_out, select, check, options, progress)
+}
 
-    // Read headers from the first chunk
-    let first_chunk = &files[0];
-    let mut rdr = ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(first_chunk)?;
-    let headers = rdr.headers()?.clone();
-    drop(rdr);
+/// Heap-merges sorted keyed CSV readers (positioned at their first data record) into `wtr`,
+/// checking the order of presorted inputs with `check` and writing the `select` columns.
+fn merge_readers<R: std::io::Read, K: std::io::Read, S: RecordSink + ?Sized>(
+    mut readers: Vec<KeyedCsvReader<'_, R, K>>,
+    wtr: &mut S,
+    mut keys_out: Option<&mut KeyWriter>,
+    select: Option<&Projection>,
+    mut check: Option<OrderCheck<'_>>,
+    options: &RunOptions,
+    progress: &mut ProgressReporter<'_>,
+) -> Result<usize> {
+    use std::collections::BinaryHeap;
 
-    // Prepare output writer
-    let mut wtr = csv::WriterBuilder::new()
-        .has_headers(false)
-        .from_path(output_path)?;
-    // Write header only if this is the final output
-    if output_path.extension().and_then(|s| s.to_str()) == Some("csv") {
-        wtr.write_record(headers.iter())?;
+    let mut heap = BinaryHeap::with_capacity(readers.len());
+    for (source_index, rdr) in readers.iter_mut().enumerate() {
+        let mut record = StringRecord::new();
+        let mut key = Vec::new();
+        if rdr.read(&mut record, &mut key)? {
+            heap.push(MergeRecord { key, record, source_index });
+        }
     }
 
-    // Determine sort indices
-    let sort_indices = Arc::clone(sort_indices);
-    if sort_indices.is_empty() {
-        warn!("No sort indices found, output will not be sorted");
+    let mut merged = 0usize;
+    let mut selected = StringRecord::new();
+    while let Some(MergeRecord { mut key, mut record, source_index }) = heap.pop() {
+        if let Some(check) = check.as_mut() {
+            check.next(&key, source_index, || record.iter().collect::<Vec<_>>().join(","))?;
+        }
+        match select {
+            Some(select) => {
+                select.apply(&record, &mut selected);
+                wtr.write_record(&selected)?;
+            }
+            None => wtr.write_record(&record)?,
+        }
+        if let Some(keys) = keys_out.as_deref_mut() {
+            keys.write(&key, || wtr.flush())?;
+        }
+        merged += 1;
+        progress.add((record.as_slice().len() + record.len()) as u64, 1);
+        if merged.is_multiple_of(CANCEL_CHECK_INTERVAL) {
+            options.cancel.check()?;
+        }
+        // Reuse the popped record's buffers for the next record of the same file
+        if readers[source_index].read(&mut record, &mut key)? {
+            heap.push(MergeRecord { key, record, source_index });
+        }
     }
+    Ok(merged)
+}
 
-    // For large merges, do multi-pass k-way merge if chunk count > k
-    let mut current_chunks = files.to_vec();
-    let mut pass = 0;
-    let mut _temp_dirs = Vec::new(); // <-- keep temp dirs alive
-    while current_chunks.len() > 1 {
-        pass += 1;
-        let mut next_chunks = Vec::new();
-        let temp_dir = tempfile::tempdir()?;
-        _temp_dirs.push(temp_dir); // <-- keep temp_dir alive
-        let temp_dir_ref = _temp_dirs.last().unwrap();
-        let groups = current_chunks.chunks(2).enumerate();
-        info!(
-            "[merge] Merge pass {}: {} groups of up to {} files",
-            pass,
-            (current_chunks.len() + 1) / 2,
-            2
-        );
+/// Merges `files` by key range on all rayon workers (see `partition.rs`) into one
+/// partition file per key range next to `output_path`, each with the header.
+fn merge_k_files_partitioned(
+    files: &[PathBuf],
+    output_path: &Path,
+    headers: &StringRecord,
+    columns: &[CsvSortColumn],
+    partitions: usize,
+    dialect: &CsvDialect,
+    options: &RunOptions,
+) -> Result<usize> {
+    let timer = Instant::now();
 
-        for (group_idx, group) in groups {
-            let out_path = temp_dir_ref
-                .path()
-                .join(format!("merge_pass{}_group{}.csv", pass, group_idx));
-            info!(
-                "[merge]   Group {}: merging {} files -> {:?}",
-                group_idx,
-                fmtnum(group.len()),
-                out_path
-            );
-            merge_k_files(group, &out_path, &headers, &sort_indices)?;
-            next_chunks.push(out_path);
+    // 1. Sample keys at evenly spaced offsets of every chunk
+    let samples = files
+        .par_iter()
+        .map(|f| -> Result<Vec<Vec<u8>>> {
+            let mut rdr = dialect.reader().from_path(f)?;
+            let data_start = data_start(&mut rdr, dialect)?;
+            let len = std::fs::metadata(f)?.len();
+            let mut file = File::open(f)?;
+            let mut samples = Vec::new();
+            for offset in sample_offsets(data_start, len, partitions) {
+                // A sample landing inside a quoted multi-line field only skews the split, never the result
+                let Some(line) = read_line_after(&mut file, data_start, offset)? else { continue };
+                let mut record = StringRecord::new();
+                let mut line_rdr = dialect.reader().has_headers(false).from_reader(line.as_slice());
+                if line_rdr.read_record(&mut record).unwrap_or(false) && record.len() == headers.len() {
+                    samples.push(csv_sort_key(&record, columns));
+                }
+            }
+            Ok(samples)
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+    let splitters = pick_splitters(samples, partitions, Ord::cmp);
+    options.cancel.check()?;
+
+    // 2. Find where every key range starts in every chunk and in its keys sidecar
+    let bounds = files
+        .par_iter()
+        .map(|f| -> Result<(Vec<(u64, u64)>, bool)> {
+            let mut rdr = dialect
+                .reader()
+                .from_reader(BufReader::with_capacity(get_merge_buf_size(), File::open(f)?));
+            let data_start = data_start(&mut rdr, dialect)?;
+            let len = std::fs::metadata(f)?.len();
+            let (keys, key_offset) = match open_keys(f, columns, get_merge_buf_size())? {
+                Some((keys, offset)) => (Some(keys), offset),
+                None => (None, 0),
+            };
+            let has_keys = keys.is_some();
+            let keys_len = if has_keys { std::fs::metadata(keys_path(f))?.len() } else { 0 };
+            if has_keys {
+                if let Some(index) = read_keys_index(f)? {
+                    let (start, end) = ((data_start, key_offset), (len, keys_len));
+                    let bounds = search_keyed_bounds(f, &index, start, end, &splitters, columns, dialect)?;
+                    return Ok((bounds, true));
+                }
+            }
+            // Chunks without a keys index are scanned up to the last splitter
+            let mut rdr = KeyedCsvReader::new(rdr, keys, key_offset, columns);
+            let mut scan = BoundaryScan::new(&splitters, (data_start, key_offset));
+            let mut record = StringRecord::new();
+            let mut key = Vec::new();
+            let mut scanned = 0usize;
+            while !scan.done() {
+                let key_offset = rdr.key_offset();
+                if !rdr.read(&mut record, &mut key)? {
+                    break;
+                }
+                let offset = record.position().map(|p| p.byte()).unwrap_or(0);
+                scan.record((offset, key_offset), &key, Ord::cmp);
+                scanned += 1;
+                if scanned.is_multiple_of(CANCEL_CHECK_INTERVAL) {
+                    options.cancel.check()?;
+                }
+            }
+            Ok((scan.finish((len, keys_len)), has_keys))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let ranges = splitters.len() + 1;
+    info!(
+        "[merge] Partitioned merge: {} key ranges over {} files (split in {:.2?})",
+        fmtnum(ranges),
+        fmtnum(files.len()),
+        timer.elapsed()
+    );
+
+    // 3. Merge every key range on its own worker
+    let (output_headers, select) = select_columns(headers, &options.select)?;
+    let select = (!select.is_identity()).then_some(&select);
+    let output_columns = match select {
+        Some(select) => selected_sort_columns(columns, select),
+        None => columns.to_vec(),
+    };
+    let merge_range = |p: usize, out: &File| -> Result<usize> {
+        options.cancel.check()?;
+        let range_bytes: u64 = bounds.iter().map(|(b, _)| b[p + 1].0 - b[p].0).sum();
+        let readers = files
+            .iter()
+            .zip(&bounds)
+            .map(|(f, (b, has_keys))| -> Result<_> {
+                let range = open_range(f, b[p].0, b[p + 1].0, get_merge_buf_size())?;
+                let rdr = dialect.reader().has_headers(false).from_reader(range);
+                let keys = if *has_keys {
+                    Some(open_range(&keys_path(f), b[p].1, b[p + 1].1, get_merge_buf_size())?)
+                } else {
+                    None
+                };
+                Ok(KeyedCsvReader::new(rdr, keys, b[p].1, columns))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut wtr = options
+            .output_dialect
+            .writer()
+            .from_writer(BufWriter::with_capacity(get_merge_buf_size(), out));
+        if options.output_dialect.has_header {
+            wtr.write_record(output_headers.iter())?;
         }
-        current_chunks = next_chunks;
-    }
-    // Final merge (or only pass if <= k)
-    let final_chunks = if current_chunks.is_empty() {
-        vec![]
-    } else {
-        current_chunks
+        let mut progress = ProgressReporter::new(options.progress.as_ref(), ProgressPhase::Merge, Some(p), range_bytes);
+        let check = (options.presorted != Presorted::No)
+            .then(|| OrderCheck::new(files, p.checked_sub(1).map(|i| splitters[i].as_slice())));
+        let merged = merge_readers(readers, &mut wtr, None, select, check, options, &mut progress)?;
+        wtr.flush()?;
+        progress.finish();
+        Ok(merged)
     };
-    if !final_chunks.is_empty() {
-        info!(
-            "[merge] Final merge: {} files -> {:?}",
-            fmtnum(final_chunks.len()),
-            output_path
-        );
-        merge_k_files(&final_chunks, output_path, &headers, &sort_indices)?;
+
+    let outputs = (0..ranges)
+        .into_par_iter()
+        .map(|p| -> Result<(AtomicOutput, usize)> {
+            let output = AtomicOutput::create(&partition_path(output_path, p))?;
+            let merged = merge_range(p, output.file())?;
+            Ok((output, merged))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Publish only once every partition has been merged
+    let mut merged = 0;
+    for (p, (output, count)) in outputs.into_iter().enumerate() {
+        output.commit_with_meta(&options.output, Some(OutputMeta::for_csv(&output_headers, &output_columns, count)))?;
+        info!("[merge]   Partition {}: {} records -> {:?}", p, fmtnum(count), partition_path(output_path, p));
+        merged += count;
     }
-    wtr.flush()?;
-    info!(
-        "[merge] Merge complete: {:?} in {:.2?}",
-        output_path,
-        merge_start.elapsed()
-    );
-    Ok(())
+    Ok(merged)
+}
+
+/// Offset of the first data record of a file read by `rdr`, past its header if it has one.
+fn data_start<R: Read>(rdr: &mut csv::Reader<R>, dialect: &CsvDialect) -> Result<u64> {
+    if !dialect.has_header {
+        return Ok(0);
+    }
+    rdr.headers()?;
+    Ok(rdr.position().byte())
+}
+
+fn get_merge_buf_size() -> usize {
+    std::env::var("MERGE_BUF_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .map(|mb| mb * 1024 * 1024)
+        .unwrap_or(8 * 1024 * 1024)
 }
 
 // --- Example: format_number helper ---
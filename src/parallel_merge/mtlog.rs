@@ -1,14 +1,35 @@
 // --- MTLog Parallel Merge Implementation ---
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use log::{debug, error, info, warn};
 use num_format::{Locale, ToFormattedString};
 use rayon::prelude::*;
 use std::cmp::Ordering;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Mutex;
 use std::time::Instant;
+use tempfile::TempDir;
+
+use super::cancel::CANCEL_CHECK_INTERVAL;
+use super::columnar::{check_columnar, ColumnarOptions, ColumnarWriter, MTLogColumns};
+use super::filter::Filter;
+use super::meta::{check_merge_into, inputs_match_meta, OutputMeta};
+use super::options::RunOptions;
+use super::output::{check_single_stdin, is_stdio, AtomicOutput, OutputOptions, Target};
+use super::partition::{
+    get_merge_partitions, partition_path, pick_splitters, read_line_after, sample_offsets,
+    search_line_bounds,
+};
+use super::pipeline::{Pipeline, PipelineChunk, PipelineStats};
+use super::presorted::{OrderCheck, Presorted, Unsorted};
+use super::progress::{NoopProgress, ProgressPhase, ProgressReporter, WorkerProgress};
+use super::ranges::{line_ranges, map_input};
+use super::select::select_mtlog_fields;
+use crate::mt_log::mt_log_record::{MTLogField, FIELDS, TOTAL_LENGTH};
+use crate::mt_log::reader::{supports_ranges, MTLogReader};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MTLogSortType {
@@ -48,53 +69,114 @@ fn get_log_interval() -> usize {
         .unwrap_or(500_000)
 }
 
-fn get_mtlog_field(line: &str, col: usize) -> String {
-    // Adjust offsets as needed for your MTLogRecord
-    const OFFSETS: &[(usize, usize)] = &[
-        (0,8), (8,6), (14,7), (21,4), (25,1), (26,8), (34,1), (35,1)
-    ];
-    if col >= OFFSETS.len() {
-        return String::new();
-    }
-    let (start, len) = OFFSETS[col];
-    line.get(start..start+len).unwrap_or("").trim().to_string()
+/// Normalized binary sort key for an MT log line.
+///
+/// Comparing two keys with `Ord` for `[u8]` orders the lines by `sort_columns` (indices
+/// into `mt_log_record::FIELDS`; an index past the end sorts as empty), so the
+/// key is extracted once per record instead of re-slicing and re-parsing fields on
+/// every comparison. Text columns (`Date`, `Time`, `Str`) are trimmed, have `0x00`
+/// escaped as `0x00 0xFF` and end in `0x00 0x00`, so a shorter value sorts before any
+/// value it prefixes. `Num` columns are big-endian `u64`s (unparsable values count as 0).
+pub fn mtlog_sort_key(line: &[u8], sort_columns: &[MTLogSortColumn]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(sort_columns.len() * 10);
+    encode_mtlog_sort_key(line, sort_columns, &mut key);
+    key
 }
 
-fn compare_mtlog_by_columns(a: &str, b: &str, sort_columns: &[MTLogSortColumn]) -> Ordering {
+/// Appends the [`mtlog_sort_key`] of `line` to `key`, so callers can reuse the buffer.
+fn encode_mtlog_sort_key(line: &[u8], sort_columns: &[MTLogSortColumn], key: &mut Vec<u8>) {
     for col in sort_columns {
-        let (v1, v2) = (get_mtlog_field(a, col.index), get_mtlog_field(b, col.index));
-        let ord = match col.col_type {
-            MTLogSortType::Date | MTLogSortType::Time => v1.cmp(&v2),
-            MTLogSortType::Num => v1.parse::<u64>().unwrap_or(0).cmp(&v2.parse::<u64>().unwrap_or(0)),
-            MTLogSortType::Str => v1.cmp(&v2),
-        };
-        if ord != Ordering::Equal {
-            return ord;
+        let field = FIELDS.get(col.index).map_or(&[][..], |field| field.bytes(line));
+        match col.col_type {
+            MTLogSortType::Num => {
+                let num = std::str::from_utf8(field).ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+                key.extend_from_slice(&num.to_be_bytes());
+            }
+            MTLogSortType::Date | MTLogSortType::Time | MTLogSortType::Str => {
+                for &b in field {
+                    key.push(b);
+                    if b == 0 {
+                        key.push(0xFF);
+                    }
+                }
+                key.extend_from_slice(&[0, 0]);
+            }
         }
     }
-    Ordering::Equal
 }
 
+/// Heap entry for the current record of input `idx`; the record itself stays in its reader.
 #[derive(Eq)]
-struct MTLogHeapItem<'a> {
-    line: String,
+struct MTLogHeapItem {
+    key: Vec<u8>,
     idx: usize,
-    sort_columns: &'a [MTLogSortColumn],
 }
 
-impl<'a> Ord for MTLogHeapItem<'a> {
+impl Ord for MTLogHeapItem {
     fn cmp(&self, other: &Self) -> Ordering {
-        compare_mtlog_by_columns(&other.line, &self.line, self.sort_columns)
+        // Reversed for a min-heap; ties go to the earlier input so the merge is stable
+        other.key.cmp(&self.key).then_with(|| other.idx.cmp(&self.idx))
     }
 }
-impl<'a> PartialOrd for MTLogHeapItem<'a> {
+impl PartialOrd for MTLogHeapItem {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
-impl<'a> PartialEq for MTLogHeapItem<'a> {
+impl PartialEq for MTLogHeapItem {
     fn eq(&self, other: &Self) -> bool {
-        self.line == other.line
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+/// A chunk of records held in one contiguous buffer, with their sort keys in another,
+/// so building and sorting a chunk does not allocate per record.
+#[derive(Default)]
+struct MTLogChunk {
+    data: Vec<u8>,
+    keys: Vec<u8>,
+    /// `(key range, record range)` into `keys` and `data`.
+    entries: Vec<((usize, usize), (usize, usize))>,
+}
+
+impl MTLogChunk {
+    fn push(&mut self, record: &[u8], sort_columns: &[MTLogSortColumn]) {
+        let key_start = self.keys.len();
+        encode_mtlog_sort_key(record, sort_columns, &mut self.keys);
+        let data_start = self.data.len();
+        self.data.extend_from_slice(record);
+        self.entries.push(((key_start, self.keys.len()), (data_start, self.data.len())));
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Sorts by key; the sort is stable, so records with equal keys keep their input order.
+    fn sort(&mut self) {
+        let keys = &self.keys;
+        self.entries
+            .par_sort_by(|a, b| keys[a.0 .0..a.0 .1].cmp(&keys[b.0 .0..b.0 .1]));
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        for &(_, (start, end)) in &self.entries {
+            writer.write_all(&self.data[start..end])?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl PipelineChunk for MTLogChunk {
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.keys.clear();
+        self.entries.clear();
     }
 }
 
@@ -102,8 +184,192 @@ pub fn merge_k_files_mtlog(
     files: &[PathBuf],
     output_path: &Path,
     sort_columns: &[MTLogSortColumn],
+) -> Result<()> {
+    merge_k_files_mtlog_with(files, output_path, sort_columns, &RunOptions::default())
+}
+
+/// Same as [`merge_k_files_mtlog_with`], writing the merged records to `sink`.
+///
+/// A stream cannot be re-read, so the merged records are not validated.
+pub fn merge_k_files_mtlog_to_writer<W: Write + Send>(
+    files: &[PathBuf],
+    sink: W,
+    sort_columns: &[MTLogSortColumn],
+    options: &RunOptions,
+) -> Result<()> {
+    merge_mtlog_files(files, Target::Writer(Box::new(sink)), sort_columns, options)
+}
+
+/// Same as [`merge_k_files_mtlog`], reporting progress through `options`.
+///
+/// With `options.output.partitioned`, the merge is split into `MERGE_PARTITIONS` key
+/// ranges (default: the rayon thread count) that are merged in parallel, each into its
+/// own partition file.
+///
+/// The output is written to a sibling temp file and renamed into place only after
+/// the record count and sort order validation pass, together with the sidecars
+/// requested in `options.output`.
+pub fn merge_k_files_mtlog_with(
+    files: &[PathBuf],
+    output_path: &Path,
+    sort_columns: &[MTLogSortColumn],
+    options: &RunOptions,
+) -> Result<()> {
+    merge_mtlog_files(files, Target::Path(output_path), sort_columns, options)
+}
+
+fn merge_mtlog_files(
+    files: &[PathBuf],
+    target: Target<'_>,
+    sort_columns: &[MTLogSortColumn],
+    options: &RunOptions,
+) -> Result<()> {
+    target.check(&options.output)?;
+    check_single_stdin(files)?;
+    check_columnar(options)?;
+    if let Some(columnar) = &options.columnar {
+        return merge_mtlog_files_to_columnar(files, target, sort_columns, columnar, options);
+    }
+    if !options.select.is_empty() {
+        return merge_mtlog_files_to_csv(files, target, sort_columns, options);
+    }
+    if options.output.partitioned {
+        // Key ranges are found by byte offset, which compressed and streamed inputs do not have
+        if !files.iter().all(|f| !is_stdio(f) && supports_ranges(f)) {
+            return Err(anyhow::anyhow!("Partitioned output needs uncompressed, regular input files"));
+        }
+        return merge_k_files_mtlog_partitioned(files, target.name(), sort_columns, get_merge_partitions(), options);
+    }
+    merge_k_files_mtlog_worker(files, target, sort_columns, options, None, &options.output)
+}
+
+/// Heap-merges `files` straight into `target` as CSV in `options.output_dialect`, one
+/// column per `options.select` field.
+///
+/// The order of presorted inputs is checked while merging, but the output is not re-read.
+fn merge_mtlog_files_to_csv(
+    files: &[PathBuf],
+    target: Target<'_>,
+    sort_columns: &[MTLogSortColumn],
+    options: &RunOptions,
+) -> Result<()> {
+    let timer = Instant::now();
+    let (headers, fields) = select_mtlog_fields(&options.select)?;
+    if options.output.partitioned {
+        return Err(anyhow::anyhow!("Partitioned output cannot be combined with selected MT log fields"));
+    }
+    let output_path = target.name().to_path_buf();
+    info!("[mtlog] [MERGE] Starting k-way merge of {} files into {:?} as CSV", files.len().to_formatted_string(&Locale::en), output_path);
+    let output = target.open(&options.output)?;
+    let dialect = &options.output_dialect;
+    let mut wtr = dialect.writer().from_writer(BufWriter::with_capacity(get_merge_buf_size(), &output));
+    if dialect.has_header {
+        wtr.write_record(headers.iter())?;
+    }
+    let mut writer = MTLogCsvWriter { wtr, fields };
+    let readers = files
+        .iter()
+        .map(|f| open_mtlog_input(f))
+        .collect::<Result<Vec<_>>>()?;
+    let input_bytes: u64 = files
+        .iter()
+        .filter_map(|f| std::fs::metadata(f).ok())
+        .map(|m| m.len())
+        .sum();
+    let mut merge_progress = ProgressReporter::new(options.progress.as_ref(), ProgressPhase::Merge, None, input_bytes);
+    let check = (options.presorted != Presorted::No).then(|| OrderCheck::new(files, None));
+    let merged_count = merge_mtlog_readers(readers, &mut writer, sort_columns, check, options, &mut merge_progress)?;
+    writer.wtr.flush()?;
+    drop(writer);
+    merge_progress.finish();
+    info!("[mtlog] [MERGE] Merge finished: {} records as {} CSV fields -> {:?} in {:.2?}", merged_count.to_formatted_string(&Locale::en), headers.len(), output_path, timer.elapsed());
+    // The CSV is not an MT log sorted by field indices, so it gets no sort metadata
+    output.commit(&options.output, None)
+}
+
+/// Receives the records of a final MT log merge: a writer, one record per line, an
+/// [`MTLogCsvWriter`] or a [`ColumnarWriter`].
+pub(crate) trait MTLogSink {
+    fn write_mtlog(&mut self, record: &[u8]) -> Result<()>;
+}
+
+impl<W: Write> MTLogSink for W {
+    fn write_mtlog(&mut self, record: &[u8]) -> Result<()> {
+        self.write_all(record)?;
+        self.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// Writes the selected fields of each merged MT log record as a CSV row.
+struct MTLogCsvWriter<W: Write> {
+    wtr: csv::Writer<W>,
+    fields: Vec<&'static MTLogField>,
+}
+
+impl<W: Write> MTLogSink for MTLogCsvWriter<W> {
+    fn write_mtlog(&mut self, record: &[u8]) -> Result<()> {
+        self.wtr.write_record(self.fields.iter().map(|field| field.bytes(record)))?;
+        Ok(())
+    }
+}
+
+/// Heap-merges `files` straight into a Parquet or Arrow IPC `target`, one column per
+/// `options.select` field (all fields if none are selected).
+///
+/// The order of presorted inputs is checked while merging, but the output is not re-read.
+fn merge_mtlog_files_to_columnar(
+    files: &[PathBuf],
+    target: Target<'_>,
+    sort_columns: &[MTLogSortColumn],
+    columnar: &ColumnarOptions,
+    options: &RunOptions,
+) -> Result<()> {
+    let timer = Instant::now();
+    let columns = match options.select.is_empty() {
+        true => MTLogColumns::all(),
+        false => {
+            let (headers, fields) = select_mtlog_fields(&options.select)?;
+            MTLogColumns::new(&headers, fields)
+        }
+    };
+    let output_path = target.name().to_path_buf();
+    info!("[mtlog] [MERGE] Starting k-way merge of {} files into {:?} as {:?}", files.len().to_formatted_string(&Locale::en), output_path, columnar.format);
+    let output = target.open(&options.output)?;
+    let sink = BufWriter::with_capacity(get_merge_buf_size(), &output);
+    let mut writer = ColumnarWriter::for_mtlog(columns, columnar, sink)?;
+    let readers = files
+        .iter()
+        .map(|f| open_mtlog_input(f))
+        .collect::<Result<Vec<_>>>()?;
+    let input_bytes: u64 = files
+        .iter()
+        .filter_map(|f| std::fs::metadata(f).ok())
+        .map(|m| m.len())
+        .sum();
+    let mut merge_progress = ProgressReporter::new(options.progress.as_ref(), ProgressPhase::Merge, None, input_bytes);
+    let check = (options.presorted != Presorted::No).then(|| OrderCheck::new(files, None));
+    let merged_count = merge_mtlog_readers(readers, &mut writer, sort_columns, check, options, &mut merge_progress)?;
+    writer.finish()?;
+    drop(writer);
+    merge_progress.finish();
+    info!("[mtlog] [MERGE] Merge finished: {} records as {:?} -> {:?} in {:.2?}", merged_count.to_formatted_string(&Locale::en), columnar.format, output_path, timer.elapsed());
+    // A columnar output is not an MT log that could be merged into, so it gets no sort metadata
+    output.commit(&options.output, None)
+}
+
+/// K-way merge reporting progress as `worker` (the parallel group index, if any).
+/// Intermediate group merges pass default `sidecars` so only the final output gets them.
+fn merge_k_files_mtlog_worker(
+    files: &[PathBuf],
+    target: Target<'_>,
+    sort_columns: &[MTLogSortColumn],
+    options: &RunOptions,
+    worker: Option<usize>,
+    sidecars: &OutputOptions,
 ) -> Result<()> {
     let merge_timer = Instant::now();
+    let output_path = &target.name().to_path_buf();
     info!("[mtlog] [MERGE] Starting k-way merge of {} files into {:?}", files.len().to_formatted_string(&Locale::en), output_path);
     for (i, f) in files.iter().enumerate() {
         if !f.exists() {
@@ -114,171 +380,587 @@ pub fn merge_k_files_mtlog(
             debug!("[mtlog] [MERGE] Input file #{}: {}", i + 1, f.display());
         }
     }
-    let mut writer = BufWriter::with_capacity(get_merge_buf_size(), File::create(output_path)?);
-    let mut readers: Vec<_> = files
+    let output = target.open(sidecars)?;
+    let mut writer = BufWriter::with_capacity(get_merge_buf_size(), &output);
+    let readers = files
         .iter()
-        .map(|f| BufReader::with_capacity(get_merge_buf_size(), File::open(f).expect("Failed to open chunk file")))
-        .collect();
-    let mut heap = std::collections::BinaryHeap::new();
+        .map(|f| open_mtlog_input(f))
+        .collect::<Result<Vec<_>>>()?;
+    let input_bytes: u64 = files
+        .iter()
+        .filter_map(|f| std::fs::metadata(f).ok())
+        .map(|m| m.len())
+        .sum();
+    let mut merge_progress =
+        ProgressReporter::new(options.progress.as_ref(), ProgressPhase::Merge, worker, input_bytes);
+    let check = (options.presorted != Presorted::No).then(|| OrderCheck::new(files, None));
+    let merged_count = match merge_mtlog_readers(readers, &mut writer, sort_columns, check, options, &mut merge_progress) {
+        Ok(count) => count,
+        Err(e) => {
+            if options.cancel.is_cancelled() {
+                warn!("[mtlog] [MERGE] Cancelled, discarding partial output {:?}", output.path().unwrap_or(output_path));
+            }
+            return Err(e);
+        }
+    };
+    writer.flush()?;
+    drop(writer);
+    merge_progress.finish();
+    let elapsed = merge_timer.elapsed();
+    match output.path() {
+        Some(path) => {
+            let output_size = std::fs::metadata(path)?.len();
+            info!("[mtlog] [MERGE] Merge finished: {} records -> {:?} ({} bytes) in {:.2?}", merged_count.to_formatted_string(&Locale::en), output_path, output_size.to_formatted_string(&Locale::en), elapsed);
+            // Validate while the output is still at its temp path
+            validate_mtlog_output(path, output_path, merged_count, sort_columns, options, worker)?;
+        }
+        None => {
+            info!("[mtlog] [MERGE] Merge finished: {} records -> {:?} in {:.2?} (streamed, not re-read for validation)", merged_count.to_formatted_string(&Locale::en), output_path, elapsed);
+        }
+    }
+    // Group merges of a multi-pass sort are intermediate files, only the final merge gets metadata
+    let meta = worker.is_none().then(|| OutputMeta::for_mtlog(sort_columns, merged_count));
+    output.commit(sidecars, meta)?;
+    Ok(())
+}
+
+/// Heap-merges sorted MT log readers into `writer`, returning the number of records written.
+/// The order of presorted inputs is checked with `check`.
+fn merge_mtlog_readers<W: MTLogSink>(
+    mut readers: Vec<MTLogReader>,
+    writer: &mut W,
+    sort_columns: &[MTLogSortColumn],
+    mut check: Option<OrderCheck<'_>>,
+    options: &RunOptions,
+    merge_progress: &mut ProgressReporter<'_>,
+) -> Result<usize> {
+    let merge_timer = Instant::now();
+    let mut heap = std::collections::BinaryHeap::with_capacity(readers.len());
     for (idx, rdr) in readers.iter_mut().enumerate() {
-        let mut buf = String::new();
-        if rdr.read_line(&mut buf)? > 0 {
-            let line = buf.trim_end_matches('\n').to_string();
-            heap.push(MTLogHeapItem { line, idx, sort_columns });
+        if rdr.next_record()? {
+            let mut key = Vec::new();
+            encode_mtlog_sort_key(rdr.record(), sort_columns, &mut key);
+            heap.push(MTLogHeapItem { key, idx });
         }
     }
     let mut merged_count = 0usize;
     let mut last_log_group = 0usize;
     let log_interval = get_log_interval();
-    while let Some(MTLogHeapItem { line, idx, .. }) = heap.pop() {
-        writeln!(writer, "{}", line)?;
+    while let Some(MTLogHeapItem { mut key, idx }) = heap.pop() {
+        let rdr = &mut readers[idx];
+        let record = rdr.record();
+        if let Some(check) = check.as_mut() {
+            check.next(&key, idx, || String::from_utf8_lossy(record).into_owned())?;
+        }
+        writer.write_mtlog(record)?;
         merged_count += 1;
+        merge_progress.add(record.len() as u64 + 1, 1);
+        if merged_count.is_multiple_of(CANCEL_CHECK_INTERVAL) {
+            options.cancel.check()?;
+        }
         let current_group = merged_count / log_interval;
         if current_group > last_log_group {
             let elapsed = merge_timer.elapsed();
             info!("[mtlog] [MERGE] Merged {} records so far... elapsed: {:.2?}", merged_count.to_formatted_string(&Locale::en), elapsed);
             last_log_group = current_group;
         }
-        let rdr = &mut readers[idx];
-        let mut buf = String::new();
-        if rdr.read_line(&mut buf)? > 0 {
-            let next_line = buf.trim_end_matches('\n').to_string();
-            heap.push(MTLogHeapItem { line: next_line, idx, sort_columns });
+        // Reuse the popped key's buffer for the next record of the same input
+        if rdr.next_record()? {
+            key.clear();
+            encode_mtlog_sort_key(rdr.record(), sort_columns, &mut key);
+            heap.push(MTLogHeapItem { key, idx });
         }
     }
-    writer.flush()?;
-    let elapsed = merge_timer.elapsed();
-    let output_size = std::fs::metadata(output_path)?.len();
-    info!("[mtlog] [MERGE] Merge finished: {} records -> {:?} ({} bytes) in {:.2?}", merged_count.to_formatted_string(&Locale::en), output_path, output_size.to_formatted_string(&Locale::en), elapsed);
-    // --- Validation: count lines in output ---
-    let file = File::open(output_path)?;
-    let reader = BufReader::new(file);
+    Ok(merged_count)
+}
+
+/// Re-reads a merged output at `path` (published later as `output_path`) and checks
+/// that it holds `merged_count` lines in sort order.
+fn validate_mtlog_output(
+    path: &Path,
+    output_path: &Path,
+    merged_count: usize,
+    sort_columns: &[MTLogSortColumn],
+    options: &RunOptions,
+    worker: Option<usize>,
+) -> Result<()> {
+    let timer = Instant::now();
+    let output_size = std::fs::metadata(path)?.len();
+    let mut reader = MTLogReader::open(path)?;
     let mut line_count = 0usize;
-    let mut prev_line: Option<String> = None;
+    let (mut prev_key, mut key) = (Vec::new(), Vec::new());
     let mut sorted = true;
-    for line in reader.lines() {
-        let line = line?;
-        if let Some(prev) = &prev_line {
-            if compare_mtlog_by_columns(prev, &line, sort_columns) == Ordering::Greater {
-                error!("[mtlog][validate] Output is NOT sorted at line {}!", line_count + 1);
-                sorted = false;
-                break;
-            }
+    let mut validate_progress =
+        ProgressReporter::new(options.progress.as_ref(), ProgressPhase::Validate, worker, output_size);
+    while reader.next_record()? {
+        let record = reader.record();
+        validate_progress.add(record.len() as u64 + 1, 1);
+        if line_count.is_multiple_of(CANCEL_CHECK_INTERVAL) {
+            options.cancel.check()?;
+        }
+        key.clear();
+        encode_mtlog_sort_key(record, sort_columns, &mut key);
+        if line_count > 0 && prev_key > key {
+            error!("[mtlog][validate] Output is NOT sorted at line {}!", line_count + 1);
+            sorted = false;
+            break;
         }
-        prev_line = Some(line.clone());
+        std::mem::swap(&mut prev_key, &mut key);
         line_count += 1;
     }
+    validate_progress.finish();
     info!("[mtlog][validate] Output line count: {}", line_count.to_formatted_string(&Locale::en));
     if sorted {
         info!("[mtlog][validate] Output is sorted correctly.");
     }
-    info!("[mtlog][SUMMARY] Merge summary: records={}, file_size={} bytes, elapsed={:.2?}, sorted={}",
+    info!("[mtlog][SUMMARY] Merge summary: records={}, file_size={} bytes, validated in {:.2?}, sorted={}",
         merged_count.to_formatted_string(&Locale::en),
         output_size.to_formatted_string(&Locale::en),
-        elapsed,
+        timer.elapsed(),
         sorted
     );
+    if !sorted {
+        return Err(anyhow::anyhow!("Merged output for {:?} is not sorted; not publishing it", output_path));
+    }
+    if line_count != merged_count {
+        return Err(anyhow::anyhow!(
+            "Merged output for {:?} has {} lines but {} records were merged; not publishing it",
+            output_path, line_count, merged_count
+        ));
+    }
     Ok(())
 }
 
+/// Merges `files` by key range on all rayon workers (see `partition.rs`) and publishes
+/// the ranges as partition files next to `output_path`.
+fn merge_k_files_mtlog_partitioned(
+    files: &[PathBuf],
+    output_path: &Path,
+    sort_columns: &[MTLogSortColumn],
+    partitions: usize,
+    options: &RunOptions,
+) -> Result<()> {
+    let merge_timer = Instant::now();
+
+    // 1. Sample lines at evenly spaced offsets of every input
+    let samples = files
+        .par_iter()
+        .map(|f| -> Result<Vec<Vec<u8>>> {
+            let len = std::fs::metadata(f)?.len();
+            let mut file = File::open(f).with_context(|| format!("Failed to open chunk file: {}", f.display()))?;
+            let mut samples = Vec::new();
+            for offset in sample_offsets(0, len, partitions) {
+                if let Some(line) = read_line_after(&mut file, 0, offset)? {
+                    samples.push(mtlog_sort_key(&line, sort_columns));
+                }
+            }
+            Ok(samples)
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+    let splitters = pick_splitters(samples, partitions, Ord::cmp);
+    options.cancel.check()?;
+
+    // 2. Find where every key range starts in every input
+    let bounds = files
+        .par_iter()
+        .map(|f| -> Result<Vec<u64>> {
+            let len = std::fs::metadata(f)?.len();
+            let mut file = File::open(f).with_context(|| format!("Failed to open chunk file: {}", f.display()))?;
+            search_line_bounds(&mut file, 0, len, &splitters, |line, splitter| {
+                mtlog_sort_key(line, sort_columns) > *splitter
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let ranges = splitters.len() + 1;
+    info!("[mtlog] [MERGE] Partitioned merge: {} key ranges over {} files (split in {:.2?})",
+        ranges.to_formatted_string(&Locale::en),
+        files.len().to_formatted_string(&Locale::en),
+        merge_timer.elapsed()
+    );
+
+    // 3. Merge every key range on its own worker
+    let merge_range = |p: usize, out: &File| -> Result<usize> {
+        options.cancel.check()?;
+        let range_bytes: u64 = bounds.iter().map(|b| b[p + 1] - b[p]).sum();
+        let readers = files
+            .iter()
+            .zip(&bounds)
+            .map(|(f, b)| MTLogReader::open_range(f, b[p], b[p + 1]))
+            .collect::<Result<Vec<_>>>()?;
+        let mut writer = BufWriter::with_capacity(get_merge_buf_size(), out);
+        let mut progress =
+            ProgressReporter::new(options.progress.as_ref(), ProgressPhase::Merge, Some(p), range_bytes);
+        let check = (options.presorted != Presorted::No)
+            .then(|| OrderCheck::new(files, p.checked_sub(1).map(|i| splitters[i].as_slice())));
+        let merged = merge_mtlog_readers(readers, &mut writer, sort_columns, check, options, &mut progress)?;
+        writer.flush()?;
+        progress.finish();
+        Ok(merged)
+    };
+
+    let outputs = (0..ranges)
+        .into_par_iter()
+        .map(|p| -> Result<(AtomicOutput, usize)> {
+            let part_path = partition_path(output_path, p);
+            let output = AtomicOutput::create(&part_path)?;
+            let merged = merge_range(p, output.file())?;
+            validate_mtlog_output(output.path(), &part_path, merged, sort_columns, options, Some(p))?;
+            Ok((output, merged))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Publish only once every partition has been merged and validated
+    let mut merged_count = 0;
+    for (p, (output, merged)) in outputs.into_iter().enumerate() {
+        output.commit_with_meta(&options.output, Some(OutputMeta::for_mtlog(sort_columns, merged)))?;
+        info!("[mtlog] [MERGE] Partition {}: {} records -> {:?}", p, merged.to_formatted_string(&Locale::en), partition_path(output_path, p));
+        merged_count += merged;
+    }
+    info!("[mtlog] [MERGE] Merge finished: {} records in {} partitions in {:.2?}", merged_count.to_formatted_string(&Locale::en), ranges.to_formatted_string(&Locale::en), merge_timer.elapsed());
+    Ok(())
+}
+
+/// Checks that every presorted MT log input is sorted, returning the first one found out of order.
+fn find_unsorted_mtlog(inputs: &[PathBuf], sort_columns: &[MTLogSortColumn], options: &RunOptions) -> Result<Option<Unsorted>> {
+    let results: Vec<Result<usize>> = inputs
+        .par_iter()
+        .map(|input| {
+            let check = OrderCheck::new(std::slice::from_ref(input), None);
+            let mut progress = ProgressReporter::new(&NoopProgress, ProgressPhase::Merge, None, 0);
+            merge_mtlog_readers(vec![MTLogReader::open(input)?], &mut std::io::sink(), sort_columns, Some(check), options, &mut progress)
+        })
+        .collect();
+    for result in results {
+        if let Err(e) = result {
+            return e.downcast::<Unsorted>().map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Opens an MT log input, reading stdin for `-`.
+fn open_mtlog_input(path: &Path) -> Result<MTLogReader> {
+    if is_stdio(path) {
+        Ok(MTLogReader::from_reader(std::io::stdin()))
+    } else {
+        MTLogReader::open(path)
+    }
+}
+
+/// Writes a sorted `chunk` to a new temp file in `dir`.
+fn write_sorted_chunk(chunk: &MTLogChunk, dir: &Path, number: usize, options: &RunOptions) -> Result<PathBuf> {
+    options.cancel.check()?;
+    let tmp = tempfile::NamedTempFile::new_in(dir)?;
+    {
+        let mut writer = BufWriter::with_capacity(get_merge_buf_size(), tmp.as_file());
+        chunk.write_to(&mut writer)?;
+        writer.flush()?;
+    }
+    let chunk_path = tmp.path().to_path_buf();
+    tmp.persist(&chunk_path)?;
+    info!("[mtlog] [CHUNK] Wrote sorted chunk file #{} ({} records): {}", number, chunk.len().to_formatted_string(&Locale::en), chunk_path.display());
+    Ok(chunk_path)
+}
+
 pub fn parallel_merge_sort_mtlog(
     input_paths: &[PathBuf],
     output_path: impl AsRef<Path>,
     sort_columns: &[MTLogSortColumn],
 ) -> Result<()> {
-    let total_timer = Instant::now();
+    parallel_merge_sort_mtlog_with(input_paths, output_path, sort_columns, &RunOptions::default())
+}
+
+/// Same as [`parallel_merge_sort_mtlog`], reporting progress through `options`.
+pub fn parallel_merge_sort_mtlog_with(
+    input_paths: &[PathBuf],
+    output_path: impl AsRef<Path>,
+    sort_columns: &[MTLogSortColumn],
+    options: &RunOptions,
+) -> Result<()> {
+    check_single_stdin(input_paths)?;
+    let sources = input_paths.iter().map(|p| MTLogSource::Path(p)).collect();
+    merge_sort_mtlog(sources, Target::Path(output_path.as_ref()), sort_columns, options)
+}
+
+/// Same as [`parallel_merge_sort_mtlog_with`], reading MT log records from `sources` and
+/// writing the sorted records to `sink`.
+///
+/// Each source is read front to back once, on its own reader thread. Chunks and merge
+/// groups still spill to the directories `options.spill` creates.
+pub fn parallel_merge_sort_mtlog_streams<R, W>(
+    sources: Vec<R>,
+    sink: W,
+    sort_columns: &[MTLogSortColumn],
+    options: &RunOptions,
+) -> Result<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send,
+{
+    let sources = sources.into_iter().map(|r| MTLogSource::Reader(MTLogReader::from_reader(r))).collect();
+    merge_sort_mtlog(sources, Target::Writer(Box::new(sink)), sort_columns, options)
+}
+
+/// Merges new MT log `input_paths` into `existing`, an output already sorted by `sort_columns`.
+///
+/// Only the new inputs are chunked and sorted; they are then merged with `existing` in a
+/// single streaming pass whose result replaces it atomically. Records of `existing` come
+/// before new records with equal keys, and its order is checked while merging. The sort
+/// keys are checked against `<existing>.meta.json`, which is rewritten along with the
+/// merged output. An `existing` that does not exist yet is created by a full sort.
+pub fn parallel_merge_into_mtlog(
+    existing: &Path,
+    input_paths: &[PathBuf],
+    sort_columns: &[MTLogSortColumn],
+    options: &RunOptions,
+) -> Result<()> {
     if input_paths.is_empty() {
+        return Err(anyhow::anyhow!("No input files provided"));
+    }
+    if input_paths.iter().any(|p| p == existing) {
+        return Err(anyhow::anyhow!("{} is both the merge target and an input", existing.display()));
+    }
+    if !options.select.is_empty() {
+        return Err(anyhow::anyhow!("Selected fields cannot be merged into an existing output"));
+    }
+    if options.columnar.is_some() {
+        return Err(anyhow::anyhow!("Only MT log outputs can be merged into, not Parquet or Arrow IPC"));
+    }
+    let meta = OutputMeta::mtlog(sort_columns);
+    if !check_merge_into(existing, &meta, &options.output)? {
+        return parallel_merge_sort_mtlog_with(input_paths, existing, sort_columns, options);
+    }
+    check_single_stdin(input_paths)?;
+    info!("[mtlog] [CHUNK] Sorting {} new files to merge into {:?}", input_paths.len().to_formatted_string(&Locale::en), existing);
+    let sources = input_paths.iter().map(|p| MTLogSource::Path(p)).collect();
+    let (chunk_files, _chunk_dir, pipeline_stats) = sort_mtlog_chunks(sources, sort_columns, options)?;
+    info!("[mtlog] [SUMMARY] Chunk pipeline: {:.2?}, utilization: {}", pipeline_stats.elapsed, pipeline_stats.utilization());
+    let mut files = vec![existing.to_path_buf()];
+    files.extend(chunk_files);
+    let options = &RunOptions { presorted: Presorted::Verify, ..options.clone() };
+    merge_mtlog_files(&files, Target::Path(existing), sort_columns, options)
+}
+
+/// An MT log input: a path (`-` for stdin), or a caller's stream.
+enum MTLogSource<'a> {
+    Path(&'a Path),
+    Reader(MTLogReader),
+}
+
+/// One reader task of the chunk phase.
+enum ChunkTask<'a> {
+    /// A record-aligned byte range of an uncompressed file.
+    Range(&'a Path, u64, u64),
+    /// A compressed file or stdin, read from the start.
+    Whole(&'a Path),
+    /// A caller's stream; taken by the reader that picks up the task.
+    Stream(Mutex<Option<MTLogReader>>),
+}
+
+fn merge_sort_mtlog(
+    sources: Vec<MTLogSource<'_>>,
+    mut target: Target<'_>,
+    sort_columns: &[MTLogSortColumn],
+    options: &RunOptions,
+) -> Result<()> {
+    let total_timer = Instant::now();
+    if sources.is_empty() {
         warn!("[mtlog] No input files provided for MT log merge");
         return Err(anyhow::anyhow!("No input files provided"));
     }
-    info!("[mtlog] [CHUNK] Starting parallel chunked merge of {} files into {:?}", input_paths.len().to_formatted_string(&Locale::en), output_path.as_ref());
-    let chunk_records = std::env::var("CHUNK_RECORDS").ok().and_then(|v| v.parse::<usize>().ok()).unwrap_or(1_000_000);
-    info!("[mtlog] [CHUNK] Chunk size: {} records", chunk_records.to_formatted_string(&Locale::en));
-    let mut chunk_files: Vec<PathBuf> = Vec::new();
-    let mut all_lines = Vec::new();
-    let mut cur_records = 0;
-    let chunk_timer = Instant::now();
-    let mut total_records: usize = 0;
-    let buf_size = get_merge_buf_size();
-    for (file_idx, path) in input_paths.iter().enumerate() {
-        info!("[mtlog] [CHUNK] Reading input file #{}: {}", file_idx + 1, path.display());
-        if !path.exists() {
-            warn!("[mtlog] [CHUNK] Input file {} does not exist!", path.display());
-            continue;
-        }
-        let file = File::open(path)?;
-        let reader = BufReader::with_capacity(buf_size, file);
-        for line in reader.lines() {
-            let line = line?;
-            cur_records += 1;
-            total_records += 1;
-            all_lines.push(line);
-            if cur_records >= chunk_records {
-                info!("[mtlog] [CHUNK] Sorting chunk of {} records...", all_lines.len().to_formatted_string(&Locale::en));
-                let mut chunk = std::mem::take(&mut all_lines);
-                cur_records = 0;
-                let sort_timer = Instant::now();
-                chunk.par_sort_unstable_by(|a, b| compare_mtlog_by_columns(a, b, sort_columns));
-                info!("[mtlog] [CHUNK] Sorted chunk of {} records in {:.2?}", chunk.len().to_formatted_string(&Locale::en), sort_timer.elapsed());
-                let tmp = tempfile::NamedTempFile::new()?;
-                {
-                    let mut writer = BufWriter::with_capacity(buf_size, tmp.as_file());
-                    for l in &chunk { writeln!(writer, "{}", l)?; }
-                    writer.flush()?;
+    target.check(&options.output)?;
+    check_columnar(options)?;
+    let output_path = &target.name().to_path_buf();
+    if options.presorted != Presorted::No {
+        let inputs: Vec<PathBuf> = sources
+            .iter()
+            .filter_map(|s| match s {
+                MTLogSource::Path(path) if !is_stdio(path) => Some(path.to_path_buf()),
+                _ => None,
+            })
+            .collect();
+        if inputs.len() < sources.len() {
+            info!("[mtlog] Streamed inputs cannot be merged as presorted, sorting them in full");
+        } else if options.filter.is_some() {
+            info!("[mtlog] Filtered inputs cannot be merged as presorted, sorting them in full");
+        } else {
+            let fallback = options.presorted == Presorted::OrSort;
+            let expected = OutputMeta::mtlog(sort_columns);
+            if inputs_match_meta(&inputs, &expected, fallback)? {
+                // Records sent to a stream cannot be taken back, so a fallback checks the inputs first
+                let mut unsorted = match fallback && target.is_stream() {
+                    true => find_unsorted_mtlog(&inputs, sort_columns, options)?,
+                    false => None,
+                };
+                if unsorted.is_none() {
+                    info!("[mtlog] [MERGE] Merging {} presorted files without sorting them", inputs.len().to_formatted_string(&Locale::en));
+                    match target {
+                        Target::Path(path) if fallback => {
+                            match merge_mtlog_files(&inputs, Target::Path(path), sort_columns, options) {
+                                Err(e) if e.is::<Unsorted>() => unsorted = e.downcast().ok(),
+                                result => return result,
+                            }
+                            target = Target::Path(path);
+                        }
+                        target => return merge_mtlog_files(&inputs, target, sort_columns, options),
+                    }
+                }
+                if let Some(unsorted) = unsorted {
+                    warn!("[mtlog] {}, sorting the inputs in full", unsorted);
                 }
-                let chunk_path = tmp.path().to_path_buf();
-                tmp.persist(&chunk_path)?;
-                info!("[mtlog] [CHUNK] Wrote sorted chunk file #{} ({} records): {}", chunk_files.len() + 1, chunk.len().to_formatted_string(&Locale::en), chunk_path.display());
-                chunk_files.push(chunk_path);
             }
         }
     }
-    if !all_lines.is_empty() {
-        info!("[mtlog] [CHUNK] Sorting final chunk of {} records...", all_lines.len().to_formatted_string(&Locale::en));
-        let sort_timer = Instant::now();
-        all_lines.par_sort_unstable_by(|a, b| compare_mtlog_by_columns(a, b, sort_columns));
-        info!("[mtlog] [CHUNK] Sorted final chunk of {} records in {:.2?}", all_lines.len().to_formatted_string(&Locale::en), sort_timer.elapsed());
-        let tmp = tempfile::NamedTempFile::new()?;
-        {
-            let mut writer = BufWriter::with_capacity(buf_size, tmp.as_file());
-            for l in &all_lines { writeln!(writer, "{}", l)?; }
-            writer.flush()?;
-        }
-        let chunk_path = tmp.path().to_path_buf();
-        tmp.persist(&chunk_path)?;
-        info!("[mtlog] [CHUNK] Wrote sorted chunk file #{} ({} records): {}", chunk_files.len() + 1, all_lines.len().to_formatted_string(&Locale::en), chunk_path.display());
-        chunk_files.push(chunk_path);
-    }
-    info!("[mtlog] [CHUNK] {} sorted chunk files created in {:.2?}", chunk_files.len().to_formatted_string(&Locale::en), chunk_timer.elapsed());
-    info!("[mtlog] [CHUNK] Total input records: {}", total_records.to_formatted_string(&Locale::en));
+    let options = &RunOptions { presorted: Presorted::No, ..options.clone() };
+    info!("[mtlog] [CHUNK] Starting parallel chunked merge of {} files into {:?}", sources.len().to_formatted_string(&Locale::en), output_path);
+    let (chunk_files, _chunk_dir, pipeline_stats) = sort_mtlog_chunks(sources, sort_columns, options)?;
     let parallel_groups = get_merge_parallel_groups();
     if parallel_groups <= 1 || chunk_files.len() <= 2 {
-        merge_k_files_mtlog(&chunk_files, output_path.as_ref(), sort_columns)?;
+        merge_mtlog_files(&chunk_files, target, sort_columns, options)?;
     } else {
-        let group_size = (chunk_files.len() + parallel_groups - 1) / parallel_groups;
+        let group_size = chunk_files.len().div_ceil(parallel_groups);
         let group_chunks: Vec<Vec<PathBuf>> = chunk_files
             .chunks(group_size)
             .map(|c| c.to_vec())
             .collect();
-        let temp_dir = tempfile::tempdir()?;
+        let temp_dir = options.spill.create_dir()?;
         info!("[mtlog] [GROUP] Starting {} parallel group merges (group size: {})", group_chunks.len(), group_size);
         let group_outputs: Vec<PathBuf> = group_chunks
             .par_iter()
             .enumerate()
             .map(|(i, group)| {
+                options.cancel.check()?;
                 let group_path = temp_dir.path().join(format!("group_merge_{}.mtlog", i));
                 info!("[mtlog] [GROUP] Merging group #{}/{} ({} files) into {}", i + 1, group_chunks.len(), group.len(), group_path.display());
                 let group_timer = Instant::now();
-                let result = merge_k_files_mtlog(group, &group_path, sort_columns);
+                let result = merge_k_files_mtlog_worker(group, Target::Path(&group_path), sort_columns, options, Some(i), &OutputOptions::default());
                 info!("[mtlog] [GROUP] Finished group #{}/{} in {:.2?}", i + 1, group_chunks.len(), group_timer.elapsed());
                 result?;
                 Ok(group_path)
             })
             .collect::<Result<Vec<_>>>()?;
         info!("[mtlog] [GROUP] All group merges complete. Merging group outputs into final output...");
-        merge_k_files_mtlog(&group_outputs, output_path.as_ref(), sort_columns)?;
+        merge_mtlog_files(&group_outputs, target, sort_columns, options)?;
     }
     let total_elapsed = total_timer.elapsed();
-    info!("[mtlog] [SUMMARY] Parallel merge complete: output={:?}, elapsed={:.2?}", output_path.as_ref(), total_elapsed);
+    info!("[mtlog] [SUMMARY] Parallel merge complete: output={:?}, elapsed={:.2?}", output_path, total_elapsed);
+    info!("[mtlog] [SUMMARY] Chunk pipeline: {:.2?}, utilization: {}", pipeline_stats.elapsed, pipeline_stats.utilization());
     Ok(())
 }
+
+/// Reads `sources` into sorted chunk files, in input order, in the returned directory.
+fn sort_mtlog_chunks(
+    sources: Vec<MTLogSource<'_>>,
+    sort_columns: &[MTLogSortColumn],
+    options: &RunOptions,
+) -> Result<(Vec<PathBuf>, TempDir, PipelineStats)> {
+    let chunk_records = std::env::var("CHUNK_RECORDS").ok().and_then(|v| v.parse::<usize>().ok()).unwrap_or(1_000_000);
+    // Every buffer in flight holds one chunk, so they share the CHUNK_RECORDS budget
+    let pipeline = Pipeline::new(rayon::current_num_threads());
+    let worker_chunk_records = (chunk_records / pipeline.buffers).max(1);
+    info!(
+        "[mtlog] [CHUNK] Chunk size: {} records ({} per buffer, {} buffers, {} readers, {} sorters, 1 writer)",
+        chunk_records.to_formatted_string(&Locale::en),
+        worker_chunk_records.to_formatted_string(&Locale::en),
+        pipeline.buffers,
+        pipeline.readers,
+        pipeline.sorters
+    );
+    let chunk_timer = Instant::now();
+    let input_bytes: u64 = sources
+        .iter()
+        .filter_map(|s| match s {
+            MTLogSource::Path(p) => std::fs::metadata(p).ok(),
+            MTLogSource::Reader(_) => None,
+        })
+        .map(|m| m.len())
+        .sum();
+    // Regular files are cut into record-aligned ranges of about one chunk each; compressed
+    // inputs and pipes can only be read from the start, so each is a single stream
+    let range_bytes = worker_chunk_records * (TOTAL_LENGTH + 1);
+    let mut inputs: Vec<ChunkTask> = Vec::new();
+    for (file_idx, source) in sources.into_iter().enumerate() {
+        let path = match source {
+            MTLogSource::Path(path) => path,
+            MTLogSource::Reader(reader) => {
+                info!("[mtlog] [CHUNK] Input #{}: stream", file_idx + 1);
+                inputs.push(ChunkTask::Stream(Mutex::new(Some(reader))));
+                continue;
+            }
+        };
+        if !is_stdio(path) && !path.exists() {
+            warn!("[mtlog] [CHUNK] Input file {} does not exist!", path.display());
+            continue;
+        }
+        if is_stdio(path) || !supports_ranges(path) {
+            info!("[mtlog] [CHUNK] Input file #{}: {} (streamed)", file_idx + 1, path.display());
+            inputs.push(ChunkTask::Whole(path));
+            continue;
+        }
+        let ranges = match map_input(path)? {
+            Some(map) => line_ranges(&map, 0, TOTAL_LENGTH + 1, range_bytes),
+            None => Vec::new(),
+        };
+        info!("[mtlog] [CHUNK] Input file #{}: {} ({} ranges)", file_idx + 1, path.display(), ranges.len().to_formatted_string(&Locale::en));
+        inputs.extend(ranges.into_iter().map(|(start, end)| ChunkTask::Range(path, start as u64, end as u64)));
+    }
+    let chunk_progress = WorkerProgress::new(options.progress.as_ref(), ProgressPhase::Chunk, input_bytes);
+    let total_records = AtomicUsize::new(0);
+    // Sorted chunks live here so they are removed when the run ends, including on error or cancel
+    let chunk_dir = options.spill.create_dir()?;
+    let filter = options.filter.as_ref().map(Filter::for_mtlog).transpose()?;
+    let mut chunk_number = 0;
+    let (chunk_files, pipeline_stats) = pipeline.run(
+        &inputs,
+        |reader_idx, task, chunk: &mut MTLogChunk, feed| {
+            options.cancel.check()?;
+            let mut reader = match task {
+                ChunkTask::Range(path, start, end) => MTLogReader::open_range(path, *start, *end)?,
+                ChunkTask::Whole(path) => open_mtlog_input(path)?,
+                ChunkTask::Stream(reader) => reader
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .take()
+                    .context("MT log stream was already read")?,
+            };
+            let mut reported = (reader.position(), 0usize);
+            let mut records: usize = 0;
+            while reader.next_record()? {
+                // Like `lines()`, drop a CR before the newline
+                let record = reader.record();
+                let record = record.strip_suffix(b"\r").unwrap_or(record);
+                records += 1;
+                if filter.as_ref().is_none_or(|f| f.matches_mtlog(record)) {
+                    chunk.push(record, sort_columns);
+                }
+                if records.is_multiple_of(CANCEL_CHECK_INTERVAL) {
+                    options.cancel.check()?;
+                    chunk_progress.add_for(reader_idx, reader.position() - reported.0, (records - reported.1) as u64);
+                    reported = (reader.position(), records);
+                }
+                if chunk.len() >= worker_chunk_records {
+                    feed.submit(chunk)?;
+                }
+            }
+            chunk_progress.add_for(reader_idx, reader.position() - reported.0, (records - reported.1) as u64);
+            total_records.fetch_add(records, AtomicOrdering::Relaxed);
+            Ok(())
+        },
+        |chunk| {
+            let sort_timer = Instant::now();
+            chunk.sort();
+            info!("[mtlog] [CHUNK] Sorted chunk of {} records in {:.2?}", chunk.len().to_formatted_string(&Locale::en), sort_timer.elapsed());
+        },
+        |chunk| {
+            chunk_number += 1;
+            write_sorted_chunk(chunk, chunk_dir.path(), chunk_number, options)
+        },
+    )?;
+    let total_records = total_records.into_inner();
+    // Chunks are returned in input order, so equal keys keep their input order through the merge
+    info!("[mtlog] [CHUNK] {} sorted chunk files created in {:.2?}", chunk_files.len().to_formatted_string(&Locale::en), chunk_timer.elapsed());
+    info!("[mtlog] [CHUNK] Total input records: {}", total_records.to_formatted_string(&Locale::en));
+    Ok((chunk_files, chunk_dir, pipeline_stats))
+}
@@ -0,0 +1,78 @@
+// --- Per-run options shared by the CSV and MT log pipelines ---
+
+use super::cancel::CancellationToken;
+use super::columnar::ColumnarOptions;
+use super::dialect::CsvDialect;
+use super::filter::Filter;
+use super::output::OutputOptions;
+use super::presorted::Presorted;
+use super::schema::Schema;
+use super::select::SelectColumn;
+use super::progress::{NoopProgress, ProgressSink};
+use super::spill::{SpillStorage, TempDirSpill};
+use std::sync::Arc;
+
+/// Options for a single merge run.
+///
+/// Tuning knobs (chunk size, `MERGE_K`, buffer sizes) stay in environment variables;
+/// this struct carries the hooks an embedding application needs per run.
+#[derive(Clone)]
+pub struct RunOptions {
+    /// Receives progress updates for every phase of the run.
+    pub progress: Arc<dyn ProgressSink>,
+    /// Checked between chunks and periodically in the merge loops.
+    pub cancel: CancellationToken,
+    /// Sidecar files to publish next to the final output.
+    pub output: OutputOptions,
+    /// Where sorted chunks and intermediate merge files are spilled.
+    pub spill: Arc<dyn SpillStorage>,
+    /// Whether the inputs of a sorted merge are already sorted, so they can be merged directly.
+    pub presorted: Presorted,
+    /// How CSV inputs are read.
+    pub input_dialect: CsvDialect,
+    /// How CSV outputs are written (comment and encoding do not apply).
+    pub output_dialect: CsvDialect,
+    /// How CSV inputs with differing headers are merged.
+    pub schema: Schema,
+    /// Columns (or MT log fields) written to the final output, in order; all if empty.
+    /// Sort keys do not have to be selected.
+    pub select: Vec<SelectColumn>,
+    /// Only records matching this filter are sorted and written.
+    pub filter: Option<Filter>,
+    /// Writes the final output as Parquet or Arrow IPC instead of CSV or an MT log.
+    pub columnar: Option<ColumnarOptions>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            progress: Arc::new(NoopProgress),
+            cancel: CancellationToken::default(),
+            output: OutputOptions::default(),
+            spill: Arc::new(TempDirSpill::default()),
+            presorted: Presorted::No,
+            input_dialect: CsvDialect::default(),
+            output_dialect: CsvDialect::default(),
+            schema: Schema::default(),
+            select: Vec::new(),
+            filter: None,
+            columnar: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for RunOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RunOptions")
+            .field("cancel", &self.cancel)
+            .field("output", &self.output)
+            .field("presorted", &self.presorted)
+            .field("input_dialect", &self.input_dialect)
+            .field("output_dialect", &self.output_dialect)
+            .field("schema", &self.schema)
+            .field("select", &self.select)
+            .field("filter", &self.filter)
+            .field("columnar", &self.columnar)
+            .finish_non_exhaustive()
+    }
+}
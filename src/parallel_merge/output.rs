@@ -0,0 +1,296 @@
+// --- Atomic output writing ---
+
+use anyhow::{Context, Result};
+use log::info;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tempfile::NamedTempFile;
+
+use super::meta::OutputMeta;
+
+/// Whether `path` is `-`, which stands for stdin as an input and stdout as an output.
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// What to publish next to a final output file once it is in place.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputOptions {
+    /// Write an empty `<output>.done` marker after the output is renamed into place.
+    pub done_marker: bool,
+    /// Write a `sha256sum`-compatible `<output>.sha256` checksum sidecar, and record
+    /// the digest as the fingerprint in `<output>.meta.json`.
+    pub checksum: bool,
+    /// Merge key ranges in parallel into partition files (`<stem>.part-NNNNN.<ext>`)
+    /// instead of one output file. Each partition gets the sidecars.
+    pub partitioned: bool,
+}
+
+/// An output file that is written to a hidden sibling temp file and only appears
+/// at its final path on [`AtomicOutput::commit`].
+///
+/// Dropping it without committing (on error, cancellation or a crash) removes the
+/// temp file, so downstream jobs never see a truncated output.
+pub struct AtomicOutput {
+    final_path: PathBuf,
+    tmp: NamedTempFile,
+}
+
+impl AtomicOutput {
+    pub fn create(final_path: &Path) -> Result<Self> {
+        let tmp = sibling_temp_file(final_path)?;
+        Ok(Self {
+            final_path: final_path.to_path_buf(),
+            tmp,
+        })
+    }
+
+    /// Path of the temp file being written.
+    pub fn path(&self) -> &Path {
+        self.tmp.path()
+    }
+
+    pub fn file(&self) -> &File {
+        self.tmp.as_file()
+    }
+
+    /// Fsyncs the temp file, renames it over the final path and writes the requested sidecars.
+    pub fn commit(self, options: &OutputOptions) -> Result<()> {
+        self.commit_with_meta(options, None)
+    }
+
+    /// Like [`AtomicOutput::commit`], and also writes `meta` as the `<output>.meta.json` sidecar.
+    pub fn commit_with_meta(self, options: &OutputOptions, meta: Option<OutputMeta>) -> Result<()> {
+        self.tmp
+            .as_file()
+            .sync_all()
+            .with_context(|| format!("Failed to fsync {}", self.tmp.path().display()))?;
+        // Hashing re-reads the whole output, so it is only done when a checksum is asked for
+        let checksum = if options.checksum {
+            Some(sha256_file(self.tmp.path())?)
+        } else {
+            None
+        };
+        let final_path = self.final_path;
+        // Stale sidecars from a previous run would describe the old file once the new one lands
+        for ext in ["done", "sha256", "meta.json"] {
+            let stale = sidecar_path(&final_path, ext);
+            if stale.exists() {
+                std::fs::remove_file(&stale)
+                    .with_context(|| format!("Failed to remove stale {}", stale.display()))?;
+            }
+        }
+        self.tmp
+            .persist(&final_path)
+            .with_context(|| format!("Failed to rename output into place: {}", final_path.display()))?;
+        sync_parent_dir(&final_path)?;
+        if let Some(checksum) = &checksum {
+            let file_name = final_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let sidecar = sidecar_path(&final_path, "sha256");
+            write_small_file_atomic(&sidecar, format!("{}  {}\n", checksum, file_name).as_bytes())?;
+            info!("[output] Wrote checksum {}", sidecar.display());
+        }
+        if let Some(meta) = meta {
+            meta.write(&final_path, checksum.as_deref())?;
+        }
+        if options.done_marker {
+            let marker = sidecar_path(&final_path, "done");
+            write_small_file_atomic(&marker, b"")?;
+            info!("[output] Wrote done marker {}", marker.display());
+        }
+        Ok(())
+    }
+}
+
+/// Where a final merge goes: a file published atomically on commit, or a stream
+/// (stdout for the `-` path) that records are written to as they are merged.
+pub(crate) enum FinalOutput<'a> {
+    File(AtomicOutput),
+    Stream(Mutex<Box<dyn Write + Send + 'a>>),
+}
+
+impl<'a> FinalOutput<'a> {
+    pub(crate) fn create(path: &Path, options: &OutputOptions) -> Result<Self> {
+        if is_stdio(path) {
+            Self::stream(Box::new(std::io::stdout()), options)
+        } else {
+            Ok(Self::File(AtomicOutput::create(path)?))
+        }
+    }
+
+    /// A stream output; partitions and sidecars need a file, so they are rejected.
+    pub(crate) fn stream(writer: Box<dyn Write + Send + 'a>, options: &OutputOptions) -> Result<Self> {
+        check_stream_options(options)?;
+        Ok(Self::Stream(Mutex::new(writer)))
+    }
+
+    /// Path of the temp file being written, or `None` for a stream (which cannot be re-read).
+    pub(crate) fn path(&self) -> Option<&Path> {
+        match self {
+            Self::File(output) => Some(output.path()),
+            Self::Stream(_) => None,
+        }
+    }
+
+    /// Commits a file output with its sidecars (including `meta`, if given); flushes a stream.
+    pub(crate) fn commit(self, options: &OutputOptions, meta: Option<OutputMeta>) -> Result<()> {
+        match self {
+            Self::File(output) => output.commit_with_meta(options, meta),
+            Self::Stream(writer) => {
+                writer.into_inner().map_err(|_| anyhow::anyhow!("Output stream poisoned"))?.flush()?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Where a run's final output goes: a path (`-` for stdout), or a caller's writer.
+pub(crate) enum Target<'a> {
+    Path(&'a Path),
+    Writer(Box<dyn Write + Send + 'a>),
+}
+
+impl<'a> Target<'a> {
+    /// The path, or `<writer>`, for logs and error messages.
+    pub(crate) fn name(&self) -> &Path {
+        match self {
+            Self::Path(path) => path,
+            Self::Writer(_) => Path::new("<writer>"),
+        }
+    }
+
+    /// Whether records are streamed out rather than written to a file.
+    pub(crate) fn is_stream(&self) -> bool {
+        match self {
+            Self::Path(path) => is_stdio(path),
+            Self::Writer(_) => true,
+        }
+    }
+
+    /// Rejects output options that a stream cannot honour, before any work is done.
+    pub(crate) fn check(&self, options: &OutputOptions) -> Result<()> {
+        if self.is_stream() {
+            check_stream_options(options)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn open(self, options: &OutputOptions) -> Result<FinalOutput<'a>> {
+        match self {
+            Self::Path(path) => FinalOutput::create(path, options),
+            Self::Writer(writer) => FinalOutput::stream(writer, options),
+        }
+    }
+}
+
+impl Write for &FinalOutput<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            FinalOutput::File(output) => output.file().write(buf),
+            FinalOutput::Stream(writer) => lock(writer).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            FinalOutput::File(output) => output.file().flush(),
+            FinalOutput::Stream(writer) => lock(writer).flush(),
+        }
+    }
+}
+
+/// Stdin can only be read once, so `-` may appear at most once among the inputs.
+pub(crate) fn check_single_stdin(inputs: &[PathBuf]) -> Result<()> {
+    if inputs.iter().filter(|p| is_stdio(p)).count() > 1 {
+        anyhow::bail!("Stdin (`-`) can only be given once as an input");
+    }
+    Ok(())
+}
+
+/// Rejects output options that only make sense for an output file.
+pub(crate) fn check_stream_options(options: &OutputOptions) -> Result<()> {
+    if options.partitioned || options.done_marker || options.checksum {
+        anyhow::bail!("Partitioned output, done markers and checksums need an output file, not a stream");
+    }
+    Ok(())
+}
+
+fn lock<'m, 'a>(writer: &'m Mutex<Box<dyn Write + Send + 'a>>) -> std::sync::MutexGuard<'m, Box<dyn Write + Send + 'a>> {
+    writer.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// `<path>.<ext>`, keeping the original extension (e.g. `merged.csv.sha256`).
+pub fn sidecar_path(path: &Path, ext: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+/// Creates a hidden temp file in the same directory as `path`, so the final rename stays on one filesystem.
+fn sibling_temp_file(path: &Path) -> Result<NamedTempFile> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "output".to_string());
+    let prefix = format!(".{}.", name);
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(&prefix).suffix(".tmp");
+    // Temp files default to 0600; outputs are meant to be picked up by other jobs
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(std::fs::Permissions::from_mode(0o644));
+    }
+    builder
+        .tempfile_in(dir)
+        .with_context(|| format!("Failed to create temp file next to {}", path.display()))
+}
+
+pub(crate) fn write_small_file_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp = sibling_temp_file(path)?;
+    tmp.write_all(contents)?;
+    tmp.as_file().sync_all()?;
+    tmp.persist(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+pub(crate) fn sha256_file(path: &Path) -> Result<String> {
+    let mut reader = BufReader::with_capacity(8 * 1024 * 1024, File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,187 @@
+// --- Partitioned final merge helpers ---
+//
+// With `--partitioned`, the final merge is split into key ranges: keys are sampled from
+// the sorted inputs, sorted, and every `n / partitions`-th sample becomes a splitter.
+// The byte offset where every key range starts is then found in each input (by binary
+// search over newline-terminated records or a CSV chunk's keys index, by a scan
+// otherwise), and each range is heap-merged on its own rayon worker into its own
+// partition file.
+//
+// The ranges are not concatenated back into one output: that would write every record
+// twice, which costs more than the parallel merge saves, so a single output is always
+// written by one heap merge.
+
+use anyhow::{Context, Result};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Samples taken from each input per partition; more samples give more even partitions.
+const SAMPLES_PER_PARTITION: u64 = 32;
+
+pub(crate) fn get_merge_partitions() -> usize {
+    std::env::var("MERGE_PARTITIONS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|&v| v >= 1)
+        .unwrap_or_else(rayon::current_num_threads)
+}
+
+/// Byte offsets in `[data_start, len)` at which an input is sampled for `partitions` partitions.
+pub(crate) fn sample_offsets(data_start: u64, len: u64, partitions: usize) -> Vec<u64> {
+    if len <= data_start {
+        return Vec::new();
+    }
+    let count = SAMPLES_PER_PARTITION * partitions as u64;
+    let span = len - data_start;
+    let mut offsets: Vec<u64> = (0..count).map(|i| data_start + span * i / count).collect();
+    offsets.dedup();
+    offsets
+}
+
+/// Reads the first complete line at or after `offset`, skipping the partial line it lands in.
+pub(crate) fn read_line_after(file: &mut File, data_start: u64, offset: u64) -> Result<Option<Vec<u8>>> {
+    Ok(line_after(file, data_start, offset)?.map(|(_, line)| line))
+}
+
+/// Same as [`read_line_after`], also returning the offset the line starts at.
+fn line_after(file: &mut File, data_start: u64, offset: u64) -> Result<Option<(u64, Vec<u8>)>> {
+    let mut reader = BufReader::with_capacity(64 * 1024, &mut *file);
+    let mut line = Vec::new();
+    let start = if offset > data_start {
+        // Start one byte early so an offset that is already a line start is not skipped
+        reader.seek(SeekFrom::Start(offset - 1))?;
+        let skipped = reader.read_until(b'\n', &mut line)?;
+        line.clear();
+        offset - 1 + skipped as u64
+    } else {
+        reader.seek(SeekFrom::Start(data_start))?;
+        data_start
+    };
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    while matches!(line.last(), Some(b'\n' | b'\r')) {
+        line.pop();
+    }
+    Ok(Some((start, line)))
+}
+
+/// Finds where every key range starts in a sorted input of newline-terminated records.
+///
+/// The range after each splitter starts at the first line `is_after` it, found by binary
+/// search over byte offsets, so only O(log n) lines are read per splitter. Returns the
+/// same bounds as a [`BoundaryScan`], from `data_start` to `len`.
+pub(crate) fn search_line_bounds<T>(
+    file: &mut File,
+    data_start: u64,
+    len: u64,
+    splitters: &[T],
+    mut is_after: impl FnMut(&[u8], &T) -> bool,
+) -> Result<Vec<u64>> {
+    let mut bounds = vec![data_start];
+    let mut lo = data_start;
+    for splitter in splitters {
+        // Lines before the previous bound are not after this splitter either
+        let mut hi = len;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match line_after(file, data_start, mid)? {
+                Some((_, line)) if !is_after(&line, splitter) => lo = mid + 1,
+                _ => hi = mid,
+            }
+        }
+        let bound = line_after(file, data_start, lo)?.map_or(len, |(start, _)| start);
+        bounds.push(bound);
+        lo = bound;
+    }
+    bounds.push(len);
+    Ok(bounds)
+}
+
+/// Sorts `samples` and picks up to `partitions - 1` distinct splitters.
+///
+/// Partition `i` holds the keys in `(splitters[i - 1], splitters[i]]`, so equal keys
+/// always land in the same partition and the merge stays stable.
+pub(crate) fn pick_splitters<T>(
+    mut samples: Vec<T>,
+    partitions: usize,
+    cmp: impl Fn(&T, &T) -> Ordering + Sync,
+) -> Vec<T>
+where
+    T: Send,
+{
+    use rayon::slice::ParallelSliceMut;
+    if partitions <= 1 || samples.is_empty() {
+        return Vec::new();
+    }
+    samples.par_sort_by(&cmp);
+    let n = samples.len();
+    let picks: Vec<usize> = (1..partitions).map(|i| i * n / partitions).collect();
+    let mut splitters: Vec<T> = Vec::with_capacity(picks.len());
+    for (i, sample) in samples.into_iter().enumerate() {
+        if picks.contains(&i) && splitters.last().is_none_or(|last| cmp(last, &sample) == Ordering::Less) {
+            splitters.push(sample);
+        }
+    }
+    splitters
+}
+
+/// Records, while scanning one sorted input, where each partition starts.
+///
+/// `bounds[i]` is the position (a byte offset, or offsets into an input and its
+/// sidecar) of the first record of partition `i`; the last entry is the end of the
+/// input, so partition `i` spans `bounds[i]..bounds[i + 1]`.
+pub(crate) struct BoundaryScan<'a, T, P> {
+    splitters: &'a [T],
+    bounds: Vec<P>,
+}
+
+impl<'a, T, P: Copy> BoundaryScan<'a, T, P> {
+    pub(crate) fn new(splitters: &'a [T], data_start: P) -> Self {
+        Self {
+            splitters,
+            bounds: vec![data_start],
+        }
+    }
+
+    /// Whether every splitter has been passed, so the rest of the input is in the last partition.
+    pub(crate) fn done(&self) -> bool {
+        self.bounds.len() > self.splitters.len()
+    }
+
+    /// Feeds the record starting at `pos`; records must come in sorted order.
+    pub(crate) fn record<K>(&mut self, pos: P, key: &K, cmp: impl Fn(&K, &T) -> Ordering) {
+        while !self.done() && cmp(key, &self.splitters[self.bounds.len() - 1]) == Ordering::Greater {
+            self.bounds.push(pos);
+        }
+    }
+
+    pub(crate) fn finish(mut self, end: P) -> Vec<P> {
+        while self.bounds.len() <= self.splitters.len() + 1 {
+            self.bounds.push(end);
+        }
+        self.bounds
+    }
+}
+
+/// A reader over `start..end` of `path`.
+pub(crate) fn open_range(path: &Path, start: u64, end: u64, buf_size: usize) -> Result<std::io::Take<BufReader<File>>> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    file.seek(SeekFrom::Start(start))?;
+    Ok(BufReader::with_capacity(buf_size, file).take(end.saturating_sub(start)))
+}
+
+/// `<stem>.part-<index>.<ext>` next to `path`, e.g. `merged.part-00003.csv`.
+pub fn partition_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "output".to_string());
+    let name = match path.extension() {
+        Some(ext) => format!("{}.part-{:05}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}.part-{:05}", stem, index),
+    };
+    path.with_file_name(name)
+}
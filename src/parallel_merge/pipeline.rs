@@ -0,0 +1,255 @@
+// --- Read / sort / spill pipeline ---
+//
+// Chunk buffers circulate between three stages: reader threads fill them, a pool of
+// sorter threads sorts them and a single writer thread spills them to temp files and
+// hands them back to the readers. The number of buffers is the memory budget: a reader
+// that finds no free buffer waits for the writer, so reading never runs more than that
+// many chunks ahead of the disk, while reading, sorting and writing overlap.
+//
+// The stages run on their own threads rather than on rayon workers, so a blocked
+// reader never holds up the rayon pool that the sorters' parallel sorts run on.
+
+use anyhow::Result;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// A reusable chunk buffer.
+pub(crate) trait PipelineChunk: Default + Send {
+    fn is_empty(&self) -> bool;
+    /// Empties the chunk, keeping its allocations for the next one.
+    fn clear(&mut self);
+}
+
+/// Position of a chunk in the output order: `(task, chunk within the task)`.
+type Seq = (usize, usize);
+
+/// A stage gave up because another one failed.
+#[derive(Debug)]
+struct Stopped;
+
+impl std::fmt::Display for Stopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pipeline stopped")
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+/// Stage sizes of a [`Pipeline`].
+pub(crate) struct Pipeline {
+    pub(crate) readers: usize,
+    pub(crate) sorters: usize,
+    /// Chunk buffers in flight; always more than `readers`.
+    pub(crate) buffers: usize,
+}
+
+/// Time each stage spent working, as opposed to waiting on the other stages.
+pub(crate) struct PipelineStats {
+    pub(crate) elapsed: Duration,
+    stages: [(&'static str, usize, Duration); 3],
+}
+
+impl PipelineStats {
+    /// E.g. `read 92% (8 threads), sort 31% (4 threads), write 64% (1 thread)`.
+    pub(crate) fn utilization(&self) -> String {
+        let wall = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        self.stages
+            .iter()
+            .map(|&(name, threads, busy)| {
+                let percent = 100.0 * busy.as_secs_f64() / (wall * threads as f64);
+                format!("{} {:.0}% ({} thread{})", name, percent, threads, if threads == 1 { "" } else { "s" })
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Handed to the read function to pass full chunks on to the sorters.
+pub(crate) struct ChunkFeed<'a, C> {
+    free: &'a Receiver<C>,
+    sort_tx: &'a Sender<(Seq, C)>,
+    stop: &'a AtomicBool,
+    task: usize,
+    next: usize,
+    waited: Duration,
+}
+
+impl<C: PipelineChunk> ChunkFeed<'_, C> {
+    /// Sends `chunk` to the sorters and replaces it with an empty buffer, waiting for
+    /// one while the whole budget is in flight.
+    pub(crate) fn submit(&mut self, chunk: &mut C) -> Result<()> {
+        let wait = Instant::now();
+        if self.stop.load(Ordering::Relaxed) {
+            return Err(Stopped.into());
+        }
+        let empty = self.free.recv().map_err(|_| Stopped)?;
+        let full = std::mem::replace(chunk, empty);
+        self.sort_tx.send(((self.task, self.next), full)).map_err(|_| Stopped)?;
+        self.next += 1;
+        self.waited += wait.elapsed();
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct BusyTime(AtomicU64);
+
+impl BusyTime {
+    fn add(&self, d: Duration) {
+        self.0.fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+}
+
+impl Pipeline {
+    /// Sizes the stages for `workers` threads; the budget is `readers + 2` buffers, so a
+    /// chunk can be sorting and another writing while every reader fills one.
+    pub(crate) fn new(workers: usize) -> Self {
+        let readers = workers.max(1);
+        Self { readers, sorters: (readers / 2).max(1), buffers: readers + 2 }
+    }
+
+    /// Runs `read` over every task, sorting each submitted chunk with `sort` and spilling
+    /// it with `write`. Returns the written paths in task order.
+    ///
+    /// `read(reader, task, chunk, feed)` fills `chunk`, calling `feed.submit` whenever it
+    /// is full; whatever is left in it at the end of the task is submitted too.
+    pub(crate) fn run<T, C, R, S, W>(&self, tasks: &[T], read: R, sort: S, mut write: W) -> Result<(Vec<PathBuf>, PipelineStats)>
+    where
+        T: Sync,
+        C: PipelineChunk,
+        R: Fn(usize, &T, &mut C, &mut ChunkFeed<C>) -> Result<()> + Sync,
+        S: Fn(&mut C) + Sync,
+        W: FnMut(&C) -> Result<PathBuf> + Send,
+    {
+        let start = Instant::now();
+        let buffers = self.buffers.max(self.readers + 1);
+        let (free_tx, free_rx) = bounded(buffers);
+        for _ in 0..buffers {
+            free_tx.send(C::default()).map_err(|_| Stopped)?;
+        }
+        let (sort_tx, sort_rx) = bounded::<(Seq, C)>(buffers);
+        let (write_tx, write_rx) = bounded::<(Seq, C)>(buffers);
+        let stop = &AtomicBool::new(false);
+        let next_task = &AtomicUsize::new(0);
+        let (read_busy, sort_busy, write_busy) = (&BusyTime::default(), &BusyTime::default(), &BusyTime::default());
+        let (read, sort) = (&read, &sort);
+
+        let (written, errors) = std::thread::scope(|scope| {
+            // Only the writer returns buffers, so readers stop waiting once it is gone
+            let writer = scope.spawn(move || -> Result<Vec<(Seq, PathBuf)>> {
+                let mut written = Vec::new();
+                for (seq, mut chunk) in write_rx.iter() {
+                    let timer = Instant::now();
+                    let path = write(&chunk);
+                    write_busy.add(timer.elapsed());
+                    match path {
+                        Ok(path) => written.push((seq, path)),
+                        Err(e) => {
+                            stop.store(true, Ordering::Relaxed);
+                            return Err(e);
+                        }
+                    }
+                    chunk.clear();
+                    let _ = free_tx.send(chunk);
+                }
+                Ok(written)
+            });
+            for _ in 0..self.sorters {
+                let (sort_rx, write_tx) = (sort_rx.clone(), write_tx.clone());
+                scope.spawn(move || {
+                    for (seq, mut chunk) in sort_rx.iter() {
+                        let timer = Instant::now();
+                        sort(&mut chunk);
+                        sort_busy.add(timer.elapsed());
+                        if write_tx.send((seq, chunk)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop((sort_rx, write_tx));
+            let readers: Vec<_> = (0..self.readers)
+                .map(|reader| {
+                    let (free_rx, sort_tx) = (free_rx.clone(), sort_tx.clone());
+                    scope.spawn(move || -> Result<()> {
+                        let result = read_tasks(reader, tasks, next_task, read, &free_rx, &sort_tx, stop, read_busy);
+                        if result.is_err() {
+                            stop.store(true, Ordering::Relaxed);
+                        }
+                        result
+                    })
+                })
+                .collect();
+            drop((free_rx, sort_tx));
+            let mut errors = Vec::new();
+            for reader in readers {
+                if let Err(e) = reader.join().expect("pipeline reader panicked") {
+                    errors.push(e);
+                }
+            }
+            let written = match writer.join().expect("pipeline writer panicked") {
+                Ok(written) => written,
+                Err(e) => {
+                    errors.push(e);
+                    Vec::new()
+                }
+            };
+            (written, errors)
+        });
+        // Report the failure that stopped the pipeline, not the stages it stopped
+        if let Some(e) = errors.into_iter().min_by_key(|e| e.is::<Stopped>()) {
+            return Err(e);
+        }
+        let mut written = written;
+        written.sort_by_key(|&(seq, _)| seq);
+        let stats = PipelineStats {
+            elapsed: start.elapsed(),
+            stages: [
+                ("read", self.readers, read_busy.get()),
+                ("sort", self.sorters, sort_busy.get()),
+                ("write", 1, write_busy.get()),
+            ],
+        };
+        Ok((written.into_iter().map(|(_, path)| path).collect(), stats))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn read_tasks<T, C: PipelineChunk>(
+    reader: usize,
+    tasks: &[T],
+    next_task: &AtomicUsize,
+    read: &(impl Fn(usize, &T, &mut C, &mut ChunkFeed<C>) -> Result<()> + Sync),
+    free: &Receiver<C>,
+    sort_tx: &Sender<(Seq, C)>,
+    stop: &AtomicBool,
+    busy: &BusyTime,
+) -> Result<()> {
+    // A reader keeps its buffer across tasks until it has something to submit
+    let mut held: Option<C> = None;
+    loop {
+        let task = next_task.fetch_add(1, Ordering::Relaxed);
+        if task >= tasks.len() || stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let timer = Instant::now();
+        let mut chunk = match held.take() {
+            Some(chunk) => chunk,
+            None => free.recv().map_err(|_| Stopped)?,
+        };
+        let mut feed = ChunkFeed { free, sort_tx, stop, task, next: 0, waited: timer.elapsed() };
+        read(reader, &tasks[task], &mut chunk, &mut feed)?;
+        if chunk.is_empty() {
+            held = Some(chunk);
+        } else {
+            sort_tx.send(((task, feed.next), chunk)).map_err(|_| Stopped)?;
+        }
+        busy.add(timer.elapsed().saturating_sub(feed.waited));
+    }
+}
@@ -0,0 +1,78 @@
+// --- Merging inputs that are already sorted ---
+//
+// With `RunOptions::presorted` set, the inputs are not chunked and sorted but merged
+// directly, like the sorted chunks of a full sort. The merge checks the order as it goes:
+// a heap merge of sorted inputs emits non-decreasing keys, so the first key that is
+// smaller than the previous one (or, in a key range of a partitioned merge, not above
+// the range's lower splitter) shows that the input it came from is not sorted.
+
+use anyhow::Result;
+use std::path::PathBuf;
+
+/// Whether the inputs of a sorted merge are already sorted by the sort columns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Presorted {
+    /// Chunk and sort the inputs.
+    #[default]
+    No,
+    /// Merge the inputs directly; an input that is out of order fails the run with [`Unsorted`].
+    Verify,
+    /// Merge the inputs directly, and sort them in full if one turns out to be out of order.
+    OrSort,
+}
+
+/// Error returned when an input of a presorted merge is not sorted by the sort columns.
+///
+/// Callers can tell it apart from other failures with `err.downcast_ref::<Unsorted>()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsorted {
+    pub input: PathBuf,
+    /// The first record found out of order.
+    pub record: String,
+}
+
+impl std::fmt::Display for Unsorted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is not sorted by the sort columns (out of order at {:?})",
+            self.input.display(),
+            self.record
+        )
+    }
+}
+
+impl std::error::Error for Unsorted {}
+
+/// Longest record text kept in an [`Unsorted`] error.
+const MAX_RECORD_CHARS: usize = 200;
+
+/// Checks the keys a heap merge of presorted `inputs` emits, in order.
+pub(crate) struct OrderCheck<'a> {
+    inputs: &'a [PathBuf],
+    /// Keys of a partitioned merge's key range are all above its lower splitter.
+    lower: Option<&'a [u8]>,
+    last: Option<Vec<u8>>,
+}
+
+impl<'a> OrderCheck<'a> {
+    pub(crate) fn new(inputs: &'a [PathBuf], lower: Option<&'a [u8]>) -> Self {
+        Self { inputs, lower, last: None }
+    }
+
+    /// Checks the next merged `key`, read from `inputs[source]`; `record` renders it for the error.
+    pub(crate) fn next(&mut self, key: &[u8], source: usize, record: impl FnOnce() -> String) -> Result<()> {
+        let out_of_order = match &self.last {
+            Some(last) => key < last.as_slice(),
+            None => self.lower.is_some_and(|lower| key <= lower),
+        };
+        if out_of_order {
+            let record: String = record().chars().take(MAX_RECORD_CHARS).collect();
+            return Err(Unsorted { input: self.inputs[source].clone(), record }.into());
+        }
+        let last = self.last.get_or_insert_with(Vec::new);
+        last.clear();
+        last.extend_from_slice(key);
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+// --- Progress reporting ---
+
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::Arc;
+
+/// Phase of a merge run that a [`ProgressUpdate`] refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProgressPhase {
+    /// Reading inputs, sorting chunks and spilling them to temp files.
+    Chunk,
+    /// K-way merging sorted chunks into the output.
+    Merge,
+    /// Re-reading the output to check record count and sort order.
+    Validate,
+}
+
+/// A snapshot of how far a phase has progressed.
+///
+/// When `worker` is `Some`, the counters are cumulative for that worker within
+/// the phase (e.g. one rayon thread sorting chunks, or one parallel merge group).
+/// When `worker` is `None`, the counters cover the whole phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressUpdate {
+    pub phase: ProgressPhase,
+    pub worker: Option<usize>,
+    pub bytes_processed: u64,
+    pub records_processed: u64,
+    /// Total bytes the phase (or worker) is expected to process, 0 if unknown.
+    pub total_bytes: u64,
+}
+
+/// Receives progress updates from the library.
+///
+/// Implementations are called from rayon workers, so they must be cheap and thread-safe.
+pub trait ProgressSink: Send + Sync {
+    fn on_progress(&self, update: &ProgressUpdate);
+}
+
+/// A sink that discards every update.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopProgress;
+
+impl ProgressSink for NoopProgress {
+    fn on_progress(&self, _update: &ProgressUpdate) {}
+}
+
+impl<T: ProgressSink + ?Sized> ProgressSink for Arc<T> {
+    fn on_progress(&self, update: &ProgressUpdate) {
+        (**self).on_progress(update)
+    }
+}
+
+fn get_progress_interval() -> u64 {
+    std::env::var("PROGRESS_INTERVAL")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|&v| v > 0)
+        .unwrap_or(50_000)
+}
+
+/// Accumulates counters and forwards them to a sink every `PROGRESS_INTERVAL` records.
+pub(crate) struct ProgressReporter<'a> {
+    sink: &'a dyn ProgressSink,
+    update: ProgressUpdate,
+    interval: u64,
+    next_report: u64,
+}
+
+impl<'a> ProgressReporter<'a> {
+    pub(crate) fn new(
+        sink: &'a dyn ProgressSink,
+        phase: ProgressPhase,
+        worker: Option<usize>,
+        total_bytes: u64,
+    ) -> Self {
+        let interval = get_progress_interval();
+        let reporter = Self {
+            sink,
+            update: ProgressUpdate {
+                phase,
+                worker,
+                bytes_processed: 0,
+                records_processed: 0,
+                total_bytes,
+            },
+            interval,
+            next_report: interval,
+        };
+        reporter.sink.on_progress(&reporter.update);
+        reporter
+    }
+
+    /// Adds `records` records spanning `bytes` bytes, reporting if the interval was crossed.
+    pub(crate) fn add(&mut self, bytes: u64, records: u64) {
+        self.update.bytes_processed += bytes;
+        self.update.records_processed += records;
+        if self.update.records_processed >= self.next_report {
+            self.next_report = self.update.records_processed + self.interval;
+            self.sink.on_progress(&self.update);
+        }
+    }
+
+    /// Reports the final counters regardless of the interval.
+    pub(crate) fn finish(&mut self) {
+        self.sink.on_progress(&self.update);
+    }
+}
+
+/// Per-worker cumulative counters for phases that fan out over rayon threads.
+pub(crate) struct WorkerProgress<'a> {
+    sink: &'a dyn ProgressSink,
+    phase: ProgressPhase,
+    total_bytes: u64,
+    counters: Vec<(AtomicU64, AtomicU64)>,
+}
+
+impl<'a> WorkerProgress<'a> {
+    pub(crate) fn new(sink: &'a dyn ProgressSink, phase: ProgressPhase, total_bytes: u64) -> Self {
+        let counters = (0..rayon::current_num_threads().max(1))
+            .map(|_| (AtomicU64::new(0), AtomicU64::new(0)))
+            .collect();
+        Self { sink, phase, total_bytes, counters }
+    }
+
+    /// Adds work done by the calling rayon worker and reports its cumulative counters.
+    pub(crate) fn add(&self, bytes: u64, records: u64) {
+        self.add_for(rayon::current_thread_index().unwrap_or(0), bytes, records);
+    }
+
+    /// Adds work done by `worker` (e.g. a pipeline reader thread) and reports its cumulative counters.
+    pub(crate) fn add_for(&self, worker: usize, bytes: u64, records: u64) {
+        let worker = worker % self.counters.len();
+        let (worker_bytes, worker_records) = &self.counters[worker];
+        let bytes_processed = worker_bytes.fetch_add(bytes, AtomicOrdering::Relaxed) + bytes;
+        let records_processed = worker_records.fetch_add(records, AtomicOrdering::Relaxed) + records;
+        self.sink.on_progress(&ProgressUpdate {
+            phase: self.phase,
+            worker: Some(worker),
+            bytes_processed,
+            records_processed,
+            total_bytes: self.total_bytes,
+        });
+    }
+}
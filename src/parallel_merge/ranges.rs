@@ -0,0 +1,193 @@
+// --- Record-aligned input ranges ---
+//
+// A large input is cut into byte ranges of about one chunk each, every range starting
+// right after a record terminator, so the ranges can be parsed and sorted on separate
+// rayon workers. MT log records are fixed-width, so boundaries fall on multiples of the
+// record length (snapped to the next newline where a file does not follow the layout).
+// CSV boundaries must skip newlines inside quoted fields: the quotes before every
+// nominal boundary are counted in parallel, and their parity tells whether the boundary
+// lands inside a quoted field. Dialects with escaped quotes or comment lines are walked
+// record by record instead.
+
+use anyhow::{Context, Result};
+use memmap2::Mmap;
+use rayon::prelude::*;
+use std::fs::File;
+use std::path::Path;
+
+/// Memory-maps `path` if it is a non-empty regular file.
+pub(crate) fn map_input(path: &Path) -> Result<Option<Mmap>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Ok(None);
+    }
+    // SAFETY: inputs are not modified while they are being sorted
+    let map = unsafe { Mmap::map(&file) }.with_context(|| format!("Failed to memory-map {}", path.display()))?;
+    Ok(Some(map))
+}
+
+/// Splits `data[start..]` into ranges of about `target` bytes that end after a `\n`.
+///
+/// Nominal boundaries are multiples of `record_len` from `start`; one that does not
+/// follow a newline is moved past the next one.
+pub(crate) fn line_ranges(data: &[u8], start: usize, record_len: usize, target: usize) -> Vec<(usize, usize)> {
+    let record_len = record_len.max(1);
+    let step = (target / record_len).max(1) * record_len;
+    let mut bounds = vec![start];
+    let mut next = start + step;
+    while next < data.len() {
+        let bound = if data[next - 1] == b'\n' {
+            next
+        } else {
+            match memchr::memchr(b'\n', &data[next..]) {
+                Some(i) => next + i + 1,
+                None => break,
+            }
+        };
+        if bound < data.len() {
+            bounds.push(bound);
+        }
+        next = bound + step;
+    }
+    to_ranges(bounds, data.len())
+}
+
+/// The bytes that delimit CSV records. They are ASCII, so record boundaries can be found
+/// in the raw bytes of any ASCII-compatible encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CsvSyntax {
+    pub quote: u8,
+    pub escape: Option<u8>,
+    pub terminator: u8,
+    pub comment: Option<u8>,
+}
+
+impl Default for CsvSyntax {
+    fn default() -> Self {
+        Self { quote: b'"', escape: None, terminator: b'\n', comment: None }
+    }
+}
+
+impl CsvSyntax {
+    /// Escaped quotes and comment records break the quote parity, so their records are
+    /// walked one by one instead.
+    fn has_parity(&self) -> bool {
+        self.escape.is_none() && self.comment.is_none()
+    }
+
+    /// Offset just past the record (or comment) starting at `pos`, if it is complete.
+    pub(crate) fn record_end(&self, data: &[u8], pos: usize) -> Option<usize> {
+        if self.comment.is_some() && data.get(pos) == self.comment.as_ref() {
+            return memchr::memchr(self.terminator, &data[pos..]).map(|i| pos + i + 1);
+        }
+        let escape = self.escape.unwrap_or(self.quote);
+        let mut in_quotes = false;
+        let mut escaped = pos;
+        for i in memchr::memchr3_iter(self.quote, self.terminator, escape, &data[pos..]) {
+            let i = pos + i;
+            if i < escaped {
+                continue;
+            }
+            if in_quotes && self.escape == Some(data[i]) {
+                escaped = i + 2;
+            } else if data[i] == self.quote {
+                in_quotes = !in_quotes;
+            } else if data[i] == self.terminator && !in_quotes {
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+
+    /// Offset just past the first record in `data` that is not a comment, if it is complete.
+    pub(crate) fn first_record_end(&self, data: &[u8]) -> Option<usize> {
+        let mut pos = 0;
+        loop {
+            let end = self.record_end(data, pos)?;
+            if self.comment.is_none() || data.get(pos) != self.comment.as_ref() {
+                return Some(end);
+            }
+            pos = end;
+        }
+    }
+}
+
+/// Splits the CSV records in `data[start..]` into ranges of about `target` bytes.
+///
+/// `start` must be a record boundary outside any quoted field, e.g. right after the header.
+/// Quotes are assumed to appear only in quoted fields (escaped as `""` inside them).
+pub(crate) fn csv_record_ranges(data: &[u8], start: usize, target: usize, syntax: &CsvSyntax) -> Vec<(usize, usize)> {
+    let target = target.max(1);
+    if !syntax.has_parity() {
+        let mut bounds = vec![start];
+        let mut next = start + target;
+        let mut pos = start;
+        while let Some(end) = syntax.record_end(data, pos) {
+            pos = end;
+            if pos >= next && pos < data.len() {
+                bounds.push(pos);
+                next = pos + target;
+            }
+        }
+        return to_ranges(bounds, data.len());
+    }
+    let edges: Vec<usize> = (start..data.len()).step_by(target).collect();
+    let quotes: Vec<usize> = edges
+        .par_windows(2)
+        .map(|w| memchr::memchr_iter(syntax.quote, &data[w[0]..w[1]]).count())
+        .collect();
+    let mut bounds = vec![start];
+    let mut in_quotes = false;
+    for (&nominal, count) in edges.iter().skip(1).zip(quotes) {
+        in_quotes ^= count % 2 == 1;
+        if let Some(bound) = next_record_start(data, nominal, in_quotes, syntax) {
+            if bound > *bounds.last().unwrap() && bound < data.len() {
+                bounds.push(bound);
+            }
+        }
+    }
+    to_ranges(bounds, data.len())
+}
+
+/// Offset just past the first terminator at or after `pos` that is outside a quoted field.
+fn next_record_start(data: &[u8], pos: usize, mut in_quotes: bool, syntax: &CsvSyntax) -> Option<usize> {
+    for i in memchr::memchr2_iter(syntax.quote, syntax.terminator, &data[pos..]) {
+        if data[pos + i] == syntax.quote {
+            in_quotes = !in_quotes;
+        } else if !in_quotes {
+            return Some(pos + i + 1);
+        }
+    }
+    None
+}
+
+/// Offset just past the last terminator outside a quoted field, for `data` starting at a record boundary.
+pub(crate) fn last_record_end(data: &[u8], syntax: &CsvSyntax) -> Option<usize> {
+    let mut end = None;
+    if !syntax.has_parity() {
+        let mut pos = 0;
+        while let Some(next) = syntax.record_end(data, pos) {
+            (pos, end) = (next, Some(next));
+        }
+        return end;
+    }
+    let mut in_quotes = false;
+    for i in memchr::memchr2_iter(syntax.quote, syntax.terminator, data) {
+        if data[i] == syntax.quote {
+            in_quotes = !in_quotes;
+        } else if !in_quotes {
+            end = Some(i + 1);
+        }
+    }
+    end
+}
+
+fn to_ranges(mut bounds: Vec<usize>, end: usize) -> Vec<(usize, usize)> {
+    bounds.push(end);
+    bounds
+        .windows(2)
+        .filter(|w| w[0] < w[1])
+        .map(|w| (w[0], w[1]))
+        .collect()
+}
@@ -0,0 +1,207 @@
+// --- Reconciling the headers of CSV inputs ---
+//
+// Inputs of one merge normally have identical headers. A schema mode lets extracts whose
+// columns are reordered, added or dropped be merged anyway: every input's header is
+// renamed by the caller's mapping, the merged header is built from all of them, and each
+// input gets a projection that maps its records onto the merged header.
+
+use anyhow::{Context, Result};
+use csv::{ByteRecord, StringRecord};
+use log::info;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// How the headers of CSV inputs are reconciled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchemaMode {
+    /// Headers must be identical, in the same order.
+    #[default]
+    Strict,
+    /// Headers must have the same columns, in any order; records are reordered to
+    /// the first input's order.
+    Reorder,
+    /// All columns of all inputs, in order of first appearance; columns an input
+    /// lacks are left empty.
+    Union,
+    /// Only the columns every input has, in the first input's order.
+    Intersect,
+}
+
+impl std::str::FromStr for SchemaMode {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode.trim().to_ascii_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "reorder" => Ok(Self::Reorder),
+            "union" => Ok(Self::Union),
+            "intersect" => Ok(Self::Intersect),
+            _ => anyhow::bail!("Unknown schema mode {:?} (strict, reorder, union or intersect)", mode),
+        }
+    }
+}
+
+/// How CSV inputs with differing headers are merged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema {
+    pub mode: SchemaMode,
+    /// Input column names mapped to the names they are merged under, e.g. `acct_no` to
+    /// `acct` for extracts that renamed a column. Applied before the headers are compared.
+    pub renames: HashMap<String, String>,
+}
+
+impl Schema {
+    /// Reads a rename mapping: a JSON object of old to new column names.
+    pub fn load_renames(path: &Path) -> Result<HashMap<String, String>> {
+        let json = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&json).with_context(|| {
+            format!("Invalid column mapping in {}: expected a JSON object of old to new names", path.display())
+        })
+    }
+}
+
+/// Maps the records of one input onto another header: the merged header of a
+/// [`Schema`], or the columns selected for an output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Projection {
+    /// For every output column, its index in the input, or `None` to leave it empty.
+    columns: Vec<Option<usize>>,
+    /// Number of columns in the input.
+    input_len: usize,
+}
+
+impl Projection {
+    /// Records are taken as they are.
+    pub fn identity(columns: usize) -> Self {
+        Self { columns: (0..columns).map(Some).collect(), input_len: columns }
+    }
+
+    /// Takes the input columns at `indices`, in that order.
+    pub(crate) fn select(indices: Vec<usize>, input_len: usize) -> Self {
+        Self { columns: indices.into_iter().map(Some).collect(), input_len }
+    }
+
+    /// This projection followed by `next`, which maps this one's output.
+    pub fn and_then(&self, next: &Projection) -> Projection {
+        let columns = next.columns.iter().map(|c| c.and_then(|i| self.columns.get(i).copied().flatten())).collect();
+        Self { columns, input_len: self.input_len }
+    }
+
+    /// Where input column `index` ends up, if it is kept.
+    pub(crate) fn output_index(&self, index: usize) -> Option<usize> {
+        self.columns.iter().position(|c| *c == Some(index))
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.columns.len() == self.input_len && self.columns.iter().enumerate().all(|(i, c)| *c == Some(i))
+    }
+
+    /// Number of columns in the input's records.
+    pub fn input_len(&self) -> usize {
+        self.input_len
+    }
+
+    /// Writes `record`, an input record, to `out` in the output columns.
+    pub fn apply(&self, record: &StringRecord, out: &mut StringRecord) {
+        out.clear();
+        for column in &self.columns {
+            out.push_field(column.and_then(|i| record.get(i)).unwrap_or(""));
+        }
+    }
+
+    /// The fields of `record`, an input record, in the output columns.
+    pub(crate) fn fields<'r>(&'r self, record: &'r ByteRecord) -> impl Iterator<Item = &'r [u8]> + 'r {
+        self.columns.iter().map(|c| c.and_then(|i| record.get(i)).unwrap_or_default())
+    }
+}
+
+/// Builds the merged header of `inputs` (each a name, for errors, and its header) and
+/// the projection of every input onto it.
+pub fn reconcile_headers(inputs: &[(&Path, StringRecord)], schema: &Schema) -> Result<(StringRecord, Vec<Projection>)> {
+    let Some((first_name, _)) = inputs.first() else {
+        anyhow::bail!("No input files provided");
+    };
+    let renamed: Vec<Vec<&str>> = inputs
+        .iter()
+        .map(|(_, header)| {
+            header.iter().map(|name| schema.renames.get(name).map(String::as_str).unwrap_or(name)).collect()
+        })
+        .collect();
+    if schema.mode == SchemaMode::Strict {
+        for ((name, _), columns) in inputs.iter().zip(&renamed).skip(1) {
+            if columns != &renamed[0] {
+                anyhow::bail!(
+                    "Header mismatch in file {}:\nExpected: {:?}\nFound:    {:?}",
+                    name.display(),
+                    renamed[0],
+                    columns
+                );
+            }
+        }
+        let merged: StringRecord = renamed[0].iter().collect();
+        let projections = vec![Projection::identity(merged.len()); inputs.len()];
+        return Ok((merged, projections));
+    }
+
+    for ((name, _), columns) in inputs.iter().zip(&renamed) {
+        let mut seen = HashSet::new();
+        if let Some(duplicate) = columns.iter().find(|c| !seen.insert(**c)) {
+            anyhow::bail!("Column {:?} appears twice in the header of {}", duplicate, name.display());
+        }
+    }
+    let merged: Vec<&str> = match schema.mode {
+        SchemaMode::Strict => unreachable!(),
+        SchemaMode::Reorder => {
+            let expected: HashSet<&str> = renamed[0].iter().copied().collect();
+            for ((name, _), columns) in inputs.iter().zip(&renamed).skip(1) {
+                let found: HashSet<&str> = columns.iter().copied().collect();
+                if found != expected {
+                    let mut missing: Vec<&str> = expected.difference(&found).copied().collect();
+                    let mut extra: Vec<&str> = found.difference(&expected).copied().collect();
+                    missing.sort_unstable();
+                    extra.sort_unstable();
+                    anyhow::bail!(
+                        "Columns of {} differ from {}: missing {:?}, extra {:?} (merge with union or intersect instead)",
+                        name.display(),
+                        first_name.display(),
+                        missing,
+                        extra
+                    );
+                }
+            }
+            renamed[0].clone()
+        }
+        SchemaMode::Union => {
+            let mut merged = Vec::new();
+            let mut seen = HashSet::new();
+            for columns in &renamed {
+                merged.extend(columns.iter().filter(|c| seen.insert(**c)));
+            }
+            merged
+        }
+        SchemaMode::Intersect => {
+            let merged: Vec<&str> = renamed[0]
+                .iter()
+                .filter(|c| renamed.iter().all(|columns| columns.contains(c)))
+                .copied()
+                .collect();
+            if merged.is_empty() {
+                anyhow::bail!("The inputs have no columns in common");
+            }
+            merged
+        }
+    };
+    let projections: Vec<Projection> = renamed
+        .iter()
+        .map(|columns| Projection {
+            columns: merged.iter().map(|m| columns.iter().position(|c| c == m)).collect(),
+            input_len: columns.len(),
+        })
+        .collect();
+    for ((name, _), projection) in inputs.iter().zip(&projections) {
+        if !projection.is_identity() {
+            info!("[schema] Mapping the columns of {} onto {:?}", name.display(), merged);
+        }
+    }
+    Ok((merged.into_iter().collect(), projections))
+}
@@ -0,0 +1,80 @@
+// --- Output column selection ---
+//
+// A run can write only some columns of its records, in a chosen order and under new
+// names. Selection only applies where the final output (or a split part) is written:
+// chunks and intermediate merge files keep every column, so sort keys and partition
+// columns do not have to be selected.
+
+use anyhow::{Context, Result};
+use csv::StringRecord;
+
+use super::csv_key::CsvSortColumn;
+use super::schema::Projection;
+use crate::mt_log::mt_log_record::MTLogField;
+
+/// An output column: a CSV column or MT log field, optionally written under a new name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectColumn {
+    pub name: String,
+    pub rename: Option<String>,
+}
+
+impl SelectColumn {
+    /// Parses `name` or `name=new_name`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (name, rename) = match spec.split_once('=') {
+            Some((name, rename)) => (name.trim(), Some(rename.trim())),
+            None => (spec.trim(), None),
+        };
+        if name.is_empty() || rename.is_some_and(str::is_empty) {
+            anyhow::bail!("Invalid column selection {:?}: expected `name` or `name=new_name`", spec);
+        }
+        Ok(Self { name: name.to_string(), rename: rename.map(str::to_string) })
+    }
+
+    /// The name written to the output header.
+    fn output_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.rename.as_deref().unwrap_or(name)
+    }
+}
+
+/// The output header and projection for writing the `select` columns of records with
+/// `headers` (names are matched case-insensitively). An empty `select` keeps every column.
+pub fn select_columns(headers: &StringRecord, select: &[SelectColumn]) -> Result<(StringRecord, Projection)> {
+    if select.is_empty() {
+        return Ok((headers.clone(), Projection::identity(headers.len())));
+    }
+    let mut output = StringRecord::new();
+    let mut indices = Vec::with_capacity(select.len());
+    for column in select {
+        let index = headers
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(&column.name))
+            .with_context(|| format!("Selected column '{}' not found in headers {:?}", column.name, headers))?;
+        output.push_field(column.output_name(&headers[index]));
+        indices.push(index);
+    }
+    Ok((output, Projection::select(indices, headers.len())))
+}
+
+/// The sort columns of a selected output: the longest prefix of `columns` that was
+/// selected, by its index in the output, since the output is only sorted by that prefix.
+pub(crate) fn selected_sort_columns(columns: &[CsvSortColumn], projection: &Projection) -> Vec<CsvSortColumn> {
+    columns
+        .iter()
+        .map_while(|c| projection.output_index(c.index).map(|index| CsvSortColumn { index, ..*c }))
+        .collect()
+}
+
+/// The CSV header and fields for writing the `select` fields of MT log records
+/// (names as for [`MTLogField::find`]).
+pub(crate) fn select_mtlog_fields(select: &[SelectColumn]) -> Result<(StringRecord, Vec<&'static MTLogField>)> {
+    let mut output = StringRecord::new();
+    let mut fields = Vec::with_capacity(select.len());
+    for column in select {
+        let field = MTLogField::find(&column.name).with_context(|| format!("Unknown MT log field: {}", column.name))?;
+        output.push_field(column.output_name(field.name));
+        fields.push(field);
+    }
+    Ok((output, fields))
+}
@@ -0,0 +1,40 @@
+// --- Spill storage ---
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+/// Where a run spills its sorted chunks and intermediate merge passes.
+///
+/// Spill files are memory-mapped, seeked and read by byte range, so they live in a
+/// local directory; an implementation decides which one (e.g. a fast scratch volume,
+/// or a quota-checked directory per tenant). Every directory is removed when the
+/// returned [`TempDir`] is dropped, including on error and cancellation.
+pub trait SpillStorage: Send + Sync {
+    /// Creates a new, empty directory for one phase of a run.
+    fn create_dir(&self) -> Result<TempDir>;
+}
+
+/// Spills to temp directories under `root`, or under the system temp dir (`TMPDIR`).
+#[derive(Debug, Default, Clone)]
+pub struct TempDirSpill {
+    pub root: Option<PathBuf>,
+}
+
+impl TempDirSpill {
+    pub fn in_dir(root: impl Into<PathBuf>) -> Self {
+        Self { root: Some(root.into()) }
+    }
+}
+
+impl SpillStorage for TempDirSpill {
+    fn create_dir(&self) -> Result<TempDir> {
+        match &self.root {
+            Some(root) => tempfile::Builder::new()
+                .prefix(".spill")
+                .tempdir_in(root)
+                .with_context(|| format!("Failed to create spill directory in {}", root.display())),
+            None => TempDir::new().context("Failed to create spill directory"),
+        }
+    }
+}
@@ -0,0 +1,777 @@
+// --- Splitting one input into many outputs ---
+//
+// Records are routed to partitions (one per key value, or a single one) and each
+// partition is cut into parts by record count or size, numbered by the template's `{part}`.
+// Alternatively, records are dealt to a fixed number of parts by key hash or round-robin.
+// Parts are written to a staging directory inside the output directory and only renamed
+// to their templated names once the whole input has been split, so an interrupted split
+// never leaves a partial set of files.
+//
+// A split by a high-cardinality key can have more partitions than the process can keep
+// files open for: records are buffered per part, and when a buffer is flushed to a part
+// whose file was closed, the least recently used file is closed and the part's file is
+// reopened for appending.
+
+use anyhow::{Context, Result};
+use csv::{ByteRecord, StringRecord};
+use log::info;
+use num_format::{Locale, ToFormattedString};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+use tempfile::TempDir;
+
+use super::cancel::CANCEL_CHECK_INTERVAL;
+use super::csv_key::resolve_sort_columns;
+use super::dialect::CsvDialect;
+use super::filter::Filter;
+use super::mtlog::MTLogSortColumn;
+use super::options::RunOptions;
+use super::output::{write_small_file_atomic, OutputOptions};
+use super::presorted::Presorted;
+use super::select::{select_columns, select_mtlog_fields};
+use crate::mt_log::mt_log_record::MTLogField;
+use crate::mt_log::reader::MTLogReader;
+
+/// Default template for splitting a CSV file by size.
+pub const CSV_TEMPLATE: &str = "{stem}_part_{part:04}.csv";
+/// Default template for splitting a CSV file by key.
+pub const CSV_KEY_TEMPLATE: &str = "{key}/{stem}_{part:04}.csv";
+/// Default template for splitting an MT log file by size.
+pub const MTLOG_TEMPLATE: &str = "{stem}_part_{part:04}.mtlog";
+/// Default template for splitting an MT log file by key.
+pub const MTLOG_KEY_TEMPLATE: &str = "{key}/{stem}_{part:04}.mtlog";
+
+/// Bytes buffered per part before they are written to its file.
+const PART_BUF_SIZE: usize = 64 * 1024;
+
+fn get_split_max_open_files() -> usize {
+    std::env::var("SPLIT_MAX_OPEN_FILES")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|&v| v >= 1)
+        .unwrap_or(128)
+}
+
+fn get_split_buffer_size() -> usize {
+    std::env::var("SPLIT_BUFFER_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|&v| v >= 1)
+        .map(|mb| mb * 1024 * 1024)
+        .unwrap_or(256 * 1024 * 1024)
+}
+
+/// How the files of a split are named and cut.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitOptions {
+    /// Output file name, relative to the output directory. Placeholders: `{stem}` (the
+    /// input's file stem), `{key}` (the partition key) and `{part}` (the part number
+    /// within the partition, zero-padded with e.g. `{part:04}`). `/` creates subdirectories.
+    pub template: String,
+    /// Write one set of files per value of these CSV columns or MT log fields (joined with
+    /// `_` if there are several). Empty splits the input into one sequence of parts.
+    pub partition_by: Vec<String>,
+    /// Start a new part after this many records.
+    pub max_rows: Option<usize>,
+    /// Start a new part before a record would take it over this many bytes (including the
+    /// CSV header). Records are never broken, so a record larger than this gets a part of its own.
+    pub max_bytes: Option<u64>,
+    /// Deal the records to exactly this many parts, e.g. for a loader that reads N files in
+    /// parallel: by a hash of `hash_by`, so all records with the same values end up in the
+    /// same part, or round-robin if `hash_by` is empty. Cannot be combined with the other modes.
+    pub parts: Option<usize>,
+    /// CSV columns or MT log fields hashed to pick the part when `parts` is set.
+    pub hash_by: Vec<String>,
+}
+
+impl SplitOptions {
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+            partition_by: Vec::new(),
+            max_rows: None,
+            max_bytes: None,
+            parts: None,
+            hash_by: Vec::new(),
+        }
+    }
+
+    /// The columns or fields that route records: `partition_by` or `hash_by`.
+    fn key_columns(&self) -> &[String] {
+        match self.parts {
+            Some(_) => &self.hash_by,
+            None => &self.partition_by,
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        match self.parts {
+            Some(0) => anyhow::bail!("Cannot split into 0 parts"),
+            Some(_) if !self.partition_by.is_empty() => {
+                anyhow::bail!("Splitting into a fixed number of parts cannot be combined with partition columns")
+            }
+            Some(_) if self.max_rows.is_some() || self.max_bytes.is_some() => {
+                anyhow::bail!("Splitting into a fixed number of parts cannot be combined with row or size limits")
+            }
+            None if !self.hash_by.is_empty() => anyhow::bail!("Hash columns need a number of parts to split into"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What a split wrote, saved as `<output_dir>/<stem>.manifest.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitManifest {
+    pub input: PathBuf,
+    /// Columns or MT log fields the records were partitioned by.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub partition_by: Vec<String>,
+    /// Columns or MT log fields hashed to deal the records to a fixed number of parts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hash_by: Vec<String>,
+    pub files: Vec<SplitFile>,
+}
+
+/// One output file of a split.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitFile {
+    /// Path relative to the output directory.
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub part: usize,
+    pub records: u64,
+    pub bytes: u64,
+}
+
+impl SplitManifest {
+    /// Total records across all files.
+    pub fn records(&self) -> u64 {
+        self.files.iter().map(|f| f.records).sum()
+    }
+
+    /// Writes the manifest to [`manifest_path`] in `output_dir`.
+    pub fn write(&self, output_dir: &Path) -> Result<()> {
+        let mut json = serde_json::to_vec_pretty(self)?;
+        json.push(b'\n');
+        let path = manifest_path(output_dir, &self.input);
+        write_small_file_atomic(&path, &json)?;
+        info!("[split] Wrote manifest {}", path.display());
+        Ok(())
+    }
+}
+
+/// Splits a CSV file into parts as `split` says, each starting with the input's header
+/// (or the `options.select` columns only).
+///
+/// With `sort_columns` (`name[:type][:asc|desc]`, as for [`super::parallel_merge_sort_with`])
+/// the input is sorted first, so the parts (of each key) follow each other in sorted order;
+/// otherwise records keep their input order. Partition columns are matched case-insensitively.
+/// The input is read in `options.input_dialect` and the parts are written in `options.output_dialect`.
+pub fn split_csv(
+    input: &Path,
+    output_dir: &Path,
+    sort_columns: &[&str],
+    split: &SplitOptions,
+    options: &RunOptions,
+) -> Result<SplitManifest> {
+    let timer = Instant::now();
+    if !sort_columns.is_empty() {
+        check_sort_columns(input, sort_columns, &options.input_dialect)?;
+    }
+    let mut writer = PartitionWriter::create(output_dir, input, split)?;
+    let (source, dialect) = match sort_columns.is_empty() {
+        true => (input.to_path_buf(), options.input_dialect),
+        false => {
+            // Sorted into the default dialect, with positional names if the input has no header
+            let sorted = writer.staging.path().join("sorted.csv");
+            info!("[split] Sorting {:?} by {:?}", input, sort_columns);
+            super::parallel_merge_sort_with(&[input.to_path_buf()], &sorted, sort_columns, &sort_options(options))?;
+            (sorted, CsvDialect::default())
+        }
+    };
+    let file = File::open(&source).with_context(|| format!("Failed to open input file: {}", input.display()))?;
+    let mut rdr = dialect.reader().from_reader(dialect.decode_reader(file));
+    let headers = dialect.read_headers(&mut rdr)?;
+    let key_indices = split
+        .key_columns()
+        .iter()
+        .map(|col| {
+            headers
+                .iter()
+                .position(|h| h.trim().eq_ignore_ascii_case(col.trim()))
+                .with_context(|| format!("Split column '{}' not found in headers {:?}", col, headers))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // A sorted input was already filtered by the sort
+    let filter = match sort_columns.is_empty() {
+        true => options.filter.as_ref().map(|f| f.for_csv(&headers)).transpose()?,
+        false => None,
+    };
+    let output_dialect = &options.output_dialect;
+    let (output_headers, select) = select_columns(&headers, &options.select)?;
+    let select = (!select.is_identity()).then_some(select);
+    if output_dialect.has_header {
+        writer.header = Some(encode_header(output_dialect, &output_headers)?);
+    }
+
+    // Records are re-encoded into `scratch`, which is replaced once it has grown
+    let mut scratch = output_dialect.writer().from_writer(Vec::new());
+    let mut record = ByteRecord::new();
+    let mut route = Route::new(split);
+    let mut count = 0usize;
+    while rdr.read_byte_record(&mut record)? {
+        count += 1;
+        if count.is_multiple_of(CANCEL_CHECK_INTERVAL) {
+            options.cancel.check()?;
+        }
+        if filter.as_ref().is_some_and(|f| !f.matches(|i| record.get(i).unwrap_or_default())) {
+            continue;
+        }
+        let start = scratch.get_ref().len();
+        match &select {
+            Some(select) => scratch.write_record(select.fields(&record))?,
+            None => scratch.write_byte_record(&record)?,
+        }
+        scratch.flush()?;
+        let key = key_indices.iter().map(|&i| record.get(i).unwrap_or_default());
+        writer.route(&mut route, key, &scratch.get_ref()[start..])?;
+        if start > PART_BUF_SIZE {
+            scratch = output_dialect.writer().from_writer(Vec::new());
+        }
+    }
+    let manifest = writer.finish(input, split.key_columns().to_vec())?;
+    log_split(&manifest, output_dir, timer);
+    Ok(manifest)
+}
+
+/// Fails unless at least one of `sort_columns` is in the header of `input`, since the
+/// sort would otherwise leave the parts in input order.
+fn check_sort_columns(input: &Path, sort_columns: &[&str], dialect: &CsvDialect) -> Result<()> {
+    let file = File::open(input).with_context(|| format!("Failed to open input file: {}", input.display()))?;
+    let mut rdr = dialect.reader().from_reader(dialect.decode_reader(file));
+    let headers = dialect.read_headers(&mut rdr)?;
+    if resolve_sort_columns(&headers, sort_columns).is_empty() {
+        anyhow::bail!("No valid sort columns found: none of {:?} is in headers {:?}", sort_columns, headers);
+    }
+    Ok(())
+}
+
+/// Splits an MT log file into parts as `split` says. Partition fields are MT log field
+/// names such as `mit_isc_tran_branch_code` (or indices into `mt_log_record::FIELDS`).
+///
+/// With `sort_columns` the input is sorted first, so the parts (of each key) follow each
+/// other in sorted order; otherwise records keep their input order. With `options.select`
+/// the parts are CSV files of the selected fields, in `options.output_dialect`.
+pub fn split_mtlog(
+    input: &Path,
+    output_dir: &Path,
+    sort_columns: &[MTLogSortColumn],
+    split: &SplitOptions,
+    options: &RunOptions,
+) -> Result<SplitManifest> {
+    let fields = split
+        .key_columns()
+        .iter()
+        .map(|name| MTLogField::find(name.trim()).with_context(|| format!("Unknown MT log field: {}", name)))
+        .collect::<Result<Vec<_>>>()?;
+    let timer = Instant::now();
+    let mut writer = PartitionWriter::create(output_dir, input, split)?;
+    let source = match sort_columns.is_empty() {
+        true => input.to_path_buf(),
+        false => {
+            let sorted = writer.staging.path().join("sorted.mtlog");
+            info!("[split] Sorting {:?} by {:?}", input, sort_columns);
+            super::parallel_merge_sort_mtlog_with(&[input.to_path_buf()], &sorted, sort_columns, &sort_options(options))?;
+            sorted
+        }
+    };
+    let select = match options.select.is_empty() {
+        true => None,
+        false => Some(select_mtlog_fields(&options.select)?),
+    };
+    let output_dialect = &options.output_dialect;
+    if let Some((headers, _)) = &select {
+        if output_dialect.has_header {
+            writer.header = Some(encode_header(output_dialect, headers)?);
+        }
+    }
+    let filter = match sort_columns.is_empty() {
+        true => options.filter.as_ref().map(Filter::for_mtlog).transpose()?,
+        false => None,
+    };
+    let mut reader = MTLogReader::open(&source)?;
+    let mut line = Vec::new();
+    let mut csv = output_dialect.writer().from_writer(Vec::new());
+    let mut route = Route::new(split);
+    let mut count = 0usize;
+    while reader.next_record()? {
+        count += 1;
+        if count.is_multiple_of(CANCEL_CHECK_INTERVAL) {
+            options.cancel.check()?;
+        }
+        let record = reader.record();
+        if filter.as_ref().is_some_and(|f| !f.matches_mtlog(record)) {
+            continue;
+        }
+        line.clear();
+        match &select {
+            Some((_, fields)) => {
+                let start = csv.get_ref().len();
+                csv.write_record(fields.iter().map(|field| field.bytes(record)))?;
+                csv.flush()?;
+                line.extend_from_slice(&csv.get_ref()[start..]);
+                if start > PART_BUF_SIZE {
+                    csv = output_dialect.writer().from_writer(Vec::new());
+                }
+            }
+            None => {
+                line.extend_from_slice(record);
+                line.push(b'\n');
+            }
+        }
+        let key = fields.iter().map(|field| field.bytes(record));
+        writer.route(&mut route, key, &line)?;
+    }
+    let key_names = fields.iter().map(|f| f.name.to_string()).collect();
+    let manifest = writer.finish(input, key_names)?;
+    log_split(&manifest, output_dir, timer);
+    Ok(manifest)
+}
+
+/// Options for sorting a split's input into its staging directory: the sorted copy is
+/// an intermediate file, so it gets none of the caller's output sidecars and keeps every
+/// column, as CSV (or MT log) in the default dialect.
+fn sort_options(options: &RunOptions) -> RunOptions {
+    RunOptions {
+        output: OutputOptions::default(),
+        presorted: Presorted::No,
+        output_dialect: CsvDialect::default(),
+        select: Vec::new(),
+        columnar: None,
+        ..options.clone()
+    }
+}
+
+/// `headers` as a header row in `dialect`.
+fn encode_header(dialect: &CsvDialect, headers: &StringRecord) -> Result<Vec<u8>> {
+    let mut header = dialect.writer().from_writer(Vec::new());
+    header.write_record(headers)?;
+    header.into_inner().map_err(|e| anyhow::anyhow!("Failed to write header: {}", e))
+}
+
+/// `<output_dir>/<stem>.manifest.json` for a split of `input`.
+pub fn manifest_path(output_dir: &Path, input: &Path) -> PathBuf {
+    output_dir.join(format!("{}.manifest.json", file_stem(input)))
+}
+
+fn file_stem(input: &Path) -> String {
+    input
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "split".to_string())
+}
+
+fn log_split(manifest: &SplitManifest, output_dir: &Path, timer: Instant) {
+    info!(
+        "[split] Split {:?}: {} records into {} files in {:?} in {:.2?}",
+        manifest.input,
+        manifest.records().to_formatted_string(&Locale::en),
+        manifest.files.len().to_formatted_string(&Locale::en),
+        output_dir,
+        timer.elapsed()
+    );
+}
+
+/// A parsed [`SplitOptions::template`].
+struct Template {
+    pieces: Vec<Piece>,
+}
+
+enum Piece {
+    Text(String),
+    Stem,
+    Key,
+    Part(usize),
+}
+
+impl Template {
+    /// Parses `template`, which must name a different file for every key (`by_key`) and
+    /// every part (`by_part`) so no two parts end up in the same file, and can only use
+    /// `{key}` when there is one.
+    fn parse(template: &str, by_key: bool, by_part: bool) -> Result<Self> {
+        let path = Path::new(template);
+        if path.components().any(|c| !matches!(c, Component::Normal(_))) {
+            anyhow::bail!("Split template must be a relative path without `..`: {}", template);
+        }
+        let mut pieces = Vec::new();
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                pieces.push(Piece::Text(rest[..open].to_string()));
+            }
+            let close = rest[open..]
+                .find('}')
+                .map(|i| open + i)
+                .with_context(|| format!("Unclosed `{{` in split template: {}", template))?;
+            pieces.push(match &rest[open + 1..close] {
+                "stem" => Piece::Stem,
+                "key" => Piece::Key,
+                "part" => Piece::Part(0),
+                spec => match spec.strip_prefix("part:").and_then(|w| w.parse::<usize>().ok()) {
+                    Some(width) => Piece::Part(width),
+                    None => anyhow::bail!("Unknown placeholder `{{{}}}` in split template: {}", spec, template),
+                },
+            });
+            rest = &rest[close + 1..];
+        }
+        if !rest.is_empty() {
+            pieces.push(Piece::Text(rest.to_string()));
+        }
+        let has_key = pieces.iter().any(|p| matches!(p, Piece::Key));
+        if by_key && !has_key {
+            anyhow::bail!("Split template needs a `{{key}}` placeholder: {}", template);
+        }
+        if !by_key && has_key {
+            anyhow::bail!("Split template uses `{{key}}`, but no partition columns are given: {}", template);
+        }
+        if by_part && !pieces.iter().any(|p| matches!(p, Piece::Part(_))) {
+            anyhow::bail!("Split template needs a `{{part}}` placeholder: {}", template);
+        }
+        Ok(Self { pieces })
+    }
+
+    fn render(&self, stem: &str, key: &str, part: usize) -> PathBuf {
+        let mut name = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => name.push_str(text),
+                Piece::Stem => name.push_str(stem),
+                Piece::Key => name.push_str(&sanitize_key(key)),
+                Piece::Part(width) => name.push_str(&format!("{:0width$}", part, width = *width)),
+            }
+        }
+        PathBuf::from(name)
+    }
+}
+
+/// Makes a key safe to use as (part of) a file name.
+fn sanitize_key(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    match key.as_str() {
+        "" => "_empty".to_string(),
+        "." | ".." => key.replace('.', "_"),
+        _ => key,
+    }
+}
+
+/// One output file being written.
+struct Part {
+    key: String,
+    number: usize,
+    path: PathBuf,
+    staged: PathBuf,
+    records: u64,
+    bytes: u64,
+    buf: Vec<u8>,
+    file: Option<File>,
+    last_used: u64,
+}
+
+/// How a record's key fields pick its part.
+enum Route {
+    /// One sequence of parts per key value (a single one without key fields).
+    Key(String),
+    /// Part `hash(key) % parts`.
+    Hash(usize),
+    RoundRobin { parts: usize, next: usize },
+}
+
+impl Route {
+    fn new(split: &SplitOptions) -> Self {
+        match split.parts {
+            Some(parts) if !split.hash_by.is_empty() => Self::Hash(parts),
+            Some(parts) => Self::RoundRobin { parts, next: 0 },
+            None => Self::Key(String::new()),
+        }
+    }
+}
+
+/// FNV-1a over the key fields (separated by `0x1F`), so a key lands in the same part in every run.
+fn hash_key<'r>(fields: impl Iterator<Item = &'r [u8]>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for (i, field) in fields.enumerate() {
+        let separator: &[u8] = if i > 0 { &[0x1F] } else { &[] };
+        for &b in separator.iter().chain(field) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// Routes records to per-key parts in a staging directory, keeping at most
+/// `SPLIT_MAX_OPEN_FILES` files open.
+struct PartitionWriter {
+    output_dir: PathBuf,
+    staging: TempDir,
+    template: Template,
+    stem: String,
+    /// Written at the start of every part.
+    header: Option<Vec<u8>>,
+    max_rows: Option<usize>,
+    max_bytes: Option<u64>,
+    parts: Vec<Part>,
+    /// Index of the part currently written for each key.
+    current: HashMap<String, usize>,
+    /// Index of the part for each of a fixed number of parts, once created.
+    buckets: Vec<Option<usize>>,
+    paths: HashSet<PathBuf>,
+    open: usize,
+    max_open: usize,
+    buffered: usize,
+    max_buffered: usize,
+    clock: u64,
+}
+
+impl PartitionWriter {
+    fn create(output_dir: &Path, input: &Path, split: &SplitOptions) -> Result<Self> {
+        split.check()?;
+        let by_part = split.max_rows.is_some() || split.max_bytes.is_some() || split.parts.is_some();
+        let template = Template::parse(&split.template, !split.partition_by.is_empty(), by_part)?;
+        std::fs::create_dir_all(output_dir)
+            .with_context(|| format!("Failed to create output directory {}", output_dir.display()))?;
+        let staging = tempfile::Builder::new()
+            .prefix(".split")
+            .tempdir_in(output_dir)
+            .with_context(|| format!("Failed to create staging directory in {}", output_dir.display()))?;
+        Ok(Self {
+            output_dir: output_dir.to_path_buf(),
+            staging,
+            template,
+            stem: file_stem(input),
+            header: None,
+            max_rows: split.max_rows,
+            max_bytes: split.max_bytes,
+            parts: Vec::new(),
+            current: HashMap::new(),
+            buckets: vec![None; split.parts.unwrap_or(0)],
+            paths: HashSet::new(),
+            open: 0,
+            max_open: get_split_max_open_files(),
+            buffered: 0,
+            max_buffered: get_split_buffer_size(),
+            clock: 0,
+        })
+    }
+
+    /// Appends `record` (including its line terminator) to the part that `route` picks
+    /// for the record's `key` fields.
+    fn route<'r>(&mut self, route: &mut Route, key: impl Iterator<Item = &'r [u8]>, record: &[u8]) -> Result<()> {
+        match route {
+            Route::Key(buf) => {
+                buf.clear();
+                for (i, field) in key.enumerate() {
+                    if i > 0 {
+                        buf.push('_');
+                    }
+                    buf.push_str(&String::from_utf8_lossy(field));
+                }
+                self.write(buf, record)
+            }
+            Route::Hash(parts) => self.write_bucket((hash_key(key) % *parts as u64) as usize, record),
+            Route::RoundRobin { parts, next } => {
+                let bucket = *next;
+                *next = (bucket + 1) % *parts;
+                self.write_bucket(bucket, record)
+            }
+        }
+    }
+
+    /// Appends `record` to the current part for `key`, starting a new part if it is full.
+    fn write(&mut self, key: &str, record: &[u8]) -> Result<()> {
+        let index = match self.current.get(key) {
+            Some(&index) if !self.is_full(index, record.len()) => index,
+            Some(&index) => {
+                self.close(index)?;
+                self.new_part(key, self.parts[index].number + 1)?
+            }
+            None => self.new_part(key, 0)?,
+        };
+        self.append(index, record)
+    }
+
+    /// Appends `record` to part number `bucket` of a fixed number of parts.
+    fn write_bucket(&mut self, bucket: usize, record: &[u8]) -> Result<()> {
+        let index = match self.buckets[bucket] {
+            Some(index) => index,
+            None => self.new_bucket(bucket)?,
+        };
+        self.append(index, record)
+    }
+
+    fn new_bucket(&mut self, bucket: usize) -> Result<usize> {
+        let index = self.new_part(&bucket.to_string(), bucket)?;
+        self.buckets[bucket] = Some(index);
+        Ok(index)
+    }
+
+    fn append(&mut self, index: usize, record: &[u8]) -> Result<()> {
+        let part = &mut self.parts[index];
+        part.buf.extend_from_slice(record);
+        part.records += 1;
+        part.bytes += record.len() as u64;
+        self.buffered += record.len();
+        if part.buf.len() >= PART_BUF_SIZE {
+            self.flush(index)?;
+        }
+        if self.buffered >= self.max_buffered {
+            for index in 0..self.parts.len() {
+                self.flush(index)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether part `index` has to be closed before a record of `len` bytes is written.
+    fn is_full(&self, index: usize, len: usize) -> bool {
+        let part = &self.parts[index];
+        self.max_rows.is_some_and(|max| part.records >= max as u64)
+            || self.max_bytes.is_some_and(|max| part.records > 0 && part.bytes + len as u64 > max)
+    }
+
+    fn new_part(&mut self, key: &str, number: usize) -> Result<usize> {
+        let path = self.template.render(&self.stem, key, number);
+        if !self.paths.insert(path.clone()) {
+            let other = self.parts.iter().find(|p| p.path == path).map(|p| p.key.as_str()).unwrap_or_default();
+            anyhow::bail!(
+                "Keys {:?} and {:?} both map to {}; they only differ in characters that are replaced in file names",
+                other,
+                key,
+                path.display()
+            );
+        }
+        let index = self.parts.len();
+        let mut buf = Vec::new();
+        if let Some(header) = &self.header {
+            buf.extend_from_slice(header);
+            self.buffered += header.len();
+        }
+        self.parts.push(Part {
+            key: key.to_string(),
+            number,
+            path,
+            staged: self.staging.path().join(format!("part_{:06}", index)),
+            records: 0,
+            bytes: buf.len() as u64,
+            buf,
+            file: None,
+            last_used: 0,
+        });
+        self.current.insert(key.to_string(), index);
+        Ok(index)
+    }
+
+    /// Writes the buffered records of part `index`, (re)opening its file if needed.
+    fn flush(&mut self, index: usize) -> Result<()> {
+        if self.parts[index].buf.is_empty() {
+            return Ok(());
+        }
+        if self.parts[index].file.is_none() {
+            if self.open >= self.max_open {
+                self.close_least_recently_used();
+            }
+            let staged = &self.parts[index].staged;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(staged)
+                .with_context(|| format!("Failed to open {}", staged.display()))?;
+            self.parts[index].file = Some(file);
+            self.open += 1;
+        }
+        self.clock += 1;
+        let part = &mut self.parts[index];
+        part.last_used = self.clock;
+        if let Some(file) = &mut part.file {
+            file.write_all(&part.buf)
+                .with_context(|| format!("Failed to write {}", part.staged.display()))?;
+        }
+        self.buffered -= part.buf.len();
+        part.buf.clear();
+        Ok(())
+    }
+
+    fn close_least_recently_used(&mut self) {
+        let lru = self
+            .parts
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.file.is_some())
+            .min_by_key(|(_, p)| p.last_used)
+            .map(|(i, _)| i);
+        if let Some(index) = lru {
+            self.parts[index].file = None;
+            self.open -= 1;
+        }
+    }
+
+    /// Flushes and closes part `index`, which gets no more records.
+    fn close(&mut self, index: usize) -> Result<()> {
+        self.flush(index)?;
+        let part = &mut self.parts[index];
+        part.buf = Vec::new();
+        if part.file.take().is_some() {
+            self.open -= 1;
+        }
+        Ok(())
+    }
+
+    /// Moves every part to its templated path and writes the manifest, which lists
+    /// `key_names` as the partition or hash columns.
+    fn finish(mut self, input: &Path, key_names: Vec<String>) -> Result<SplitManifest> {
+        // A fixed number of parts always gives that many files, even if some stay empty
+        for bucket in 0..self.buckets.len() {
+            if self.buckets[bucket].is_none() {
+                let index = self.new_bucket(bucket)?;
+                let staged = &self.parts[index].staged;
+                File::create(staged).with_context(|| format!("Failed to create {}", staged.display()))?;
+            }
+        }
+        let (partition_by, hash_by) = match self.buckets.is_empty() {
+            true => (key_names, Vec::new()),
+            false => (Vec::new(), key_names),
+        };
+        for index in 0..self.parts.len() {
+            self.close(index)?;
+        }
+        let mut files = Vec::with_capacity(self.parts.len());
+        for part in &self.parts {
+            let path = self.output_dir.join(&part.path);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+            }
+            std::fs::rename(&part.staged, &path)
+                .with_context(|| format!("Failed to move split output into place: {}", path.display()))?;
+            files.push(SplitFile {
+                path: part.path.clone(),
+                key: (!partition_by.is_empty()).then(|| part.key.clone()),
+                part: part.number,
+                records: part.records,
+                bytes: part.bytes,
+            });
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let manifest = SplitManifest { input: input.to_path_buf(), partition_by, hash_by, files };
+        manifest.write(&self.output_dir)?;
+        Ok(manifest)
+    }
+}
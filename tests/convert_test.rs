@@ -0,0 +1,94 @@
+use split_merge_hub_demo::mt_log::mt_log_record::MTLogRecord;
+use split_merge_hub_demo::parallel_merge::{convert_file, ConvertFormat, RunOptions};
+use std::fs;
+
+#[test]
+fn test_convert_mtlog_to_csv_and_jsonl_and_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("mt_log01");
+    let content: String = (0..50)
+        .map(|i| {
+            let record = MTLogRecord {
+                milog_rec_sys_date: 20240101 + i % 28,
+                milog_rec_sys_time: format!("{:06}", i * 37),
+                mit_isc_tran_branch_code: format!("{:04}", i % 5),
+                mit_mq_trans_desc: "TRANSFER, \"ATM\"".to_string(),
+                mit_dr_tran_amount: (i * 1000) as i64,
+                ..MTLogRecord::default()
+            };
+            record.to_fixed_checked().unwrap() + "\n"
+        })
+        .collect();
+    fs::write(&input, &content).unwrap();
+    let options = RunOptions::default();
+
+    let csv = dir.path().join("mt_log01.csv");
+    convert_file(&input, &csv, ConvertFormat::MtLog, ConvertFormat::Csv, &options).unwrap();
+    let exported = fs::read_to_string(&csv).unwrap();
+    let mut lines = exported.lines();
+    assert!(lines.next().unwrap().starts_with("milog_rec_sys_date,milog_rec_sys_time,milog_rec_taskno,"));
+    assert!(lines.nth(1).unwrap().starts_with("20240102,000037,0,"));
+
+    let jsonl = dir.path().join("mt_log01.jsonl");
+    convert_file(&input, &jsonl, ConvertFormat::MtLog, ConvertFormat::Jsonl, &options).unwrap();
+    let first: serde_json::Value = serde_json::from_str(fs::read_to_string(&jsonl).unwrap().lines().next().unwrap()).unwrap();
+    assert_eq!(first["milog_rec_sys_date"], 20240101);
+    assert_eq!(first["mit_mq_trans_desc"], "TRANSFER, \"ATM\"");
+
+    for converted in [&csv, &jsonl] {
+        let back = dir.path().join("back.mtlog");
+        convert_file(converted, &back, ConvertFormat::from_path(converted), ConvertFormat::MtLog, &options).unwrap();
+        assert_eq!(fs::read_to_string(&back).unwrap(), content);
+    }
+
+    // A value wider than its field fails instead of shifting the fields after it
+    let wide = dir.path().join("wide.csv");
+    fs::write(&wide, "milog_rec_sys_date,mit_isc_tran_branch_code\n20240101,00001\n").unwrap();
+    let back = dir.path().join("wide.mtlog");
+    let err = convert_file(&wide, &back, ConvertFormat::Csv, ConvertFormat::MtLog, &options).unwrap_err();
+    assert!(err.to_string().contains("mit_isc_tran_branch_code does not fit in 4 bytes"), "{}", err);
+    assert!(!back.exists());
+}
+
+#[test]
+fn test_convert_mtlog_to_arrow_ipc() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Int64Type, UInt64Type};
+    use split_merge_hub_demo::parallel_merge::{ColumnarCompression, ColumnarFormat, ColumnarOptions};
+
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("mt_log01");
+    let content: String = (0..30)
+        .map(|i| {
+            let record = MTLogRecord {
+                milog_rec_sys_date: 20240101 + i,
+                mit_isc_tran_branch_code: format!("{:04}", i % 5),
+                mit_dr_tran_amount: i as i64 * 100,
+                ..MTLogRecord::default()
+            };
+            record.to_fixed_checked().unwrap() + "\n"
+        })
+        .collect();
+    fs::write(&input, &content).unwrap();
+    let output = dir.path().join("mt_log01.arrow");
+    let columnar = ColumnarOptions {
+        row_group_rows: 8,
+        compression: Some(ColumnarCompression::Zstd),
+        ..ColumnarOptions::new(ColumnarFormat::ArrowIpc)
+    };
+    let options = RunOptions { columnar: Some(columnar), ..RunOptions::default() };
+
+    convert_file(&input, &output, ConvertFormat::MtLog, ConvertFormat::from_path(&output), &options).unwrap();
+
+    let reader = arrow_ipc::reader::FileReader::try_new(fs::File::open(&output).unwrap(), None).unwrap();
+    let batches: Vec<_> = reader.map(|b| b.unwrap()).collect();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(), [8, 8, 8, 6]);
+    let last = &batches[3];
+    let date = last.column_by_name("milog_rec_sys_date").unwrap().as_primitive::<UInt64Type>();
+    assert_eq!(date.value(5), 20240130);
+    assert_eq!(last.column_by_name("mit_isc_tran_branch_code").unwrap().as_string::<i32>().value(5), "0004");
+    assert_eq!(last.column_by_name("mit_dr_tran_amount").unwrap().as_primitive::<Int64Type>().value(5), 2900);
+
+    let err = convert_file(&output, &input, ConvertFormat::ArrowIpc, ConvertFormat::MtLog, &options).unwrap_err();
+    assert!(err.to_string().contains("can only be written"), "{}", err);
+}
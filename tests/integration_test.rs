@@ -1,3 +1,4 @@
+use split_merge_hub_demo::parallel_merge::meta_path;
 use std::fs;
 use std::process::Command;
 
@@ -18,8 +19,8 @@ fn test_merge_csv_files() {
     fs::write(input2, "id,name\n2,Bob\n4,David").unwrap();
 
     // Run the merge command
-    let status = Command::new("cargo")
-        .args(["run", "--", "merge", output, input1, input2])
+    let status = Command::new(env!("CARGO_BIN_EXE_split_merge_hub_demo"))
+        .args(["merge", "-o", output, input1, input2])
         .status()
         .expect("Failed to execute command");
 
@@ -57,19 +58,19 @@ fn test_merge_with_sorting() {
     let _ = fs::remove_file(input1);
     let _ = fs::remove_file(input2);
     let _ = fs::remove_file(output);
+    let _ = fs::remove_file(meta_path(output.as_ref()));
 
     // Create test input files with out-of-order IDs
     fs::write(input1, "id,name\n3,Charlie\n1,Alice").unwrap();
     fs::write(input2, "id,name\n4,David\n2,Bob").unwrap();
 
     // Run the merge command with sorting
-    let status = Command::new("cargo")
+    let status = Command::new(env!("CARGO_BIN_EXE_split_merge_hub_demo"))
         .args([
-            "run",
-            "--",
             "merge",
             "--sort-by",
             "id",
+            "-o",
             output,
             input1,
             input2,
@@ -111,6 +112,7 @@ fn test_merge_with_sorting() {
     fs::remove_file(input1).unwrap();
     fs::remove_file(input2).unwrap();
     fs::remove_file(output).unwrap();
+    fs::remove_file(meta_path(output.as_ref())).unwrap();
 }
 
 #[test]
@@ -126,13 +128,12 @@ fn test_split_file() {
     fs::create_dir_all(output_dir).expect("Failed to create output directory");
 
     // Run the split command
-    let output = Command::new("cargo")
-        .arg("run")
-        .arg("--")
+    let output = Command::new(env!("CARGO_BIN_EXE_split_merge_hub_demo"))
         .arg("split")
         .arg(input)
+        .arg("--output-dir")
         .arg(output_dir)
-        .arg("--rows")
+        .arg("--rows-per-file")
         .arg("2")
         .output()
         .expect("Failed to execute command");
@@ -144,7 +145,7 @@ fn test_split_file() {
     let entries: Vec<_> = fs::read_dir(output_dir)
         .expect("Failed to read output directory")
         .filter_map(Result::ok)
-        .filter(|e| e.path().extension().map_or(false, |ext| ext == "csv"))
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "csv"))
         .collect();
 
     assert!(
@@ -184,3 +185,152 @@ fn test_split_file() {
     fs::remove_file(input).unwrap();
     fs::remove_dir_all(output_dir).unwrap();
 }
+
+#[cfg(unix)]
+#[test]
+fn test_cancelled_merge_leaves_no_files() {
+    use std::io::Write;
+    use std::process::Stdio;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let spill = tempfile::tempdir().unwrap();
+    let input = dir.path().join("mtlog.fifo");
+    assert!(Command::new("mkfifo").arg(&input).status().unwrap().success());
+    let output = dir.path().join("merged");
+    let line = |taskno: usize| {
+        let mut line = " ".repeat(4310);
+        line.replace_range(14..21, &format!("{:07}", taskno));
+        line + "\n"
+    };
+
+    // Reading the FIFO keeps the run going until the signal has been delivered
+    let child = Command::new(env!("CARGO_BIN_EXE_split_merge_hub_demo"))
+        .args(["merge", "--mt-log", "--mtlog-sort-cols", "2:num", "-o"])
+        .arg(&output)
+        .arg(&input)
+        .env("TMPDIR", spill.path())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to execute command");
+    let mut fifo = fs::OpenOptions::new().write(true).open(&input).unwrap();
+    fifo.write_all((line(3) + &line(1)).as_bytes()).unwrap();
+    sleep(Duration::from_millis(500));
+    let kill = Command::new("kill").arg("-INT").arg(child.id().to_string()).status().unwrap();
+    assert!(kill.success());
+    sleep(Duration::from_millis(200));
+    fifo.write_all((line(4) + &line(2)).as_bytes()).unwrap();
+    drop(fifo);
+    let status = child.wait_with_output().unwrap().status;
+
+    assert_eq!(status.code(), Some(130), "Cancelled merge should exit with 130");
+    assert!(!output.exists(), "Cancelled merge left a partial output");
+    // Only the input is left next to the output, and every temp directory is gone
+    let left: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(left, vec!["mtlog.fifo"]);
+    assert_eq!(fs::read_dir(spill.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_partitioned_merge_finds_key_ranges() {
+    use split_merge_hub_demo::parallel_merge::partition_path;
+
+    let dir = tempfile::tempdir().unwrap();
+    let run = |args: &[&str], output: &std::path::Path, inputs: &[std::path::PathBuf]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_split_merge_hub_demo"))
+            .args(["merge", "--partitioned"])
+            .args(args)
+            .arg("-o")
+            .arg(output)
+            .args(inputs)
+            .env("MERGE_PARTITIONS", "4")
+            .env("CHUNK_RECORDS", "300")
+            .stderr(std::process::Stdio::null())
+            .status()
+            .expect("Failed to execute command");
+        assert!(status.success());
+        assert!(!output.exists());
+        // Every partition holds a key range, in order
+        let mut parts = Vec::new();
+        while partition_path(output, parts.len()).exists() {
+            parts.push(fs::read_to_string(partition_path(output, parts.len())).unwrap());
+        }
+        assert_eq!(parts.len(), 4, "expected one partition per key range");
+        parts
+    };
+
+    // MT log: 1000 records with duplicated task numbers, read in chunks of 300
+    let mtlog = dir.path().join("in.mtlog");
+    let line = |taskno: usize| {
+        let mut line = " ".repeat(4310);
+        line.replace_range(14..21, &format!("{:07}", taskno));
+        line
+    };
+    let lines: Vec<String> = (0..1000).map(|i| line((i * 7919) % 500)).collect();
+    fs::write(&mtlog, lines.join("\n") + "\n").unwrap();
+    let output = dir.path().join("merged.mtlog");
+    let parts = run(&["--mt-log", "--mtlog-sort-cols", "2:num"], &output, &[mtlog]);
+    let tasknos: Vec<usize> = parts.concat().lines().map(|l| l[14..21].parse().unwrap()).collect();
+    let expected: Vec<usize> = (0..1000).map(|i| i / 2).collect();
+    assert_eq!(tasknos, expected);
+
+    // CSV: three inputs, large enough for the chunks' keys index, each partition with the header
+    let inputs: Vec<_> = (0..3)
+        .map(|f| {
+            let path = dir.path().join(format!("in_{}.csv", f));
+            let rows: String = (0..2000).rev().map(|r| format!("{},\"row, {}\"\n", r * 3 + f, r)).collect();
+            fs::write(&path, format!("id,name\n{}", rows)).unwrap();
+            path
+        })
+        .collect();
+    let output = dir.path().join("merged.csv");
+    let parts = run(&["--sort-by", "id"], &output, &inputs);
+    let mut ids = Vec::new();
+    for part in parts {
+        let mut lines = part.lines();
+        assert_eq!(lines.next(), Some("id,name"));
+        ids.extend(lines.map(|l| l.split(',').next().unwrap().parse::<usize>().unwrap()));
+    }
+    assert_eq!(ids, (0..6000).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_merge_stdin_to_stdout() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let merge = |args: &[&str], input: Vec<u8>| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_split_merge_hub_demo"))
+            .arg("merge")
+            .args(args)
+            .args(["-o", "-", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to execute command");
+        let mut stdin = child.stdin.take().unwrap();
+        let feeder = std::thread::spawn(move || stdin.write_all(&input).unwrap());
+        let output = child.wait_with_output().unwrap();
+        feeder.join().unwrap();
+        assert!(output.status.success(), "Merge from stdin to stdout failed");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let csv = merge(&["--sort-by", "id:num"], b"id,name\n3,Charlie\n10,Judy\n1,Alice\n2,Bob\n".to_vec());
+    assert_eq!(csv, "id,name\n1,Alice\n2,Bob\n3,Charlie\n10,Judy\n");
+
+    // 4310-byte MT log records with the task number at 14..21
+    let mtlog_line = |taskno: usize| {
+        let mut line = vec![b' '; 4310];
+        line[14..21].copy_from_slice(format!("{:07}", taskno).as_bytes());
+        line.push(b'\n');
+        line
+    };
+    let input: Vec<u8> = [5, 3, 9, 1].into_iter().flat_map(mtlog_line).collect();
+    let merged = merge(&["--mt-log", "--mtlog-sort-cols", "2:num"], input);
+    let tasknos: Vec<&str> = merged.lines().map(|line| &line[14..21]).collect();
+    assert_eq!(tasknos, ["0000001", "0000003", "0000005", "0000009"]);
+    assert!(merged.lines().all(|line| line.len() == 4310));
+}
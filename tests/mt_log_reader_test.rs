@@ -0,0 +1,70 @@
+use split_merge_hub_demo::mt_log::reader::{supports_ranges, MTLogReader};
+use std::fs;
+use std::io::Write;
+
+fn collect(mut reader: MTLogReader) -> Vec<Vec<u8>> {
+    let mut records = Vec::new();
+    while reader.next_record().unwrap() {
+        records.push(reader.record().to_vec());
+    }
+    records
+}
+
+#[test]
+fn test_mapped_gzip_and_stream_inputs_yield_same_records() {
+    let dir = tempfile::tempdir().unwrap();
+    // Last record has no trailing newline
+    let content = b"20240101AAA\n20240102BBB\n\n20240103CCC";
+    let plain = dir.path().join("mt_log");
+    fs::write(&plain, content).unwrap();
+    let gz = dir.path().join("mt_log.gz");
+    let mut encoder = flate2::write::GzEncoder::new(fs::File::create(&gz).unwrap(), flate2::Compression::fast());
+    encoder.write_all(content).unwrap();
+    encoder.finish().unwrap();
+
+    let expected: Vec<Vec<u8>> = vec![b"20240101AAA".to_vec(), b"20240102BBB".to_vec(), vec![], b"20240103CCC".to_vec()];
+    let mapped = MTLogReader::open(&plain).unwrap();
+    assert!(mapped.is_mapped());
+    assert_eq!(collect(mapped), expected);
+    assert_eq!(collect(MTLogReader::open(&gz).unwrap()), expected);
+    assert_eq!(collect(MTLogReader::from_reader(&content[..])), expected);
+    assert!(supports_ranges(&plain));
+    assert!(!supports_ranges(&gz));
+
+    // Ranges start at a record boundary and end at the given offset
+    assert_eq!(collect(MTLogReader::open_range(&plain, 12, 24).unwrap()), vec![b"20240102BBB".to_vec()]);
+}
+
+#[test]
+fn test_fields_match_record_layout() {
+    use split_merge_hub_demo::mt_log::mt_log_record::{MTLogFieldType, MTLogRecord, FIELDS, TOTAL_LENGTH};
+
+    // The fields are contiguous and cover the whole record
+    assert_eq!(FIELDS[0].start, 0);
+    assert!(FIELDS.windows(2).all(|pair| pair[0].end == pair[1].start));
+    assert_eq!(FIELDS.last().unwrap().end, TOTAL_LENGTH);
+
+    // Fill every field, edge to edge, with a value of its own
+    let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    let mut line = String::with_capacity(TOTAL_LENGTH);
+    for (i, field) in FIELDS.iter().enumerate() {
+        let width = field.end - field.start;
+        match field.field_type {
+            MTLogFieldType::Str => line.extend((0..width).map(|k| alphabet[(i * 7 + k) % alphabet.len()] as char)),
+            MTLogFieldType::U64 | MTLogFieldType::I64 => line.push_str(&format!("{:0width$}", i + 1)),
+        }
+    }
+    let record = MTLogRecord::parse_from_fixed(&line).unwrap();
+    assert_eq!(record.to_fixed_string(), line);
+
+    // Each field's range holds the value parsed into the record member of the same name
+    let parsed = format!("{:?}", record);
+    for field in FIELDS {
+        let value = std::str::from_utf8(field.bytes(line.as_bytes())).unwrap();
+        let expected = match field.field_type {
+            MTLogFieldType::Str => format!(" {}: {:?}", field.name, value),
+            MTLogFieldType::U64 | MTLogFieldType::I64 => format!(" {}: {}", field.name, value.parse::<u64>().unwrap()),
+        };
+        assert!(parsed.contains(&expected), "{} does not match its range {}..{}", field.name, field.start, field.end);
+    }
+}
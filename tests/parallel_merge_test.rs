@@ -0,0 +1,683 @@
+use split_merge_hub_demo::parallel_merge::parallel_merge_chunks;
+use std::fs;
+use std::path::PathBuf;
+
+/// Writes `n` sorted chunks with interleaved ids, so the output is only sorted if every chunk is merged.
+fn write_chunks(dir: &std::path::Path, n: usize, rows_per_chunk: usize) -> Vec<PathBuf> {
+    (0..n)
+        .map(|c| {
+            let path = dir.join(format!("chunk_{}.csv", c));
+            let mut content = String::from("id,name\n");
+            for r in 0..rows_per_chunk {
+                let id = r * n + c;
+                content.push_str(&format!("{},name{}\n", id, id));
+            }
+            fs::write(&path, content).unwrap();
+            path
+        })
+        .collect()
+}
+
+/// An MT log line whose `milog_rec_taskno` (columns 14..21) is `taskno`; every other field is blank.
+fn mtlog_line(taskno: usize) -> String {
+    let mut line = " ".repeat(4310);
+    line.replace_range(14..21, &format!("{:07}", taskno));
+    line
+}
+
+#[test]
+fn test_merge_sort_reports_progress() {
+    use split_merge_hub_demo::parallel_merge::{
+        parallel_merge_sort_mtlog_with, MTLogSortColumn, MTLogSortType, ProgressPhase, ProgressSink, ProgressUpdate,
+        RunOptions,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<ProgressUpdate>>);
+
+    impl ProgressSink for Recorder {
+        fn on_progress(&self, update: &ProgressUpdate) {
+            self.0.lock().unwrap().push(*update);
+        }
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let inputs: Vec<PathBuf> = (0..2)
+        .map(|f| {
+            let path = dir.path().join(format!("in_{}", f));
+            let lines: Vec<String> = (0..500).rev().map(|r| mtlog_line(r * 2 + f)).collect();
+            fs::write(&path, lines.join("\n") + "\n").unwrap();
+            path
+        })
+        .collect();
+    let output = dir.path().join("sorted");
+    let recorder = Arc::new(Recorder::default());
+    let options = RunOptions { progress: recorder.clone(), ..RunOptions::default() };
+    let sort_columns = [MTLogSortColumn { index: 2, col_type: MTLogSortType::Num }];
+
+    parallel_merge_sort_mtlog_with(&inputs, &output, &sort_columns, &options).unwrap();
+
+    let updates = recorder.0.lock().unwrap();
+    // The last whole-phase update, or the last updates of all workers, cover every record
+    for phase in [ProgressPhase::Chunk, ProgressPhase::Merge] {
+        let mut last = std::collections::HashMap::new();
+        for update in updates.iter().filter(|u| u.phase == phase) {
+            last.insert(update.worker, *update);
+        }
+        assert!(!last.is_empty(), "no {:?} progress reported", phase);
+        let records: u64 = match last.get(&None) {
+            Some(whole) => whole.records_processed,
+            None => last.values().map(|u| u.records_processed).sum(),
+        };
+        assert_eq!(records, 1_000);
+        assert!(last.values().all(|u| u.total_bytes > 0 && u.bytes_processed > 0));
+    }
+    let content = fs::read_to_string(&output).unwrap();
+    let tasknos: Vec<usize> = content.lines().map(|l| l[14..21].parse().unwrap()).collect();
+    assert_eq!(tasknos, (0..1_000).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_atomic_output_appears_on_commit_with_sidecars() {
+    use sha2::{Digest, Sha256};
+    use split_merge_hub_demo::parallel_merge::{
+        parallel_merge_sort_mtlog_with, sidecar_path, AtomicOutput, MTLogSortColumn, MTLogSortType, OutputOptions,
+        RunOptions,
+    };
+    use std::io::Write;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("merged");
+    let sidecars = OutputOptions { done_marker: true, checksum: true, partitioned: false };
+
+    // The output is written to a sibling temp file and only shows up once renamed
+    let atomic = AtomicOutput::create(&output).unwrap();
+    writeln!(atomic.file(), "{}", mtlog_line(1)).unwrap();
+    let tmp = atomic.path().to_path_buf();
+    assert_eq!(tmp.parent(), Some(dir.path()));
+    assert!(tmp.exists() && !output.exists());
+    assert!(!sidecar_path(&output, "done").exists());
+    atomic.commit(&sidecars).unwrap();
+    assert!(output.exists() && !tmp.exists());
+
+    // A merge publishes the same sidecars, describing the final file
+    let input = dir.path().join("in");
+    fs::write(&input, [3, 1, 2].map(|t| mtlog_line(t) + "\n").concat()).unwrap();
+    let options = RunOptions { output: sidecars, ..RunOptions::default() };
+    let sort_columns = [MTLogSortColumn { index: 2, col_type: MTLogSortType::Num }];
+    parallel_merge_sort_mtlog_with(std::slice::from_ref(&input), &output, &sort_columns, &options).unwrap();
+
+    let merged = fs::read(&output).unwrap();
+    assert_eq!(merged, [1, 2, 3].map(|t| mtlog_line(t) + "\n").concat().as_bytes());
+    assert_eq!(fs::read(sidecar_path(&output, "done")).unwrap(), b"");
+    let digest: String = Sha256::digest(&merged).iter().map(|b| format!("{:02x}", b)).collect();
+    assert_eq!(
+        fs::read_to_string(sidecar_path(&output, "sha256")).unwrap(),
+        format!("{}  merged\n", digest)
+    );
+    let mut names: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+    names.sort();
+    assert_eq!(names, ["in", "merged", "merged.done", "merged.meta.json", "merged.sha256"]);
+}
+
+fn check_merge(chunks: usize, k: usize) {
+    let dir = tempfile::tempdir().unwrap();
+    let rows_per_chunk = 5;
+    let paths = write_chunks(dir.path(), chunks, rows_per_chunk);
+    let output = dir.path().join("merged.csv");
+
+    parallel_merge_chunks(paths, &output, &["id"], k).unwrap();
+
+    let content = fs::read_to_string(&output).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines[0], "id,name", "chunks={} k={}", chunks, k);
+    assert_eq!(
+        lines.iter().filter(|l| **l == "id,name").count(),
+        1,
+        "header repeated for chunks={} k={}",
+        chunks,
+        k
+    );
+    let ids: Vec<usize> = lines[1..].iter().map(|l| l.split(',').next().unwrap().parse().unwrap()).collect();
+    let expected: Vec<usize> = (0..chunks * rows_per_chunk).collect();
+    assert_eq!(ids, expected, "chunks={} k={}", chunks, k);
+}
+
+#[test]
+fn test_merge_chunks_around_k() {
+    let k = 3;
+    for chunks in [1, 2, k, k + 1] {
+        check_merge(chunks, k);
+    }
+}
+
+#[test]
+fn test_merge_chunks_multiple_passes() {
+    // 3 passes with k = 2
+    check_merge(7, 2);
+}
+
+#[test]
+fn test_partitioned_output() {
+    use split_merge_hub_demo::parallel_merge::{parallel_merge_chunks_with, partition_path, RunOptions};
+
+    let dir = tempfile::tempdir().unwrap();
+    let (chunks, rows_per_chunk) = (3, 2_000);
+    let paths = write_chunks(dir.path(), chunks, rows_per_chunk);
+    let output = dir.path().join("merged.csv");
+    let mut options = RunOptions::default();
+    options.output.partitioned = true;
+
+    parallel_merge_chunks_with(paths, &output, &["id"], 3, &options).unwrap();
+
+    assert!(!output.exists());
+    let mut ids = Vec::new();
+    let mut p = 0;
+    while partition_path(&output, p).exists() {
+        let content = fs::read_to_string(partition_path(&output, p)).unwrap();
+        let mut lines = content.lines();
+        assert_eq!(lines.next(), Some("id,name"));
+        ids.extend(lines.map(|l| l.split(',').next().unwrap().parse::<usize>().unwrap()));
+        p += 1;
+    }
+    assert!(p >= 1);
+    let expected: Vec<usize> = (0..chunks * rows_per_chunk).collect();
+    assert_eq!(ids, expected);
+}
+
+#[test]
+fn test_mtlog_sort_key_matches_field_comparison() {
+    use split_merge_hub_demo::parallel_merge::{mtlog_sort_key, MTLogSortColumn, MTLogSortType};
+    use std::cmp::Ordering;
+
+    /// The per-comparison field parsing the keys replace: trimmed text, numbers as `u64`s.
+    fn compare_fields(a: &str, b: &str, cols: &[MTLogSortColumn]) -> Ordering {
+        // (start, len) of the date, time, task number and terminal fields
+        const FIELDS: [(usize, usize); 4] = [(0, 8), (8, 6), (14, 7), (21, 4)];
+        let field = |line: &str, index: usize| {
+            let (start, len) = FIELDS[index];
+            line.get(start..start + len).unwrap_or("").trim().to_string()
+        };
+        cols.iter()
+            .map(|col| {
+                let (v1, v2) = (field(a, col.index), field(b, col.index));
+                match col.col_type {
+                    MTLogSortType::Num => v1.parse::<u64>().unwrap_or(0).cmp(&v2.parse::<u64>().unwrap_or(0)),
+                    MTLogSortType::Date | MTLogSortType::Time | MTLogSortType::Str => v1.cmp(&v2),
+                }
+            })
+            .find(|ord| ord.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+
+    let cols = [
+        MTLogSortColumn { index: 0, col_type: MTLogSortType::Date },
+        MTLogSortColumn { index: 2, col_type: MTLogSortType::Num },
+        MTLogSortColumn { index: 3, col_type: MTLogSortType::Str },
+    ];
+    // Padded vs short fields, numbers of different widths, and a truncated line
+    let lines = [
+        "20240101120000     99ATM 1",
+        "20240101120000 100000ATM 1",
+        "20240101120000 100000AT  1",
+        "20240101120000 100000A\0X 1",
+        "2024010112",
+        "20231231235959      1ATM 1",
+        "20240101120000     99ATMX1",
+    ];
+    for a in &lines {
+        for b in &lines {
+            let expected = compare_fields(a, b, &cols);
+            let actual = mtlog_sort_key(a.as_bytes(), &cols).cmp(&mtlog_sort_key(b.as_bytes(), &cols));
+            assert_eq!(actual, expected, "{:?} vs {:?}", a, b);
+        }
+    }
+}
+
+#[test]
+fn test_merge_sort_typed_descending_keys() {
+    use split_merge_hub_demo::parallel_merge::parallel_merge_sort;
+
+    let dir = tempfile::tempdir().unwrap();
+    // 3 inputs -> 3 chunks with keys sidecars, one intermediate pass at k = 2, then the final merge
+    let inputs: Vec<PathBuf> = (0..3)
+        .map(|f| {
+            let path = dir.path().join(format!("in_{}.csv", f));
+            let mut content = String::from("id,amount\n");
+            for r in 0..500 {
+                let id = r * 3 + f;
+                content.push_str(&format!("{},{}.{}\n", id, id % 97, id % 10));
+            }
+            fs::write(&path, content).unwrap();
+            path
+        })
+        .collect();
+    let output = dir.path().join("sorted.csv");
+
+    parallel_merge_sort(&inputs, &output, &["amount:num:desc", "id"]).unwrap();
+
+    let mut rdr = csv::Reader::from_path(&output).unwrap();
+    let rows: Vec<(f64, i64)> = rdr
+        .records()
+        .map(|r| {
+            let r = r.unwrap();
+            (r[1].parse().unwrap(), r[0].parse().unwrap())
+        })
+        .collect();
+    assert_eq!(rows.len(), 1_500);
+    for pair in rows.windows(2) {
+        let ((a_amount, a_id), (b_amount, b_id)) = (pair[0], pair[1]);
+        assert!(a_amount > b_amount || (a_amount == b_amount && a_id < b_id), "{:?}", pair);
+    }
+}
+
+#[test]
+fn test_auto_sort_key_puts_integers_before_text() {
+    use split_merge_hub_demo::parallel_merge::{csv_sort_key, CsvSortColumn, CsvSortType};
+
+    let columns = [CsvSortColumn { index: 0, col_type: CsvSortType::Auto, descending: false }];
+    let mut values = vec!["10", "9", "abc", "1a"];
+    values.sort_by_key(|v| csv_sort_key(&csv::StringRecord::from(vec![*v]), &columns));
+    assert_eq!(values, ["9", "10", "1a", "abc"]);
+}
+
+#[test]
+fn test_split_ranges_keep_quoted_newlines() {
+    use split_merge_hub_demo::parallel_merge::parallel_split_file_to_chunks;
+
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("quoted.csv");
+    // ~3 MB of records whose quoted notes span lines and contain escaped quotes,
+    // so 1 MB ranges start inside quoted fields
+    let rows = 12_000;
+    let mut content = String::from("id,note\n");
+    for r in 0..rows {
+        let id = (r * 7_919) % rows;
+        content.push_str(&format!("{},\"line {}\n\"\"quoted\"\", {}\n{}\"\n", id, id, "x".repeat(200), id));
+    }
+    fs::write(&input, content).unwrap();
+    let headers = csv::StringRecord::from(vec!["id", "note"]);
+    let temp_dir = tempfile::TempDir::new().unwrap();
+
+    let chunks = parallel_split_file_to_chunks(&input, &temp_dir, &["id"], 1, &headers).unwrap();
+    assert!(chunks.len() >= 3, "{} chunks", chunks.len());
+    let output = dir.path().join("sorted.csv");
+    parallel_merge_chunks(chunks, &output, &["id"], 4).unwrap();
+
+    let mut rdr = csv::Reader::from_path(&output).unwrap();
+    let mut count = 0;
+    for (expected, r) in rdr.records().enumerate() {
+        let r = r.unwrap();
+        assert_eq!(r[0].parse::<usize>().unwrap(), expected);
+        assert_eq!(r[1], format!("line {}\n\"quoted\", {}\n{}", expected, "x".repeat(200), expected));
+        count += 1;
+    }
+    assert_eq!(count, rows);
+}
+
+#[test]
+fn test_merge_mtlog_keeps_input_order_of_equal_keys() {
+    use split_merge_hub_demo::parallel_merge::{
+        parallel_merge_sort_mtlog_with, MTLogSortColumn, MTLogSortType, RunOptions,
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("mt_log06");
+    // Five task numbers, each record tagged with its input position in an unsorted field
+    let content: String = (0..2_000)
+        .map(|i| {
+            let mut line = mtlog_line((i * 7) % 5);
+            line.replace_range(151..155, &format!("{:04}", i));
+            line + "\n"
+        })
+        .collect();
+    fs::write(&input, content).unwrap();
+
+    let output = dir.path().join("merged.mtlog");
+    let sort_columns = [MTLogSortColumn { index: 2, col_type: MTLogSortType::Num }];
+    parallel_merge_sort_mtlog_with(std::slice::from_ref(&input), &output, &sort_columns, &RunOptions::default())
+        .unwrap();
+    let merged = fs::read_to_string(&output).unwrap();
+    let order: Vec<(usize, usize)> = merged
+        .lines()
+        .map(|line| (line[14..21].parse().unwrap(), line[151..155].parse().unwrap()))
+        .collect();
+    let mut expected = order.clone();
+    expected.sort();
+    assert_eq!(order, expected);
+}
+
+#[test]
+fn test_split_and_merge_in_csv_dialects() {
+    use split_merge_hub_demo::parallel_merge::{
+        parallel_merge_chunks_with, parallel_split_file_to_chunks_with, CsvDialect, RecordTerminator, RunOptions,
+    };
+
+    // TIS-620 is read as windows-874; multibyte encodings, whose trail bytes can be `\` or `|`, are rejected
+    assert_eq!(CsvDialect::encoding_for_label("tis-620").unwrap(), encoding_rs::WINDOWS_874);
+    for label in ["shift_jis", "gbk", "big5", "utf-16le"] {
+        assert!(CsvDialect::encoding_for_label(label).is_err(), "{}", label);
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("thai.csv");
+    // ~3 MB of headerless TIS-620 records with comments and backslash-escaped quotes, so
+    // 1 MB ranges start inside quoted fields and comment lines
+    let rows = 12_000;
+    let mut content = String::from("# exported\n");
+    for r in 0..rows {
+        let id = (r * 7_919) % rows;
+        content.push_str(&format!("{};\"สาขา {}\n\\\"{}\\\"; {}\"\n# after {}\n", id, id, "ก".repeat(250), id, id));
+    }
+    let (encoded, _, unmappable) = encoding_rs::WINDOWS_874.encode(&content);
+    assert!(!unmappable);
+    fs::write(&input, encoded).unwrap();
+    let options = RunOptions {
+        input_dialect: CsvDialect {
+            delimiter: b';',
+            escape: Some(b'\\'),
+            comment: Some(b'#'),
+            has_header: false,
+            encoding: CsvDialect::encoding_for_label("tis-620").unwrap(),
+            ..CsvDialect::default()
+        },
+        output_dialect: CsvDialect { delimiter: b'|', terminator: RecordTerminator::Crlf, ..CsvDialect::default() },
+        ..RunOptions::default()
+    };
+    let headers = csv::StringRecord::from(vec!["1", "2"]);
+    let temp_dir = tempfile::TempDir::new().unwrap();
+
+    let chunks = parallel_split_file_to_chunks_with(&input, &temp_dir, &["1:num"], 1, &headers, &options).unwrap();
+    assert!(chunks.len() >= 3, "{} chunks", chunks.len());
+    let output = dir.path().join("sorted.csv");
+    parallel_merge_chunks_with(chunks, &output, &["1:num"], 4, &options).unwrap();
+
+    let merged = fs::read(&output).unwrap();
+    assert!(merged.starts_with(b"1|2\r\n"));
+    let mut rdr = csv::ReaderBuilder::new().delimiter(b'|').from_reader(merged.as_slice());
+    let mut count = 0;
+    for (expected, r) in rdr.records().enumerate() {
+        let r = r.unwrap();
+        assert_eq!(r[0].parse::<usize>().unwrap(), expected);
+        assert_eq!(r[1], format!("สาขา {}\n\"{}\"; {}", expected, "ก".repeat(250), expected));
+        count += 1;
+    }
+    assert_eq!(count, rows);
+}
+
+#[test]
+fn test_merge_sort_reconciles_differing_headers() {
+    use split_merge_hub_demo::parallel_merge::{parallel_merge_sort_with, RunOptions, Schema, SchemaMode};
+
+    let dir = tempfile::tempdir().unwrap();
+    let inputs: Vec<PathBuf> = [
+        ("a.csv", "id,name,amount\n3,c,30\n1,a,10\n"),
+        ("b.csv", "amount,acct_no,name\n25,2,b\n"),
+        ("c.csv", "name,id,branch\nd,0,BKK\n"),
+    ]
+    .iter()
+    .map(|(name, content)| {
+        let path = dir.path().join(name);
+        fs::write(&path, content).unwrap();
+        path
+    })
+    .collect();
+    let renames: std::collections::HashMap<_, _> = [("acct_no".to_string(), "id".to_string())].into_iter().collect();
+    let merge = |mode| {
+        let output = dir.path().join("merged.csv");
+        let options = RunOptions { schema: Schema { mode, renames: renames.clone() }, ..RunOptions::default() };
+        parallel_merge_sort_with(&inputs, &output, &["id:num"], &options).map(|_| fs::read_to_string(&output).unwrap())
+    };
+
+    assert!(merge(SchemaMode::Strict).is_err());
+    assert!(merge(SchemaMode::Reorder).is_err());
+    assert_eq!(merge(SchemaMode::Union).unwrap(), "id,name,amount,branch\n0,d,,BKK\n1,a,10,\n2,b,25,\n3,c,30,\n");
+    assert_eq!(merge(SchemaMode::Intersect).unwrap(), "id,name\n0,d\n1,a\n2,b\n3,c\n");
+}
+
+#[test]
+fn test_merge_sort_filters_rows() {
+    use split_merge_hub_demo::parallel_merge::{parallel_merge_sort_with, Filter, Presorted, RunOptions};
+
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("tx.csv");
+    fs::write(
+        &input,
+        "id,branch,amount,posted\n1,001,500,2024-01-05\n2,002,1500,2024-02-10\n3,003,2500,20240115\n\
+         4,001,n/a,2024-01-20\n5,002,900,2023-12-31\n",
+    )
+    .unwrap();
+    let output = dir.path().join("filtered.csv");
+    let merge = |filter: &str| {
+        let options = RunOptions { filter: Some(Filter::parse(filter)?), presorted: Presorted::OrSort, ..RunOptions::default() };
+        parallel_merge_sort_with(std::slice::from_ref(&input), &output, &["id:num"], &options)?;
+        let merged = fs::read_to_string(&output).unwrap();
+        Ok::<_, anyhow::Error>(merged.lines().skip(1).map(|l| l[..1].to_string()).collect::<String>())
+    };
+
+    assert_eq!(merge("branch IN ('001', '002') AND amount >= 900").unwrap(), "25");
+    assert_eq!(merge("NOT (amount < 1000) OR branch = '001'").unwrap(), "1234");
+    assert_eq!(merge("posted BETWEEN DATE '2024-01-01' AND DATE '2024-01-31'").unwrap(), "134");
+    assert_eq!(merge("posted:date < '2024-01-01' OR branch LIKE '%3'").unwrap(), "35");
+    assert_eq!(merge("branch MATCHES '^00[12]$' AND NOT amount BETWEEN 0 AND 1000").unwrap(), "24");
+    assert!(merge("missing = 1").is_err());
+    assert!(merge("amount >").is_err());
+}
+
+#[test]
+fn test_merge_sort_filters_headerless_rows_by_position() {
+    use split_merge_hub_demo::parallel_merge::{parallel_merge_sort_with, CsvDialect, Filter, RunOptions};
+
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("tx.csv");
+    let today = chrono::Local::now().date_naive();
+    let day = |offset: i64| (today + chrono::Duration::days(offset)).format("%Y-%m-%d").to_string();
+    // Headerless, so the columns are named 1 (id), 2 (branch), 3 (amount) and 4 (posted)
+    let rows = [
+        ("1", "001", "500", day(-40)),
+        ("2", "002", "1500", day(-3)),
+        ("3", "013", "2500", day(0)),
+        ("4", "001", "999", day(1)),
+        ("5", "020", "1000", day(-7)),
+    ];
+    let content: String = rows.iter().map(|(id, b, a, p)| format!("{},{},{},{}\n", id, b, a, p)).collect();
+    fs::write(&input, content).unwrap();
+    let output = dir.path().join("filtered.csv");
+    let merge = |filter: &str| {
+        let options = RunOptions {
+            filter: Some(Filter::parse(filter)?),
+            input_dialect: CsvDialect { has_header: false, ..CsvDialect::default() },
+            output_dialect: CsvDialect { has_header: false, ..CsvDialect::default() },
+            ..RunOptions::default()
+        };
+        parallel_merge_sort_with(std::slice::from_ref(&input), &output, &["1:num"], &options)?;
+        let merged = fs::read_to_string(&output).unwrap();
+        Ok::<_, anyhow::Error>(merged.lines().map(|l| l[..1].to_string()).collect::<String>())
+    };
+
+    // Positional names, bare or quoted, and the same column by either name
+    assert_eq!(merge("2 = '001'").unwrap(), "14");
+    assert_eq!(merge("\"2\" = '001' AND 3 > 600").unwrap(), "4");
+    assert_eq!(merge("2 NOT IN ('001', '002')").unwrap(), "35");
+    assert_eq!(merge("2 LIKE '0_3' OR 2 LIKE '02%'").unwrap(), "35");
+    assert_eq!(merge("2 NOT LIKE '00%'").unwrap(), "35");
+    assert_eq!(merge("3 BETWEEN 999 AND 1500").unwrap(), "245");
+    assert_eq!(merge("3:num NOT BETWEEN 999 AND 1500").unwrap(), "13");
+    assert_eq!(merge("4 = TODAY").unwrap(), "3");
+    assert_eq!(merge("4 >= TODAY - 7 AND 4 < TODAY + 1").unwrap(), "235");
+    assert_eq!(merge("4 BETWEEN TODAY - 3 AND TODAY + 1").unwrap(), "234");
+    assert!(merge("5 = 'x'").is_err());
+}
+
+#[test]
+fn test_merge_sort_streams_to_writer() {
+    use split_merge_hub_demo::parallel_merge::{parallel_merge_sort_streams, RunOptions, TempDirSpill};
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    let spill_root = tempfile::tempdir().unwrap();
+    let sources: Vec<Cursor<Vec<u8>>> = (0..3)
+        .map(|f| {
+            let mut content = String::from("id,name\n");
+            for r in (0..400).rev() {
+                content.push_str(&format!("{},\"row\n{}\"\n", r * 3 + f, r));
+            }
+            Cursor::new(content.into_bytes())
+        })
+        .collect();
+    let options = RunOptions {
+        spill: Arc::new(TempDirSpill::in_dir(spill_root.path())),
+        ..RunOptions::default()
+    };
+    let mut sink = Vec::new();
+
+    parallel_merge_sort_streams(sources, &mut sink, &["id:num"], &options).unwrap();
+
+    let mut rdr = csv::Reader::from_reader(sink.as_slice());
+    assert_eq!(rdr.headers().unwrap(), vec!["id", "name"]);
+    let ids: Vec<usize> = rdr.records().map(|r| r.unwrap()[0].parse().unwrap()).collect();
+    assert_eq!(ids, (0..1_200).collect::<Vec<_>>());
+    // Every spill directory is removed once the run is done
+    assert_eq!(fs::read_dir(spill_root.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_presorted_merge_verifies_order() {
+    use split_merge_hub_demo::parallel_merge::{parallel_merge_sort_with, Presorted, RunOptions, Unsorted};
+
+    let dir = tempfile::tempdir().unwrap();
+    let inputs: Vec<PathBuf> = (0..3)
+        .map(|f| {
+            let path = dir.path().join(format!("in_{}.csv", f));
+            let mut content = String::from("id,name\n");
+            for r in 0..2_000 {
+                content.push_str(&format!("{},n{}\n", r * 3 + f, r));
+            }
+            fs::write(&path, content).unwrap();
+            path
+        })
+        .collect();
+    let output = dir.path().join("merged.csv");
+    let read_ids = |path: &PathBuf| -> Vec<usize> {
+        let mut rdr = csv::Reader::from_path(path).unwrap();
+        rdr.records().map(|r| r.unwrap()[0].parse().unwrap()).collect()
+    };
+
+    let verify = RunOptions { presorted: Presorted::Verify, ..RunOptions::default() };
+    parallel_merge_sort_with(&inputs, &output, &["id:num"], &verify).unwrap();
+    assert_eq!(read_ids(&output), (0..6_000).collect::<Vec<_>>());
+
+    // Swap two records near the end of one input
+    fs::remove_file(&output).unwrap();
+    let mut content = fs::read_to_string(&inputs[1]).unwrap();
+    content = content.replace("5995,n1998\n5998,n1999\n", "5998,n1999\n5995,n1998\n");
+    fs::write(&inputs[1], content).unwrap();
+    let err = parallel_merge_sort_with(&inputs, &output, &["id:num"], &verify).unwrap_err();
+    assert_eq!(err.downcast_ref::<Unsorted>().map(|u| &u.input), Some(&inputs[1]), "{:#}", err);
+    assert!(!output.exists());
+
+    let fallback = RunOptions { presorted: Presorted::OrSort, ..RunOptions::default() };
+    parallel_merge_sort_with(&inputs, &output, &["id:num"], &fallback).unwrap();
+    assert_eq!(read_ids(&output), (0..6_000).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_merge_into_existing_output() {
+    use split_merge_hub_demo::parallel_merge::{parallel_merge_into, OutputMeta, OutputOptions, RunOptions};
+
+    let dir = tempfile::tempdir().unwrap();
+    let write_input = |name: &str, ids: std::ops::Range<usize>| {
+        let path = dir.path().join(name);
+        let mut content = String::from("id,day\n");
+        for id in ids.rev() {
+            content.push_str(&format!("{},{}\n", id % 700, name));
+        }
+        fs::write(&path, content).unwrap();
+        path
+    };
+    let day1 = write_input("day1.csv", 0..1_000);
+    let day2 = write_input("day2.csv", 1_000..1_500);
+    let merged = dir.path().join("merged.csv");
+    let options = RunOptions::default();
+
+    // The first run creates the output and its metadata, the second merges into it
+    parallel_merge_into(&merged, std::slice::from_ref(&day1), &["id:num"], &options).unwrap();
+    let meta = OutputMeta::load(&merged).unwrap().unwrap();
+    assert_eq!(meta.sort_keys, OutputMeta::csv(&["id:num"]).sort_keys);
+    assert_eq!(meta.columns, ["id", "day"]);
+    assert_eq!(meta.record_count, Some(1_000));
+    // Without a checksum the output is not re-read, so there is no fingerprint
+    assert_eq!(meta.fingerprint, None);
+    assert!(meta.verify_fingerprint(&merged).is_err());
+    let checksum = RunOptions { output: OutputOptions { checksum: true, ..OutputOptions::default() }, ..RunOptions::default() };
+    parallel_merge_into(&merged, std::slice::from_ref(&day2), &["id:num"], &checksum).unwrap();
+    let meta = OutputMeta::load(&merged).unwrap().unwrap();
+    assert_eq!(meta.record_count, Some(1_500));
+    meta.verify_fingerprint(&merged).unwrap();
+
+    let mut rdr = csv::Reader::from_path(&merged).unwrap();
+    let rows: Vec<(usize, String)> = rdr
+        .records()
+        .map(|r| {
+            let r = r.unwrap();
+            (r[0].parse().unwrap(), r[1].to_string())
+        })
+        .collect();
+    assert_eq!(rows.len(), 1_500);
+    // Sorted by id, with existing records before new ones of the same id
+    assert!(rows.windows(2).all(|w| w[0].0 < w[1].0 || (w[0].0 == w[1].0 && w[0].1 <= w[1].1)));
+
+    let err = parallel_merge_into(&merged, std::slice::from_ref(&day2), &["day"], &options).unwrap_err();
+    assert!(err.to_string().contains("sorted by [id:num]"), "{:#}", err);
+
+    // A file changed after it was written no longer matches its fingerprint
+    fs::write(&merged, "id,day\n").unwrap();
+    assert!(meta.verify_fingerprint(&merged).is_err());
+}
+
+#[test]
+fn test_merge_sort_to_parquet_infers_column_types() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Date32Type, Float64Type, Int64Type};
+    use arrow_schema::DataType;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use split_merge_hub_demo::parallel_merge::{parallel_merge_sort_with, ColumnarFormat, ColumnarOptions, RunOptions};
+
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("tx.csv");
+    let mut content = String::from("id,branch,amount,rate,posted,note\n");
+    for id in (0..250).rev() {
+        let amount = if id % 10 == 0 { String::new() } else { (id * 100).to_string() };
+        content.push_str(&format!("{},{:03},{},{}.5,2024-01-{:02},\"n, {}\"\n", id, id % 7, amount, id, id % 28 + 1, id));
+    }
+    fs::write(&input, &content).unwrap();
+    let output = dir.path().join("tx.parquet");
+    let columnar = ColumnarOptions { row_group_rows: 100, ..ColumnarOptions::new(ColumnarFormat::Parquet) };
+    let options = RunOptions { columnar: Some(columnar), ..RunOptions::default() };
+
+    parallel_merge_sort_with(std::slice::from_ref(&input), &output, &["id:num"], &options).unwrap();
+
+    let builder = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&output).unwrap()).unwrap();
+    assert_eq!(builder.metadata().num_row_groups(), 3);
+    let types: Vec<DataType> = builder.schema().fields().iter().map(|f| f.data_type().clone()).collect();
+    // Zero-padded branch codes stay text
+    assert_eq!(types, [DataType::Int64, DataType::Utf8, DataType::Int64, DataType::Float64, DataType::Date32, DataType::Utf8]);
+    let batches: Vec<_> = builder.build().unwrap().map(|b| b.unwrap()).collect();
+    let batch = arrow_select::concat::concat_batches(&batches[0].schema(), &batches).unwrap();
+    let ids: Vec<i64> = batch.column(0).as_primitive::<Int64Type>().values().to_vec();
+    assert_eq!(ids, (0..250).collect::<Vec<_>>());
+    assert_eq!(batch.column(1).as_string::<i32>().value(3), "003");
+    assert!(batch.column(2).is_null(10));
+    assert_eq!(batch.column(2).as_primitive::<Int64Type>().value(11), 1100);
+    assert_eq!(batch.column(3).as_primitive::<Float64Type>().value(4), 4.5);
+    assert_eq!(batch.column(4).as_primitive::<Date32Type>().value(0), 19723); // 2024-01-01
+    assert_eq!(batch.column(5).as_string::<i32>().value(7), "n, 7");
+
+    // A value after the first row group that does not fit the inferred type fails the merge
+    fs::write(&input, content.replace("249,", "x249,")).unwrap();
+    let err = parallel_merge_sort_with(std::slice::from_ref(&input), &output, &["id"], &options).unwrap_err();
+    assert!(format!("{:#}", err).contains("Column 'id' was inferred as Int64 from the first 100 records"), "{:#}", err);
+    let options = RunOptions { columnar: Some(ColumnarOptions { infer_types: false, ..columnar }), ..options };
+    parallel_merge_sort_with(std::slice::from_ref(&input), &output, &["id"], &options).unwrap();
+}
@@ -0,0 +1,390 @@
+use split_merge_hub_demo::mt_log::mt_log_record::{MTLogField, TOTAL_LENGTH};
+use split_merge_hub_demo::parallel_merge::{split_csv, split_mtlog, MTLogSortColumn, MTLogSortType, RunOptions, SplitOptions};
+use std::collections::BTreeSet;
+use std::fs;
+use std::sync::Mutex;
+
+/// Held by tests that split, since some of them set `SPLIT_MAX_OPEN_FILES`.
+static ENV: Mutex<()> = Mutex::new(());
+
+/// An MT log line with `branch` as `mit_isc_tran_branch_code` and `serno` as `milog_rec_taskno`.
+fn mtlog_line(branch: &str, serno: usize) -> String {
+    let mut line = vec![b' '; TOTAL_LENGTH];
+    let taskno = MTLogField::find("milog_rec_taskno").unwrap();
+    line[taskno.start..taskno.end].copy_from_slice(format!("{:07}", serno).as_bytes());
+    let field = MTLogField::find("mit_isc_tran_branch_code").unwrap();
+    line[field.start..field.start + branch.len()].copy_from_slice(branch.as_bytes());
+    String::from_utf8(line).unwrap() + "\n"
+}
+
+#[test]
+fn test_split_csv_by_key_reopens_parts_for_appending() {
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("tx.csv");
+    let branches = ["001", "002", "003", "004"];
+    let pad = "x".repeat(80);
+    let mut content = String::from("id,branch,memo\n");
+    for i in 0..8_000 {
+        content.push_str(&format!("{},{},{}\n", i, branches[i % 3 + i / 4_000], pad));
+    }
+    fs::write(&input, &content).unwrap();
+    let output_dir = dir.path().join("out");
+
+    // Each key's buffer is flushed many times, and only two files may be open at once
+    // SAFETY: other tests in this binary only read the environment while holding `ENV`
+    unsafe { std::env::set_var("SPLIT_MAX_OPEN_FILES", "2") };
+    let split = SplitOptions { partition_by: vec!["BRANCH".to_string()], ..SplitOptions::new("{stem}_{key}.csv") };
+    let manifest = split_csv(&input, &output_dir, &[], &split, &RunOptions::default());
+    unsafe { std::env::remove_var("SPLIT_MAX_OPEN_FILES") };
+    let manifest = manifest.unwrap();
+
+    assert_eq!(manifest.records(), 8_000);
+    assert_eq!(manifest.files.len(), branches.len());
+    for file in &manifest.files {
+        let branch = file.key.as_deref().unwrap();
+        assert_eq!(file.path, std::path::Path::new(&format!("tx_{}.csv", branch)));
+        // One header, then the key's records in input order
+        let expected: String = std::iter::once("id,branch,memo\n")
+            .chain(content.lines().skip(1).filter(|l| l.split(',').nth(1) == Some(branch)).flat_map(|l| [l, "\n"]))
+            .collect();
+        let written = fs::read_to_string(output_dir.join(&file.path)).unwrap();
+        assert_eq!(written.len() as u64, file.bytes);
+        assert!(written == expected, "{} does not hold the key's records in order", file.path.display());
+    }
+}
+
+#[test]
+fn test_split_csv_by_keys_into_templated_parts_with_manifest() {
+    use split_merge_hub_demo::parallel_merge::{manifest_path, SplitManifest};
+
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("sales.2024.csv");
+    let regions = ["north west", "south"];
+    let mut content = String::from("id,region,year\n");
+    for i in 0..25 {
+        content.push_str(&format!("{},{},{}\n", i, regions[i % 2], 2023 + i % 3));
+    }
+    fs::write(&input, content).unwrap();
+    let output_dir = dir.path().join("out");
+
+    let split = SplitOptions {
+        partition_by: vec!["region".to_string(), "Year".to_string()],
+        max_rows: Some(3),
+        ..SplitOptions::new("{key}/{stem}-{part:03}.csv")
+    };
+    let manifest = split_csv(&input, &output_dir, &[], &split, &RunOptions::default()).unwrap();
+
+    assert_eq!(manifest.input, input);
+    assert_eq!(manifest.partition_by, ["region", "Year"]);
+    assert_eq!(manifest.records(), 25);
+    // Key values are joined with `_` and made safe for file names
+    let keys: BTreeSet<&str> = manifest.files.iter().map(|f| f.key.as_deref().unwrap()).collect();
+    let expected_keys = ["north west_2023", "north west_2024", "north west_2025", "south_2023", "south_2024", "south_2025"];
+    assert_eq!(keys, expected_keys.into_iter().collect());
+    for file in &manifest.files {
+        let key = file.key.as_deref().unwrap();
+        let dir_name = key.replace(' ', "_");
+        assert_eq!(file.path, std::path::Path::new(&format!("{}/sales.2024-{:03}.csv", dir_name, file.part)));
+        let written = fs::read_to_string(output_dir.join(&file.path)).unwrap();
+        let mut lines = written.lines();
+        assert_eq!(lines.next(), Some("id,region,year"));
+        let rows: Vec<&str> = lines.collect();
+        assert_eq!(rows.len() as u64, file.records);
+        assert!(file.records <= 3 && file.records > 0);
+        assert!(rows.iter().all(|row| row.ends_with(&key.replacen('_', ",", 1))));
+    }
+    // The manifest on disk lists the same files, and the staging directory is gone
+    let saved: SplitManifest = serde_json::from_slice(&fs::read(manifest_path(&output_dir, &input)).unwrap()).unwrap();
+    assert_eq!(saved, manifest);
+    let entries: BTreeSet<String> =
+        fs::read_dir(&output_dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    let mut expected: BTreeSet<String> = keys.iter().map(|k| k.replace(' ', "_")).collect();
+    expected.insert("sales.2024.manifest.json".to_string());
+    assert_eq!(entries, expected);
+}
+
+#[test]
+fn test_split_csv_fails_without_valid_sort_columns() {
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("tx.csv");
+    fs::write(&input, "id,branch\n2,001\n1,002\n").unwrap();
+    let output_dir = dir.path().join("out");
+
+    // Sorting by columns that are not in the header would silently keep the input order
+    let split = SplitOptions { max_rows: Some(1), ..SplitOptions::new("{stem}_{part}.csv") };
+    let err = split_csv(&input, &output_dir, &["missing"], &split, &RunOptions::default()).unwrap_err();
+    assert!(err.to_string().contains("No valid sort columns"), "{}", err);
+    assert!(!output_dir.exists());
+}
+
+#[test]
+fn test_split_csv_by_max_bytes() {
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("notes.csv");
+    let mut content = String::from("id,note\n");
+    for i in (0..300).rev() {
+        // Every fifth note spans two lines inside its quotes
+        match i % 5 {
+            0 => content.push_str(&format!("{},\"line one of {}\nline two of {}\"\n", i, i, i)),
+            _ => content.push_str(&format!("{},note {}\n", i, i)),
+        }
+    }
+    fs::write(&input, &content).unwrap();
+
+    let max_bytes = 200;
+    let split = SplitOptions { max_bytes: Some(max_bytes), ..SplitOptions::new("{stem}_{part:03}.csv") };
+    let manifest = split_csv(&input, &dir.path().join("out"), &["id:num"], &split, &RunOptions::default()).unwrap();
+
+    assert!(manifest.files.len() > 10);
+    assert_eq!(manifest.records(), 300);
+    let mut ids = Vec::new();
+    for file in &manifest.files {
+        let written = fs::read(dir.path().join("out").join(&file.path)).unwrap();
+        assert!(written.len() as u64 <= max_bytes, "{} is over the limit", file.path.display());
+        assert_eq!(written.len() as u64, file.bytes);
+        // Each part is a CSV file of its own: the header, then whole records
+        let mut rdr = csv::Reader::from_reader(written.as_slice());
+        assert_eq!(rdr.headers().unwrap(), vec!["id", "note"]);
+        for record in rdr.records() {
+            let record = record.unwrap();
+            let id: usize = record[0].parse().unwrap();
+            let note = match id % 5 {
+                0 => format!("line one of {}\nline two of {}", id, id),
+                _ => format!("note {}", id),
+            };
+            assert_eq!(&record[1], note);
+            ids.push(id);
+        }
+    }
+    // Sorted before splitting, so the parts follow each other in sorted order
+    assert_eq!(ids, (0..300).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_split_csv_into_parts() {
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("tx.csv");
+    let mut content = String::from("id,customer\n");
+    for i in 0..1_003 {
+        content.push_str(&format!("{},c{}\n", i, i * 7 % 40));
+    }
+    fs::write(&input, content).unwrap();
+    let read_part = |path: std::path::PathBuf| {
+        let mut rdr = csv::Reader::from_path(path).unwrap();
+        assert_eq!(rdr.headers().unwrap(), vec!["id", "customer"]);
+        rdr.records().map(|r| r.unwrap()).collect::<Vec<_>>()
+    };
+
+    // Hashing puts all records of a customer in exactly one part
+    let split = SplitOptions {
+        parts: Some(4),
+        hash_by: vec!["customer".to_string()],
+        ..SplitOptions::new("{stem}.{part}.csv")
+    };
+    let manifest = split_csv(&input, &dir.path().join("hash"), &[], &split, &RunOptions::default()).unwrap();
+    assert_eq!(manifest.files.len(), 4);
+    assert_eq!(manifest.hash_by, ["customer"]);
+    assert_eq!(manifest.records(), 1_003);
+    let mut owner = std::collections::HashMap::new();
+    for file in &manifest.files {
+        for record in read_part(dir.path().join("hash").join(&file.path)) {
+            let part = *owner.entry(record[1].to_string()).or_insert(file.part);
+            assert_eq!(part, file.part, "customer {} is in more than one part", &record[1]);
+        }
+    }
+    assert_eq!(owner.len(), 40);
+
+    // Round-robin deals the records so that part sizes differ by at most one
+    let split = SplitOptions { parts: Some(4), ..SplitOptions::new("{stem}.{part}.csv") };
+    let manifest = split_csv(&input, &dir.path().join("rr"), &[], &split, &RunOptions::default()).unwrap();
+    let sizes: Vec<u64> = manifest.files.iter().map(|f| f.records).collect();
+    assert_eq!(sizes, [251, 251, 251, 250]);
+    let mut ids: Vec<usize> = manifest
+        .files
+        .iter()
+        .flat_map(|f| read_part(dir.path().join("rr").join(&f.path)))
+        .map(|r| r[0].parse().unwrap())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, (0..1_003).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_split_mtlog_by_key_with_few_open_files() {
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("mt_log01");
+    let branches = ["0001", "0002", "0003", "0004", "0005"];
+    let content: String = (0..100).map(|i| mtlog_line(branches[i % 7 % 5], i)).collect();
+    fs::write(&input, content).unwrap();
+    let output_dir = dir.path().join("out");
+
+    // Every key needs its file reopened for appending, and each key is cut into parts of 8 records
+    // SAFETY: other tests in this binary only read the environment while holding `ENV`
+    unsafe { std::env::set_var("SPLIT_MAX_OPEN_FILES", "2") };
+    let split = SplitOptions {
+        partition_by: vec!["mit_isc_tran_branch_code".to_string()],
+        max_rows: Some(8),
+        ..SplitOptions::new("{key}/{stem}_{part:02}.mtlog")
+    };
+    let manifest = split_mtlog(&input, &output_dir, &[], &split, &RunOptions::default()).unwrap();
+    unsafe { std::env::remove_var("SPLIT_MAX_OPEN_FILES") };
+
+    assert_eq!(manifest.records(), 100);
+    assert_eq!(manifest.partition_by, ["mit_isc_tran_branch_code"]);
+    for branch in branches {
+        let files: Vec<_> = manifest.files.iter().filter(|f| f.key.as_deref() == Some(branch)).collect();
+        assert!(files.iter().all(|f| f.records <= 8));
+        // Parts of a key hold its records in input order
+        let mut sernos = Vec::new();
+        for (part, file) in files.iter().enumerate() {
+            assert_eq!(file.path, std::path::Path::new(&format!("{}/mt_log01_{:02}.mtlog", branch, part)));
+            let content = fs::read_to_string(output_dir.join(&file.path)).unwrap();
+            assert_eq!(content.len() as u64, file.bytes);
+            for line in content.lines() {
+                assert_eq!(&line[151..155], branch);
+                sernos.push(line[14..21].parse::<usize>().unwrap());
+            }
+        }
+        let expected: Vec<usize> = (0..100).filter(|i| branches[i % 7 % 5] == branch).collect();
+        assert_eq!(sernos, expected);
+    }
+    let saved: serde_json::Value = serde_json::from_slice(&fs::read(output_dir.join("mt_log01.manifest.json")).unwrap()).unwrap();
+    assert_eq!(saved["files"].as_array().unwrap().len(), manifest.files.len());
+}
+
+#[test]
+fn test_split_mtlog_sorted_by_size() {
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("mt_log02");
+    let content: String = (0..50).map(|i| mtlog_line("0001", (i * 37) % 50)).collect();
+    fs::write(&input, content).unwrap();
+    let output_dir = dir.path().join("out");
+
+    // Room for three records and a bit, but never for four
+    let record_len = TOTAL_LENGTH as u64 + 1;
+    let split = SplitOptions { max_bytes: Some(3 * record_len + 100), ..SplitOptions::new("{stem}.{part:03}") };
+    let sort_columns = [MTLogSortColumn { index: 2, col_type: MTLogSortType::Num }];
+    let manifest = split_mtlog(&input, &output_dir, &sort_columns, &split, &RunOptions::default()).unwrap();
+
+    assert_eq!(manifest.files.len(), 17);
+    let mut sernos = Vec::new();
+    for file in &manifest.files {
+        assert_eq!(file.bytes, file.records * record_len);
+        assert!(file.records <= 3);
+        let content = fs::read_to_string(output_dir.join(&file.path)).unwrap();
+        sernos.extend(content.lines().map(|line| line[14..21].parse::<usize>().unwrap()));
+    }
+    // The parts follow each other in sorted order
+    assert_eq!(sernos, (0..50).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_split_mtlog_into_hashed_parts() {
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("mt_log03");
+    let branches = ["0001", "0002", "0003", "0004", "0005", "0006", "0007"];
+    let content: String = (0..70).map(|i| mtlog_line(branches[i % 7], i)).collect();
+    fs::write(&input, content).unwrap();
+
+    let split = SplitOptions {
+        parts: Some(3),
+        hash_by: vec!["MIT_ISC_TRAN_BRANCH_CODE".to_string()],
+        ..SplitOptions::new("{stem}.{part}")
+    };
+    let manifest = split_mtlog(&input, &dir.path().join("hash"), &[], &split, &RunOptions::default()).unwrap();
+    assert_eq!(manifest.files.len(), 3);
+    assert_eq!(manifest.hash_by, ["mit_isc_tran_branch_code"]);
+    assert_eq!(manifest.records(), 70);
+    // Each branch lands in exactly one part
+    let mut seen = Vec::new();
+    for file in &manifest.files {
+        let content = fs::read_to_string(dir.path().join("hash").join(&file.path)).unwrap();
+        let ours: BTreeSet<&str> = content.lines().map(|line| &line[151..155]).collect();
+        assert!(ours.iter().all(|b| !seen.contains(&b.to_string())));
+        seen.extend(ours.iter().map(|b| b.to_string()));
+    }
+    assert_eq!(seen.len(), branches.len());
+
+    // Round-robin deals the records evenly, and still makes every part
+    let split = SplitOptions { parts: Some(4), ..SplitOptions::new("{stem}.{part}") };
+    let input = dir.path().join("mt_log04");
+    fs::write(&input, (0..2).map(|i| mtlog_line("0001", i)).collect::<String>()).unwrap();
+    let manifest = split_mtlog(&input, &dir.path().join("rr"), &[], &split, &RunOptions::default()).unwrap();
+    let records: Vec<u64> = manifest.files.iter().map(|f| f.records).collect();
+    assert_eq!(records, [1, 1, 0, 0]);
+    assert!(dir.path().join("rr/mt_log04.3").exists());
+}
+
+#[test]
+fn test_sort_mtlog_by_any_field() {
+    use split_merge_hub_demo::mt_log::mt_log_record::FIELDS;
+    use split_merge_hub_demo::parallel_merge::parallel_merge_sort_mtlog_with;
+
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("mt_log07");
+    let content: String = (0..60).map(|i| mtlog_line(&format!("{:04}", (i * 17) % 60), i)).collect();
+    fs::write(&input, content).unwrap();
+    let branch = FIELDS.iter().position(|f| f.name == "mit_isc_tran_branch_code").unwrap();
+    let sort_columns = [MTLogSortColumn { index: branch, col_type: MTLogSortType::Str }];
+
+    let output = dir.path().join("merged.mtlog");
+    parallel_merge_sort_mtlog_with(std::slice::from_ref(&input), &output, &sort_columns, &RunOptions::default())
+        .unwrap();
+    let merged = fs::read_to_string(&output).unwrap();
+    let branches: Vec<usize> = merged.lines().map(|line| line[151..155].parse().unwrap()).collect();
+    assert_eq!(branches, (0..60).collect::<Vec<_>>());
+
+    let split = SplitOptions { max_rows: Some(25), ..SplitOptions::new("{stem}.{part}") };
+    let manifest = split_mtlog(&input, &dir.path().join("parts"), &sort_columns, &split, &RunOptions::default()).unwrap();
+    let mut branches = Vec::new();
+    for file in &manifest.files {
+        let content = fs::read_to_string(dir.path().join("parts").join(&file.path)).unwrap();
+        branches.extend(content.lines().map(|line| line[151..155].parse::<usize>().unwrap()));
+    }
+    assert_eq!(branches, (0..60).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_select_mtlog_fields_as_csv() {
+    use split_merge_hub_demo::parallel_merge::{parallel_merge_sort_mtlog_with, SelectColumn};
+
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("mt_log05");
+    let content: String = (0..20).map(|i| mtlog_line(&format!("{:04}", i % 3), (i * 7) % 20)).collect();
+    fs::write(&input, content).unwrap();
+    let options = RunOptions {
+        select: vec![
+            SelectColumn::parse("mit_isc_tran_branch_code=branch").unwrap(),
+            SelectColumn::parse("MILOG_REC_TASKNO").unwrap(),
+        ],
+        ..RunOptions::default()
+    };
+
+    // Sorted by task number, written as CSV of the selected fields
+    let output = dir.path().join("merged.csv");
+    let sort_columns = [MTLogSortColumn { index: 2, col_type: MTLogSortType::Num }];
+    parallel_merge_sort_mtlog_with(std::slice::from_ref(&input), &output, &sort_columns, &options).unwrap();
+    let merged = fs::read_to_string(&output).unwrap();
+    let mut lines = merged.lines();
+    assert_eq!(lines.next(), Some("branch,milog_rec_taskno"));
+    let expected: Vec<String> = (0..20).map(|serno| format!("{:04},{:07}", (serno * 3) % 20 % 3, serno)).collect();
+    assert_eq!(lines.collect::<Vec<_>>(), expected);
+
+    // Split parts are CSV files of the selected fields too
+    let split = SplitOptions { max_rows: Some(15), ..SplitOptions::new("{stem}_{part}.csv") };
+    let manifest = split_mtlog(&input, &dir.path().join("parts"), &sort_columns, &split, &options).unwrap();
+    assert_eq!(manifest.files.len(), 2);
+    let first = fs::read_to_string(dir.path().join("parts/mt_log05_0.csv")).unwrap();
+    assert_eq!(first.lines().count(), 16);
+    assert_eq!(first.lines().nth(1), Some(expected[0].as_str()));
+}